@@ -28,6 +28,7 @@ in {
         leptosfmt
         libargon2
         nix
+        nix-eval-jobs
         nodejs # npm
         pkg-config
         rust-analyzer
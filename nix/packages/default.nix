@@ -4,5 +4,6 @@
 }: rec {
   default = typhon;
   typhon = import ./typhon.nix {inherit inputs system;};
+  typhon-agent = import ./typhon-agent.nix {inherit inputs system;};
   typhon-doc = import ./doc.nix {inherit inputs system;};
 }
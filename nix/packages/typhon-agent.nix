@@ -0,0 +1,29 @@
+{
+  inputs ? import ../inputs.nix,
+  system ? builtins.currentSystem or "unknown-system",
+  pkgs ? import ../nixpkgs.nix {inherit inputs system;},
+  rust ? import ../rust.nix {inherit inputs system;},
+}: let
+  inherit (rust) craneLib;
+
+  cargoToml = builtins.fromTOML (builtins.readFile ../../Cargo.toml);
+
+  args = {
+    pname = "typhon-agent";
+    inherit (cargoToml.workspace.package) version;
+    src = pkgs.lib.sourceByRegex ../.. [
+      "Cargo.toml"
+      "Cargo.lock"
+      "typhon.*"
+    ];
+    cargoExtraArgs = "-p typhon-agent";
+    nativeBuildInputs = [pkgs.sqlite.dev];
+    TYPHON_FLAKE = ../../typhon-flake;
+  };
+
+  cargoArtifacts = craneLib.buildDepsOnly args;
+in
+  craneLib.buildPackage (args
+    // {
+      inherit cargoArtifacts;
+    })
@@ -0,0 +1,272 @@
+use typhon_core::nix;
+use typhon_types::handles;
+use typhon_types::requests::{Agent, AgentBuildResult, Request};
+use typhon_types::responses::{AgentBuild, BuildProgress, LogRecord, Response, ResponseError};
+
+use clap::Parser;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Minimum delay between two progress reports of a build
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Typhon build agent: runs the builds of a Typhon server
+#[derive(Parser)]
+#[command(name = "Typhon agent")]
+pub struct Args {
+    /// URL of the Typhon server
+    #[arg(long, env = "TYPHON_URL")]
+    pub url: String,
+
+    /// API token of an administrator, possibly restricted to the
+    /// `agent_poll`, `agent_log`, `agent_progress` and `agent_finish`
+    /// request kinds
+    #[arg(long, env = "TYPHON_TOKEN")]
+    pub token: String,
+
+    /// Name of the agent, unique among the agents of the server
+    #[arg(long, env = "TYPHON_AGENT_NAME")]
+    pub name: String,
+
+    /// Systems the agent builds for, the current system by default
+    #[arg(long, env = "TYPHON_AGENT_SYSTEMS", value_delimiter = ',')]
+    pub systems: Vec<String>,
+
+    /// System features supported by the agent, e.g. kvm or big-parallel
+    #[arg(long, env = "TYPHON_AGENT_FEATURES", value_delimiter = ',')]
+    pub features: Vec<String>,
+
+    /// Maximum number of builds running at the same time
+    #[arg(long, env = "TYPHON_AGENT_MAX_BUILDS", default_value_t = 1)]
+    pub max_builds: u32,
+
+    /// Store of the server, e.g. ssh-ng://typhon@example.com. Derivations
+    /// and their inputs are copied from it, and outputs are copied to it.
+    /// Leave it unset when the agent shares the store of the server.
+    #[arg(long, env = "TYPHON_AGENT_STORE")]
+    pub store: Option<String>,
+
+    /// Delay in seconds between two polls of the server
+    #[arg(long, env = "TYPHON_AGENT_POLL_INTERVAL", default_value_t = 5)]
+    pub poll_interval: u64,
+}
+
+struct Client {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+    name: String,
+}
+
+impl Client {
+    async fn request(&self, req: Agent) -> Result<Response, String> {
+        let body = serde_json::to_string(&Request::Agent {
+            name: self.name.clone(),
+            req,
+        })
+        .unwrap();
+        let text = self
+            .http
+            .post(format!("{}/api", self.url.trim_end_matches('/')))
+            .bearer_auth(&self.token)
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .error_for_status()
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        serde_json::from_str::<Result<Response, ResponseError>>(&text)
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Copies a derivation and the outputs of its inputs from the store of the
+/// server
+async fn copy_inputs(store: &str, drv: &nix::DrvPath) -> Result<(), nix::Error> {
+    nix::copy(&[drv.to_string()], nix::Copy::From(store), true).await?;
+    let mut paths = Vec::new();
+    for (input, outputs) in &nix::drv::read(drv)?.input_drvs {
+        let input = nix::drv::read(&nix::DrvPath::new(input))?;
+        paths.extend(
+            outputs
+                .iter()
+                .filter_map(|output| input.outputs.get(output))
+                .map(|output| output.path.clone())
+                .filter(|path| !path.is_empty()),
+        );
+    }
+    nix::copy(&paths, nix::Copy::From(store), false).await
+}
+
+async fn build(
+    store: Option<&str>,
+    build: &AgentBuild,
+    log: mpsc::UnboundedSender<LogRecord>,
+    progress: mpsc::UnboundedSender<BuildProgress>,
+) -> AgentBuildResult {
+    let drv = nix::DrvPath::new(&build.drv);
+    if let Some(store) = store {
+        if let Err(e) = copy_inputs(store, &drv).await {
+            let _ = log.send(LogRecord::typhon(format!(
+                "failed to copy the inputs of the build: {}",
+                e
+            )));
+            return AgentBuildResult::Failure { transient: true };
+        }
+    }
+    let limits = nix::BuildLimits {
+        timeout: build.timeout.map(Duration::from_secs),
+        max_silent: build.max_silent.map(Duration::from_secs),
+    };
+    match nix::build(&drv, log.clone(), Some(progress), None, &limits).await {
+        Ok(outputs) => {
+            if let Some(store) = store {
+                let outputs: Vec<String> = outputs.into_values().collect();
+                if let Err(e) = nix::copy(&outputs, nix::Copy::To(store), false).await {
+                    let _ = log.send(LogRecord::typhon(format!(
+                        "failed to copy the outputs of the build: {}",
+                        e
+                    )));
+                    return AgentBuildResult::Failure { transient: true };
+                }
+            }
+            AgentBuildResult::Success
+        }
+        Err(nix::Error::TimedOut) => AgentBuildResult::TimedOut,
+        Err(nix::Error::BuildFailed { transient }) => AgentBuildResult::Failure { transient },
+        Err(e) => {
+            let _ = log.send(LogRecord::typhon(e.to_string()));
+            AgentBuildResult::Failure { transient: false }
+        }
+    }
+}
+
+/// Sends the log of a build to the server, in batches
+async fn forward_log(
+    client: Arc<Client>,
+    build: handles::Build,
+    mut receiver: mpsc::UnboundedReceiver<LogRecord>,
+) {
+    while let Some(record) = receiver.recv().await {
+        let mut records = vec![record];
+        while let Ok(record) = receiver.try_recv() {
+            records.push(record);
+        }
+        let req = Agent::Log {
+            build: build.clone(),
+            records,
+        };
+        if let Err(e) = client.request(req).await {
+            tracing::warn!("failed to send the log of build {}: {}", build, e);
+        }
+    }
+}
+
+/// Sends the progress of a build to the server, skipping the intermediate
+/// states reported while the previous report is sent
+async fn forward_progress(
+    client: Arc<Client>,
+    build: handles::Build,
+    mut receiver: mpsc::UnboundedReceiver<BuildProgress>,
+) {
+    while let Some(mut progress) = receiver.recv().await {
+        while let Ok(newer) = receiver.try_recv() {
+            progress = newer;
+        }
+        let req = Agent::Progress {
+            build: build.clone(),
+            progress,
+        };
+        if let Err(e) = client.request(req).await {
+            tracing::warn!("failed to send the progress of build {}: {}", build, e);
+        }
+        tokio::time::sleep(PROGRESS_INTERVAL).await;
+    }
+}
+
+async fn run(client: Arc<Client>, store: Option<String>, agent_build: AgentBuild) {
+    tracing::info!("building {} ({})", agent_build.drv, agent_build.build);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let forward = tokio::spawn(forward_log(
+        client.clone(),
+        agent_build.build.clone(),
+        receiver,
+    ));
+    let (sender_progress, receiver_progress) = mpsc::unbounded_channel();
+    let forward_progress = tokio::spawn(forward_progress(
+        client.clone(),
+        agent_build.build.clone(),
+        receiver_progress,
+    ));
+    let result = build(store.as_deref(), &agent_build, sender, sender_progress).await;
+    // the log is complete once the build has dropped its sender
+    let _ = forward.await;
+    let _ = forward_progress.await;
+    tracing::info!("build {} finished: {:?}", agent_build.build, result);
+    let req = Agent::Finish {
+        build: agent_build.build.clone(),
+        result,
+    };
+    if let Err(e) = client.request(req).await {
+        tracing::error!(
+            "failed to send the result of build {}: {}",
+            agent_build.build,
+            e
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    tracing::subscriber::set_global_default(tracing_subscriber::FmtSubscriber::new()).unwrap();
+    let args = Args::parse();
+    let systems = if args.systems.is_empty() {
+        vec![nix::current_system()]
+    } else {
+        args.systems
+    };
+    let client = Arc::new(Client {
+        http: reqwest::Client::new(),
+        url: args.url,
+        token: args.token,
+        name: args.name,
+    });
+    let mut running: HashMap<handles::Build, JoinHandle<()>> = HashMap::new();
+    loop {
+        running.retain(|_, task| !task.is_finished());
+        let req = Agent::Poll {
+            systems: systems.clone(),
+            features: args.features.clone(),
+            capacity: args.max_builds.saturating_sub(running.len() as u32),
+            running: running.keys().cloned().collect(),
+        };
+        match client.request(req).await {
+            Ok(Response::AgentPoll(poll)) => {
+                for build in poll.cancel {
+                    if let Some(task) = running.remove(&build) {
+                        tracing::info!("build {} was canceled", build);
+                        // dropping the build kills Nix
+                        task.abort();
+                    }
+                }
+                for build in poll.builds {
+                    let handle = build.build.clone();
+                    let task = tokio::spawn(run(client.clone(), args.store.clone(), build));
+                    running.insert(handle, task);
+                }
+            }
+            Ok(rsp) => tracing::error!("unexpected response to poll: {:?}", rsp),
+            Err(e) => tracing::error!("failed to poll the server: {}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(args.poll_interval)).await;
+    }
+}
@@ -0,0 +1,12 @@
+CREATE TABLE tokens (
+    hash TEXT NOT NULL,
+    id INTEGER NOT NULL PRIMARY KEY,
+    kinds TEXT,
+    name TEXT NOT NULL,
+    project_id INTEGER REFERENCES projects (id),
+    time_created BIGINT NOT NULL,
+    time_expires BIGINT,
+    time_last_used BIGINT,
+    user_id INTEGER REFERENCES users (id),
+    UNIQUE (hash)
+);
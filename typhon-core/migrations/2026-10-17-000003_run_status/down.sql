@@ -0,0 +1,3 @@
+ALTER TABLE runs DROP COLUMN status;
+ALTER TABLE runs DROP COLUMN time_finished;
+ALTER TABLE runs DROP COLUMN time_started;
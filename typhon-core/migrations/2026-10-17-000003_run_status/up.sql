@@ -0,0 +1,23 @@
+ALTER TABLE runs ADD COLUMN status INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE runs ADD COLUMN time_finished BIGINT;
+ALTER TABLE runs ADD COLUMN time_started BIGINT;
+
+-- runs of a previous version are finished once their `end` action is, and
+-- their outcome is the one of their build; the other ones cannot be running
+-- anymore and are considered canceled
+UPDATE runs SET
+    time_started = time_created,
+    time_finished = (
+        SELECT tasks.time_finished FROM actions
+        INNER JOIN tasks ON actions.task_id = tasks.id
+        WHERE actions.id = runs.end_id
+    );
+UPDATE runs SET status = CASE
+    WHEN time_finished IS NULL THEN 3
+    ELSE COALESCE((
+        SELECT tasks.status FROM builds
+        INNER JOIN tasks ON builds.task_id = tasks.id
+        WHERE builds.id = runs.build_id AND tasks.status IN (1, 2)
+    ), 3)
+END;
+UPDATE runs SET time_started = NULL WHERE time_finished IS NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE jobsets DROP COLUMN schedule;
+ALTER TABLE jobsets DROP COLUMN time_next_evaluation;
@@ -0,0 +1,2 @@
+ALTER TABLE jobsets ADD COLUMN schedule TEXT;
+ALTER TABLE jobsets ADD COLUMN time_next_evaluation BIGINT;
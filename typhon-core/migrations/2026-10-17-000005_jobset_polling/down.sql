@@ -0,0 +1,2 @@
+ALTER TABLE jobsets DROP COLUMN poll;
+ALTER TABLE jobsets DROP COLUMN time_next_poll;
@@ -0,0 +1,2 @@
+ALTER TABLE jobsets ADD COLUMN poll INTEGER;
+ALTER TABLE jobsets ADD COLUMN time_next_poll BIGINT;
@@ -0,0 +1 @@
+ALTER TABLE runs ADD COLUMN previous_status INTEGER;
@@ -0,0 +1,21 @@
+CREATE TABLE subscriptions (
+    id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    project_id INTEGER REFERENCES projects (id),
+    secret TEXT NOT NULL,
+    time_created BIGINT NOT NULL,
+    url TEXT NOT NULL,
+    UNIQUE (name)
+);
+
+CREATE TABLE deliveries (
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    event TEXT NOT NULL,
+    id INTEGER NOT NULL PRIMARY KEY,
+    response_code INTEGER,
+    status INTEGER NOT NULL DEFAULT 0,
+    subscription_id INTEGER NOT NULL REFERENCES subscriptions (id),
+    time_created BIGINT NOT NULL,
+    time_finished BIGINT
+);
@@ -0,0 +1,2 @@
+ALTER TABLE projects DROP COLUMN notify_emails;
+ALTER TABLE projects DROP COLUMN notify_maintainers;
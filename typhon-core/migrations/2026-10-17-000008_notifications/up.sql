@@ -0,0 +1,2 @@
+ALTER TABLE projects ADD COLUMN notify_emails TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE projects ADD COLUMN notify_maintainers BOOL NOT NULL DEFAULT FALSE;
@@ -0,0 +1 @@
+ALTER TABLE jobs ADD COLUMN eval_error TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE jobs ADD COLUMN outputs TEXT NOT NULL DEFAULT '{}';
+UPDATE jobs SET outputs = json_object('out', out) WHERE eval_error IS NULL;
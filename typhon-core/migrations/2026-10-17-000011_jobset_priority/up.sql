@@ -0,0 +1 @@
+ALTER TABLE jobsets ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+ALTER TABLE jobs DROP COLUMN timeout;
+ALTER TABLE jobs DROP COLUMN max_silent;
+ALTER TABLE projects DROP COLUMN build_timeout;
+ALTER TABLE projects DROP COLUMN build_max_silent;
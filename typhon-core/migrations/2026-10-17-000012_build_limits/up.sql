@@ -0,0 +1,4 @@
+ALTER TABLE projects ADD COLUMN build_max_silent BIGINT;
+ALTER TABLE projects ADD COLUMN build_timeout BIGINT;
+ALTER TABLE jobs ADD COLUMN max_silent BIGINT;
+ALTER TABLE jobs ADD COLUMN timeout BIGINT;
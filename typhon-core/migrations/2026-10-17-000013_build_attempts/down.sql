@@ -0,0 +1,2 @@
+ALTER TABLE builds DROP COLUMN previous_id;
+ALTER TABLE builds DROP COLUMN attempt;
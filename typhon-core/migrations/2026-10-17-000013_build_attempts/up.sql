@@ -0,0 +1,2 @@
+ALTER TABLE builds ADD COLUMN attempt INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE builds ADD COLUMN previous_id INTEGER REFERENCES builds(id);
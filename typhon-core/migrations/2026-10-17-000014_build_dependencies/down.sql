@@ -0,0 +1,2 @@
+ALTER TABLE builds DROP COLUMN failed_dependency_id;
+DROP TABLE build_dependencies;
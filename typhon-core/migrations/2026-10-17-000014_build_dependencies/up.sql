@@ -0,0 +1,8 @@
+CREATE TABLE build_dependencies (
+    build_id INTEGER NOT NULL REFERENCES builds (id),
+    dependency_id INTEGER NOT NULL REFERENCES builds (id),
+    id INTEGER NOT NULL PRIMARY KEY,
+    UNIQUE (build_id, dependency_id)
+);
+
+ALTER TABLE builds ADD COLUMN failed_dependency_id INTEGER REFERENCES builds(id);
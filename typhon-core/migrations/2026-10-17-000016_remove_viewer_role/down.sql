@@ -0,0 +1,2 @@
+-- the viewer roles that were removed cannot be restored
+SELECT 1;
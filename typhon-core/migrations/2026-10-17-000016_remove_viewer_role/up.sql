@@ -0,0 +1,2 @@
+-- the viewer role granted nothing, reads are public
+DELETE FROM roles WHERE role = 0;
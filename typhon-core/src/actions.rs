@@ -6,6 +6,7 @@ use crate::tasks;
 use crate::Conn;
 
 use typhon_types::data::TaskStatusKind;
+use typhon_types::responses::LogRecord;
 use typhon_types::*;
 
 use diesel::prelude::*;
@@ -65,7 +66,7 @@ async fn action(
     path: &String,
     name: &String,
     input: &Value,
-    sender: mpsc::UnboundedSender<String>,
+    sender: mpsc::UnboundedSender<LogRecord>,
 ) -> Result<String, Error> {
     use tokio::io::AsyncBufReadExt;
     use tokio::io::AsyncReadExt;
@@ -121,7 +122,7 @@ async fn action(
     let buffer = BufReader::new(stderr);
     let mut lines = buffer.lines();
     while let Some(line) = lines.next_line().await.unwrap() {
-        let _ = sender.send(line);
+        let _ = sender.send(LogRecord::output(line));
     }
 
     let mut res = String::new();
@@ -0,0 +1,375 @@
+use crate::build_manager::{Failure, Priority};
+use crate::error::Error;
+use crate::nix;
+
+use typhon_types::requests::AgentBuildResult;
+use typhon_types::responses::LogRecord;
+use typhon_types::{handles, responses};
+
+use once_cell::sync::Lazy;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// An agent that did not poll for this long is considered gone: it is not
+/// given new builds, and the builds it was running are failed
+const AGENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A build agent, as advertised by its last poll
+struct Agent {
+    systems: Vec<String>,
+    features: Vec<String>,
+    last_seen: Instant,
+}
+
+impl Agent {
+    fn alive(&self) -> bool {
+        self.last_seen.elapsed() < AGENT_TIMEOUT
+    }
+
+    fn supports(&self, system: &str, features: &[String]) -> bool {
+        self.systems.iter().any(|s| s == system)
+            && features
+                .iter()
+                .all(|feature| self.features.contains(feature))
+    }
+}
+
+/// A build waiting for an agent, or running on one
+struct RemoteBuild {
+    drv: nix::DrvPath,
+    system: String,
+    features: Vec<String>,
+    priority: Priority,
+    seq: u64,
+    limits: nix::BuildLimits,
+    /// The agent running the build, `None` while it waits for one
+    agent: Option<String>,
+    log: mpsc::UnboundedSender<LogRecord>,
+    progress: mpsc::UnboundedSender<responses::BuildProgress>,
+    result: Option<oneshot::Sender<Result<(), Failure>>>,
+}
+
+#[derive(Default)]
+struct State {
+    agents: HashMap<String, Agent>,
+    builds: HashMap<Uuid, RemoteBuild>,
+    seq: u64,
+}
+
+static STATE: Lazy<Mutex<State>> = Lazy::new(|| Mutex::new(State::default()));
+
+/// Removes a remote build when its build task finishes or is canceled, so
+/// that the agent running it is told to stop
+struct Entry {
+    uuid: Uuid,
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        STATE.lock().unwrap().builds.remove(&self.uuid);
+    }
+}
+
+/// Tells whether a live agent can build for a system with the given
+/// features
+pub fn available(system: &str, features: &[String]) -> bool {
+    STATE
+        .lock()
+        .unwrap()
+        .agents
+        .values()
+        .any(|agent| agent.alive() && agent.supports(system, features))
+}
+
+/// A build to hand to the agents
+pub struct BuildRequest {
+    pub handle: handles::Build,
+    pub drv: nix::DrvPath,
+    pub system: String,
+    /// The `requiredSystemFeatures` of the derivation
+    pub features: Vec<String>,
+    pub priority: Priority,
+    pub limits: nix::BuildLimits,
+}
+
+/// Queues a build for the agents and waits for its result. The build is
+/// failed with a transient error if its agent stops polling, or polls without
+/// it, or if it still waits for an agent when none can build it anymore, so
+/// that it can be retried locally.
+pub async fn build(
+    request: BuildRequest,
+    log: mpsc::UnboundedSender<LogRecord>,
+    progress: mpsc::UnboundedSender<responses::BuildProgress>,
+) -> Result<(), Failure> {
+    let BuildRequest {
+        handle,
+        drv,
+        system,
+        features,
+        priority,
+        limits,
+    } = request;
+    let (sender, mut receiver) = oneshot::channel();
+    let _entry = {
+        let mut state = STATE.lock().unwrap();
+        state.seq += 1;
+        let seq = state.seq;
+        state.builds.insert(
+            handle.uuid,
+            RemoteBuild {
+                drv,
+                system,
+                features,
+                priority,
+                seq,
+                limits,
+                agent: None,
+                log: log.clone(),
+                progress,
+                result: Some(sender),
+            },
+        );
+        Entry { uuid: handle.uuid }
+    };
+    let mut interval = tokio::time::interval(AGENT_TIMEOUT / 4);
+    loop {
+        tokio::select! {
+            res = &mut receiver => return res.unwrap_or(Err(Failure::Transient)),
+            _ = interval.tick() => {
+                let state = STATE.lock().unwrap();
+                let remote = &state.builds[&handle.uuid];
+                match &remote.agent {
+                    Some(name) if !state.agents.get(name).is_some_and(Agent::alive) => {
+                        let _ = log.send(LogRecord::typhon(format!("agent {} stopped responding", name)));
+                        return Err(Failure::Transient);
+                    }
+                    None if !state.agents.values().any(|agent| {
+                        agent.alive() && agent.supports(&remote.system, &remote.features)
+                    }) => {
+                        let _ = log.send(LogRecord::typhon("no agent can run the build anymore"));
+                        return Err(Failure::Transient);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// Records the poll of an agent, and hands it the waiting builds it supports,
+/// by order of priority
+pub fn poll(
+    name: &str,
+    systems: &[String],
+    features: &[String],
+    capacity: u32,
+    running: &[handles::Build],
+) -> responses::AgentPoll {
+    let mut state = STATE.lock().unwrap();
+    state.agents.insert(
+        name.to_string(),
+        Agent {
+            systems: systems.to_vec(),
+            features: features.to_vec(),
+            last_seen: Instant::now(),
+        },
+    );
+    // the builds handed out by an earlier poll that the agent no longer
+    // runs were lost, because it restarted or could not send their result
+    for (uuid, remote) in state.builds.iter_mut() {
+        if remote.agent.as_deref() == Some(name) && !running.iter().any(|build| build.uuid == *uuid)
+        {
+            if let Some(sender) = remote.result.take() {
+                let _ = remote
+                    .log
+                    .send(LogRecord::typhon(format!("agent {} lost the build", name)));
+                let _ = sender.send(Err(Failure::Transient));
+            }
+        }
+    }
+    let agent = &state.agents[name];
+    let cancel = running
+        .iter()
+        .filter(|build| {
+            state
+                .builds
+                .get(&build.uuid)
+                .and_then(|remote| remote.agent.as_deref())
+                != Some(name)
+        })
+        .cloned()
+        .collect();
+    let mut waiting: Vec<(&Uuid, &RemoteBuild)> = state
+        .builds
+        .iter()
+        .filter(|(_, remote)| {
+            remote.agent.is_none() && agent.supports(&remote.system, &remote.features)
+        })
+        .collect();
+    waiting.sort_by_key(|(_, remote)| (Reverse(remote.priority), remote.seq));
+    let assigned: Vec<Uuid> = waiting
+        .into_iter()
+        .take(capacity as usize)
+        .map(|(uuid, _)| *uuid)
+        .collect();
+    let builds = assigned
+        .into_iter()
+        .map(|uuid| {
+            let remote = state.builds.get_mut(&uuid).unwrap();
+            remote.agent = Some(name.to_string());
+            let _ = remote
+                .log
+                .send(LogRecord::typhon(format!("building on agent {}", name)));
+            responses::AgentBuild {
+                build: handles::build(uuid),
+                drv: remote.drv.to_string(),
+                timeout: remote.limits.timeout.map(|t| t.as_secs()),
+                max_silent: remote.limits.max_silent.map(|t| t.as_secs()),
+            }
+        })
+        .collect();
+    responses::AgentPoll { builds, cancel }
+}
+
+/// Returns the build `handle` if it runs on agent `name`
+fn assigned<'a>(
+    state: &'a mut State,
+    name: &str,
+    handle: &handles::Build,
+) -> Result<&'a mut RemoteBuild, Error> {
+    if let Some(agent) = state.agents.get_mut(name) {
+        agent.last_seen = Instant::now();
+    }
+    state
+        .builds
+        .get_mut(&handle.uuid)
+        .filter(|remote| remote.agent.as_deref() == Some(name))
+        .ok_or(Error::BuildNotFound(handle.clone()))
+}
+
+/// Appends records sent by an agent to the log of a build
+pub fn log(name: &str, handle: &handles::Build, records: &[LogRecord]) -> Result<(), Error> {
+    let mut state = STATE.lock().unwrap();
+    let remote = assigned(&mut state, name, handle)?;
+    for record in records {
+        let _ = remote.log.send(record.clone());
+    }
+    Ok(())
+}
+
+/// Records the progress of a build sent by an agent
+pub fn progress(
+    name: &str,
+    handle: &handles::Build,
+    progress: &responses::BuildProgress,
+) -> Result<(), Error> {
+    let mut state = STATE.lock().unwrap();
+    let remote = assigned(&mut state, name, handle)?;
+    let _ = remote.progress.send(progress.clone());
+    Ok(())
+}
+
+/// Records the result of a build sent by an agent
+pub fn finish(name: &str, handle: &handles::Build, result: AgentBuildResult) -> Result<(), Error> {
+    let mut state = STATE.lock().unwrap();
+    let remote = assigned(&mut state, name, handle)?;
+    let result = match result {
+        AgentBuildResult::Success => Ok(()),
+        AgentBuildResult::Failure { transient: false } => Err(Failure::Failed),
+        AgentBuildResult::Failure { transient: true } => Err(Failure::Transient),
+        AgentBuildResult::TimedOut => Err(Failure::TimedOut),
+    };
+    if let Some(sender) = remote.result.take() {
+        let _ = sender.send(result);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Queues a build for `system` and returns its handle and its result.
+    /// Each test uses its own system, so that the agents of the other tests
+    /// do not take its builds.
+    async fn queue(system: &str) -> (handles::Build, tokio::task::JoinHandle<Result<(), Failure>>) {
+        let handle = handles::build(Uuid::now_v7());
+        let request = BuildRequest {
+            handle: handle.clone(),
+            drv: nix::DrvPath::new("/nix/store/00000000000000000000000000000000-test.drv"),
+            system: system.to_string(),
+            features: Vec::new(),
+            priority: Priority::default(),
+            limits: nix::BuildLimits::default(),
+        };
+        let (log, _) = mpsc::unbounded_channel();
+        let (progress, _) = mpsc::unbounded_channel();
+        let result = tokio::spawn(build(request, log, progress));
+        tokio::task::yield_now().await;
+        (handle, result)
+    }
+
+    fn poll_running(
+        name: &str,
+        systems: &[String],
+        handle: &handles::Build,
+    ) -> responses::AgentPoll {
+        poll(name, systems, &[], 0, std::slice::from_ref(handle))
+    }
+
+    #[tokio::test]
+    async fn finished_build() {
+        let systems = ["test-finished".to_string()];
+        let (handle, result) = queue(&systems[0]).await;
+        let poll = poll("finished", &systems, &[], 1, &[]);
+        assert_eq!(poll.builds.len(), 1);
+        assert_eq!(poll.builds[0].build, handle);
+        let poll = poll_running("finished", &systems, &handle);
+        assert!(poll.builds.is_empty() && poll.cancel.is_empty());
+        finish("finished", &handle, AgentBuildResult::Success).unwrap();
+        assert_eq!(result.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn lost_build() {
+        let systems = ["test-lost".to_string()];
+        let (handle, result) = queue(&systems[0]).await;
+        let assigned = poll("lost", &systems, &[], 1, &[]);
+        assert_eq!(assigned.builds[0].build, handle);
+        // the agent restarted, or failed to send the result
+        poll("lost", &systems, &[], 1, &[]);
+        assert_eq!(result.await.unwrap(), Err(Failure::Transient));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waiting_build_without_agent() {
+        let systems = ["test-gone".to_string()];
+        // the agent has no room for the build, then never polls again
+        poll("gone", &systems, &[], 0, &[]);
+        let (_, result) = queue(&systems[0]).await;
+        STATE
+            .lock()
+            .unwrap()
+            .agents
+            .get_mut("gone")
+            .unwrap()
+            .last_seen = Instant::now().checked_sub(AGENT_TIMEOUT).unwrap();
+        assert_eq!(result.await.unwrap(), Err(Failure::Transient));
+    }
+
+    #[tokio::test]
+    async fn canceled_build() {
+        let systems = ["test-canceled".to_string()];
+        let (handle, result) = queue(&systems[0]).await;
+        poll("canceled", &systems, &[], 1, &[]);
+        result.abort();
+        let _ = result.await;
+        let poll = poll_running("canceled", &systems, &handle);
+        assert_eq!(poll.cancel, vec![handle]);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::agents;
 use crate::builds;
 use crate::error::Error;
 use crate::log_event;
@@ -7,21 +8,278 @@ use crate::nix::DrvPath;
 use crate::schema;
 use crate::tasks;
 use crate::Conn;
+use crate::LOGS;
 use crate::POOL;
 use crate::RUNTIME;
 
+use typhon_types::responses::LogRecord;
 use typhon_types::{data::TaskStatusKind, *};
 
 use diesel::prelude::*;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
 use time::OffsetDateTime;
 use tokio::{
     sync::{mpsc, oneshot, watch},
     task::JoinSet,
 };
 
-type Output = Option<Option<()>>;
+/// Why a build failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    Failed,
+    /// The build failed because of the machine or the network, and may
+    /// succeed if retried
+    Transient,
+    TimedOut,
+    /// The build did not run because one of its dependencies failed. The
+    /// payload is the id of the build that failed in the first place.
+    DependencyFailed(i32),
+}
+
+impl Failure {
+    /// Returns the build at the origin of the failure of build `id`
+    fn root(self, id: i32) -> i32 {
+        match self {
+            Failure::DependencyFailed(root) => root,
+            _ => id,
+        }
+    }
+}
+
+/// The outcome of a build, `None` if it was canceled
+pub type Output = Option<Result<(), Failure>>;
+
+/// Settings of the build manager
+#[derive(Clone, Debug)]
+pub struct BuildSettings {
+    /// Maximum number of builds running at the same time
+    pub max_builds: usize,
+    /// Number of times a build that failed because of a transient error is
+    /// retried
+    pub max_retries: u32,
+    /// Delay before the first retry of a build, doubled after each attempt
+    pub retry_delay: Duration,
+}
+
+/// Maximum delay before retrying a build
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Returns the delay before the retry of a build after its `attempt`-th
+/// attempt: `first`, doubled after each attempt, up to [`MAX_RETRY_DELAY`]
+fn retry_delay(first: Duration, attempt: u32) -> Duration {
+    2u32.checked_pow(attempt.saturating_sub(1))
+        .and_then(|factor| first.checked_mul(factor))
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+/// The priority of a build: builds of manual reruns come first, then builds
+/// of jobsets with a higher priority
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority {
+    pub rerun: bool,
+    pub jobset: i32,
+}
+
+/// Who asked for a build, to order the build queue, and the limits the build
+/// is subject to
+#[derive(Clone, Debug)]
+pub struct Requester {
+    pub project: String,
+    pub priority: Priority,
+    pub limits: nix::BuildLimits,
+}
+
+struct Waiting {
+    drv: DrvPath,
+    requester: Requester,
+    seq: u64,
+    start: oneshot::Sender<Slot>,
+}
+
+/// The builds waiting for a slot, and the number of running builds of each
+/// project
+#[derive(Default)]
+struct Queue {
+    waiting: Vec<Waiting>,
+    running: HashMap<String, usize>,
+    seq: u64,
+}
+
+impl Queue {
+    /// Orders waiting builds by priority, then by number of running builds of
+    /// their project so that projects share the slots fairly, then by arrival
+    fn key(&self, waiting: &Waiting) -> (std::cmp::Reverse<Priority>, usize, u64) {
+        (
+            std::cmp::Reverse(waiting.requester.priority),
+            self.running
+                .get(&waiting.requester.project)
+                .copied()
+                .unwrap_or(0),
+            waiting.seq,
+        )
+    }
+
+    fn ordered(&self) -> Vec<&Waiting> {
+        let mut waiting: Vec<&Waiting> = self.waiting.iter().collect();
+        waiting.sort_by_key(|waiting| self.key(waiting));
+        waiting
+    }
+
+    /// Starts the next waiting builds as long as fewer than `max_builds` builds
+    /// are running
+    fn start_next(&mut self, max_builds: usize) {
+        while self.running.values().sum::<usize>() < max_builds && !self.waiting.is_empty() {
+            let next = (0..self.waiting.len())
+                .min_by_key(|i| self.key(&self.waiting[*i]))
+                .unwrap();
+            let waiting = self.waiting.swap_remove(next);
+            let project = waiting.requester.project;
+            *self.running.entry(project.clone()).or_insert(0) += 1;
+            if let Err(mut slot) = waiting.start.send(Slot {
+                project: Some(project),
+            }) {
+                // the build was canceled in the meantime
+                self.release(slot.project.take().unwrap());
+            }
+        }
+    }
+
+    fn release(&mut self, project: String) {
+        if let Some(running) = self.running.get_mut(&project) {
+            *running -= 1;
+            if *running == 0 {
+                self.running.remove(&project);
+            }
+        }
+    }
+}
+
+static QUEUE: Lazy<Mutex<Queue>> = Lazy::new(|| Mutex::new(Queue::default()));
+
+/// A build slot, released when dropped
+struct Slot {
+    project: Option<String>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(project) = self.project.take() {
+            let mut queue = QUEUE.lock().unwrap();
+            queue.release(project);
+            queue.start_next(crate::Settings::get().builds.max_builds);
+        }
+    }
+}
+
+/// Removes a build from the queue if it stops waiting before getting a slot
+struct Ticket {
+    seq: u64,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        QUEUE
+            .lock()
+            .unwrap()
+            .waiting
+            .retain(|waiting| waiting.seq != self.seq);
+    }
+}
+
+/// Waits for a build slot
+async fn acquire(drv: DrvPath, requester: Requester) -> Option<Slot> {
+    let (start, receiver) = oneshot::channel();
+    let _ticket = {
+        let mut queue = QUEUE.lock().unwrap();
+        queue.seq += 1;
+        let seq = queue.seq;
+        queue.waiting.push(Waiting {
+            drv,
+            requester,
+            seq,
+            start,
+        });
+        queue.start_next(crate::Settings::get().builds.max_builds);
+        Ticket { seq }
+    };
+    receiver.await.ok()
+}
+
+/// Raises the priority of a build waiting in the queue, when another build
+/// with a higher priority depends on it
+fn raise_priority(drv: &DrvPath, requester: &Requester) {
+    let mut queue = QUEUE.lock().unwrap();
+    for waiting in queue.waiting.iter_mut() {
+        if waiting.drv == *drv && waiting.requester.priority < requester.priority {
+            waiting.requester = requester.clone();
+        }
+    }
+}
+
+/// Returns the number of builds that will start before a waiting build
+pub fn queue_position(drv: &DrvPath) -> Option<u32> {
+    QUEUE
+        .lock()
+        .unwrap()
+        .ordered()
+        .iter()
+        .position(|waiting| waiting.drv == *drv)
+        .map(|position| position as u32)
+}
+
+/// Minimum delay between two [`Event::BuildProgress`] of a build
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The progress of the running builds
+static PROGRESS: Lazy<Mutex<HashMap<handles::Build, responses::BuildProgress>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the progress of a running build
+pub fn progress(build: &handles::Build) -> Option<responses::BuildProgress> {
+    PROGRESS.lock().unwrap().get(build).cloned()
+}
+
+/// Forgets the progress of a build when it stops
+struct ProgressEntry {
+    handle: handles::Build,
+}
+
+impl Drop for ProgressEntry {
+    fn drop(&mut self) {
+        PROGRESS.lock().unwrap().remove(&self.handle);
+    }
+}
+
+/// Records the progress of a build until it stops, and announces its changes
+async fn track_progress(
+    handle: handles::Build,
+    mut receiver: mpsc::UnboundedReceiver<responses::BuildProgress>,
+) {
+    let _entry = ProgressEntry {
+        handle: handle.clone(),
+    };
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    let mut changed = false;
+    loop {
+        tokio::select! {
+            progress = receiver.recv() => match progress {
+                Some(progress) => {
+                    PROGRESS.lock().unwrap().insert(handle.clone(), progress);
+                    changed = true;
+                }
+                None => break,
+            },
+            _ = interval.tick(), if changed => {
+                log_event(Event::BuildProgress(handle.clone()));
+                changed = false;
+            }
+        }
+    }
+}
 
 pub struct BuildHandle {
     pub abort: oneshot::Sender<()>,
@@ -38,13 +296,17 @@ impl BuildHandle {
 
 enum Msg {
     Abort(DrvPath),
-    Build(DrvPath, oneshot::Sender<BuildHandle>),
+    Build(DrvPath, Requester, oneshot::Sender<BuildHandle>),
+    /// The log of a derivation that Nix built on its own for a build
+    DependencyLog(i32, nix::DependencyLog),
     Finished(DrvPath, Output),
+    Retry(DrvPath),
     Shutdown,
 }
 
 struct Build {
     build: builds::Build,
+    requester: Requester,
     senders: Vec<oneshot::Sender<Output>>,
     active_waiters: usize,
 }
@@ -67,13 +329,146 @@ impl State {
     async fn new_build(
         &mut self,
         drv: DrvPath,
+        requester: Requester,
         sender: &mpsc::UnboundedSender<Msg>,
         abort_receiver: oneshot::Receiver<()>,
         res_sender: oneshot::Sender<Output>,
     ) -> Result<i32, Error> {
+        let build = self.insert_build(&drv, None)?;
+
+        self.builds.insert(
+            drv.clone(),
+            Build {
+                build: build.clone(),
+                requester: requester.clone(),
+                senders: vec![res_sender],
+                active_waiters: 1,
+            },
+        );
+
+        log_event(Event::BuildNew(build.handle()));
+
+        let abort = {
+            let drv = drv.clone();
+            let sender = sender.clone();
+            abort_thread(drv, sender, abort_receiver)
+        };
+        self.join_set.spawn(abort);
+
+        self.start(drv, requester, &build, sender)?;
+
+        Ok(build.build.id)
+    }
+
+    /// Starts a new attempt of a build that failed because of a transient
+    /// error. The runs waiting for the build are moved to the new attempt.
+    fn retry(
+        &mut self,
+        drv: DrvPath,
+        build: &mut Build,
+        sender: &mpsc::UnboundedSender<Msg>,
+    ) -> Result<(), Error> {
+        let previous = build.build.build.clone();
+        let new = self.insert_build(&drv, Some(&previous))?;
+        let runs = self
+            .conn
+            .transaction::<Vec<handles::Run>, Error, _>(|conn| {
+                diesel::update(schema::runs::table.filter(schema::runs::build_id.eq(previous.id)))
+                    .set(schema::runs::build_id.eq(new.build.id))
+                    .execute(conn)?;
+                let runs = schema::runs::table
+                    .inner_join(schema::jobs::table.inner_join(schema::evaluations::table))
+                    .filter(schema::runs::build_id.eq(new.build.id))
+                    .select((
+                        schema::evaluations::uuid,
+                        schema::jobs::system,
+                        schema::jobs::name,
+                        schema::runs::num,
+                    ))
+                    .load::<(String, String, String, i32)>(conn)?;
+                Ok(runs
+                    .into_iter()
+                    .map(|(uuid, system, name, num)| handles::Run {
+                        job: handles::Job {
+                            evaluation: handles::evaluation(uuid::Uuid::from_str(&uuid).unwrap()),
+                            system,
+                            name,
+                        },
+                        num: num as u32,
+                    })
+                    .collect())
+            })?;
+
+        log_event(Event::BuildNew(new.handle()));
+        for run in runs {
+            log_event(Event::RunUpdated(run));
+        }
+
+        let requester = build.requester.clone();
+        build.build = new.clone();
+        self.start(drv, requester, &new, sender)
+    }
+
+    /// Attaches the log of a derivation that Nix built on its own for the
+    /// build `parent` to the build of this derivation, recording a new build
+    /// if there is none, as a dependency of `parent`
+    fn dependency_log(&mut self, parent: i32, log: nix::DependencyLog) -> Result<(), Error> {
+        let nix::DependencyLog { drv, mut lines } = log;
+        if let Some(build) = self.builds.get(&drv) {
+            let id = build.build.task.task.id;
+            self.join_set.spawn(async move {
+                while let Some(Some(line)) = lines.recv().await {
+                    LOGS.send_line(&id, line);
+                }
+            });
+            return Ok(());
+        }
+
+        let build = self.insert_build(&drv, None)?;
+        record_dependencies(&mut self.conn, parent, &[build.build.id])?;
+        log_event(Event::BuildNew(build.handle()));
+        let run = |sender_log: mpsc::UnboundedSender<LogRecord>| async move {
+            while let Some(line) = lines.recv().await {
+                match line {
+                    Some(line) => {
+                        let _ = sender_log.send(line);
+                    }
+                    // the derivation succeeded if its outputs are valid
+                    None => {
+                        return Some(
+                            outputs_status(&drv)
+                                .await
+                                .is_ok_and(|statuses| statuses.iter().all(|status| status.valid)),
+                        )
+                    }
+                }
+            }
+            // Nix was interrupted
+            None
+        };
+        let finish = {
+            let handle = build.handle();
+            |res: Option<Option<bool>>| {
+                let status = match res.flatten() {
+                    Some(true) => TaskStatusKind::Success,
+                    Some(false) => TaskStatusKind::Failure,
+                    None => TaskStatusKind::Canceled,
+                };
+                (status, Event::BuildFinished(handle))
+            }
+        };
+        build.task.run(&mut self.conn, run, finish)
+    }
+
+    /// Records a new build in the database
+    fn insert_build(
+        &mut self,
+        drv: &DrvPath,
+        previous: Option<&models::Build>,
+    ) -> Result<builds::Build, Error> {
         use uuid::{timestamp, Uuid};
 
-        let build = self.conn.transaction::<builds::Build, Error, _>(|conn| {
+        self.conn.transaction::<builds::Build, Error, _>(|conn| {
             let task = tasks::Task::new(conn)?;
             let time_created = OffsetDateTime::now_utc().unix_timestamp();
             let uuid = Uuid::new_v7(timestamp::Timestamp::from_unix(
@@ -82,7 +477,9 @@ impl State {
                 0,
             ));
             let new_build = models::NewBuild {
+                attempt: previous.map(|build| build.attempt + 1).unwrap_or(1),
                 drv: &drv.to_string(),
+                previous_id: previous.map(|build| build.id),
                 task_id: task.task.id,
                 time_created,
                 uuid: &uuid.to_string(),
@@ -91,30 +488,22 @@ impl State {
                 .values(&new_build)
                 .get_result::<models::Build>(conn)?;
             Ok(builds::Build { build, task })
-        })?;
-
-        self.builds.insert(
-            drv.clone(),
-            Build {
-                build: build.clone(),
-                senders: vec![res_sender],
-                active_waiters: 1,
-            },
-        );
-
-        log_event(Event::BuildNew(build.handle()));
-
-        let abort = {
-            let drv = drv.clone();
-            let sender = sender.clone();
-            abort_thread(drv, sender, abort_receiver)
-        };
-        self.join_set.spawn(abort);
+        })
+    }
 
+    /// Runs the task of a build
+    fn start(
+        &mut self,
+        drv: DrvPath,
+        requester: Requester,
+        build: &builds::Build,
+        sender: &mpsc::UnboundedSender<Msg>,
+    ) -> Result<(), Error> {
         let run = {
             let drv = drv.clone();
+            let build = build.clone();
             let sender = sender.clone();
-            move |sender_log| run_build(drv, sender, sender_log)
+            move |sender_log| run_build(drv, build, requester, sender, sender_log)
         };
         let finish = {
             let drv = drv.clone();
@@ -127,49 +516,186 @@ impl State {
         };
         build.task.run(&mut self.conn, run, finish)?;
 
-        Ok(build.build.id)
+        Ok(())
     }
 }
 
 fn finish_build(drv: DrvPath, sender: mpsc::UnboundedSender<Msg>, res: Output) -> TaskStatusKind {
     let _ = sender.send(Msg::Finished(drv, res.clone()));
     match res {
-        Some(Some(())) => TaskStatusKind::Success,
-        Some(None) => TaskStatusKind::Failure,
+        Some(Ok(())) => TaskStatusKind::Success,
+        Some(Err(Failure::Failed | Failure::Transient | Failure::DependencyFailed(_))) => {
+            TaskStatusKind::Failure
+        }
+        Some(Err(Failure::TimedOut)) => TaskStatusKind::TimedOut,
         None => TaskStatusKind::Canceled,
     }
 }
 
+/// Queries the status of the outputs of a derivation in the store
+async fn outputs_status(drv: &DrvPath) -> Result<Vec<nix::store::PathStatus>, nix::Error> {
+    let paths: Vec<String> = nix::drv::read(drv)?
+        .outputs
+        .values()
+        .map(|output| output.path.clone())
+        .collect();
+    Ok(nix::store::query(paths).await?.into_values().collect())
+}
+
+/// Records the builds a build depends on
+fn record_dependencies(conn: &mut Conn, build_id: i32, dependencies: &[i32]) -> Result<(), Error> {
+    let rows: Vec<models::NewBuildDependency> = dependencies
+        .iter()
+        .map(|dependency_id| models::NewBuildDependency {
+            build_id,
+            dependency_id: *dependency_id,
+        })
+        .collect();
+    diesel::insert_or_ignore_into(schema::build_dependencies::table)
+        .values(&rows)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records which build made a build fail
+fn record_failed_dependency(conn: &mut Conn, build_id: i32, root: i32) -> Result<(), Error> {
+    diesel::update(schema::builds::table.find(build_id))
+        .set(schema::builds::failed_dependency_id.eq(root))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Builds the dependencies of a derivation, then builds it on an agent if one
+/// supports its system, or locally otherwise. The build is not started if a
+/// dependency fails.
 async fn run_build(
     drv: DrvPath,
+    build: builds::Build,
+    requester: Requester,
     sender: mpsc::UnboundedSender<Msg>,
-    sender_log: mpsc::UnboundedSender<String>,
-) -> Option<()> {
-    if nix::is_cached(&drv).await == Ok(false) {
-        let json: serde_json::Value = nix::derivation_json(&nix::Expr::Path(drv.to_string()))
-            .await
-            .ok()?;
-        let input_drvs = json[&drv.to_string()]["inputDrvs"].as_object().unwrap();
+    sender_log: mpsc::UnboundedSender<LogRecord>,
+) -> Result<(), Failure> {
+    let (sender_progress, receiver_progress) = mpsc::unbounded_channel();
+    let handle = build.handle();
+    let (res, ()) = tokio::join!(
+        run_build_aux(drv, build, requester, sender, sender_log, sender_progress),
+        track_progress(handle, receiver_progress),
+    );
+    res
+}
+
+async fn run_build_aux(
+    drv: DrvPath,
+    build: builds::Build,
+    requester: Requester,
+    sender: mpsc::UnboundedSender<Msg>,
+    sender_log: mpsc::UnboundedSender<LogRecord>,
+    sender_progress: mpsc::UnboundedSender<responses::BuildProgress>,
+) -> Result<(), Failure> {
+    let available = outputs_status(&drv)
+        .await
+        .map(|statuses| statuses.iter().all(nix::store::PathStatus::available));
+    if available == Ok(false) {
+        let derivation = nix::drv::read(&drv).map_err(|_| Failure::Failed)?;
         let mut handle_receivers: Vec<oneshot::Receiver<BuildHandle>> = Vec::new();
-        for (drv, _) in input_drvs {
+        for drv in derivation.input_drvs.keys() {
             let (handle_sender, handle_receiver) = oneshot::channel();
-            let _ = sender.send(Msg::Build(DrvPath::new(drv), handle_sender));
+            let _ = sender.send(Msg::Build(
+                DrvPath::new(drv),
+                requester.clone(),
+                handle_sender,
+            ));
             handle_receivers.push(handle_receiver);
         }
         let mut join_set = JoinSet::new();
-        for handle_receiver in handle_receivers.drain(..) {
-            join_set.spawn(async move {
-                let _ = handle_receiver.await.unwrap().wait().await; // FIXME
-            });
+        let mut dependencies = Vec::new();
+        for (drv, handle_receiver) in derivation.input_drvs.keys().zip(handle_receivers) {
+            let handle = handle_receiver.await.map_err(|_| Failure::Failed)?;
+            let (id, drv) = (handle.id, drv.clone());
+            dependencies.push(id);
+            join_set.spawn(async move { (id, drv, handle.wait().await) });
+        }
+        let build_id = build.build.id;
+        let res = RUNTIME
+            .spawn_blocking(move || {
+                record_dependencies(&mut POOL.get().unwrap(), build_id, &dependencies)
+            })
+            .await
+            .unwrap();
+        if let Err(e) = res {
+            tracing::error!(
+                "failed to record the dependencies of build {}: {}",
+                build_id,
+                e
+            );
         }
         while let Some(res) = join_set.join_next().await {
-            if res.is_err() {
-                return None;
+            match res {
+                Ok((id, drv, Some(Err(failure)))) => {
+                    let root = failure.root(id);
+                    let _ =
+                        sender_log.send(LogRecord::typhon(format!("dependency {} failed", drv)));
+                    let res = RUNTIME
+                        .spawn_blocking(move || {
+                            record_failed_dependency(&mut POOL.get().unwrap(), build_id, root)
+                        })
+                        .await
+                        .unwrap();
+                    if let Err(e) = res {
+                        tracing::error!(
+                            "failed to record the failed dependency of build {}: {}",
+                            build_id,
+                            e
+                        );
+                    }
+                    return Err(Failure::DependencyFailed(root));
+                }
+                Ok(_) => (),
+                Err(_) => return Err(Failure::Failed),
             }
         }
+        let features: Vec<String> = derivation
+            .env
+            .get("requiredSystemFeatures")
+            .map(|features| features.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
+        if agents::available(&derivation.system, &features) {
+            let request = agents::BuildRequest {
+                handle: build.handle(),
+                drv,
+                system: derivation.system.clone(),
+                features,
+                priority: requester.priority,
+                limits: requester.limits,
+            };
+            return agents::build(request, sender_log, sender_progress).await;
+        }
+    }
+    let limits = requester.limits.clone();
+    let _slot = acquire(drv.clone(), requester)
+        .await
+        .ok_or(Failure::Failed)?;
+    let (sender_dependencies, mut receiver_dependencies) = mpsc::unbounded_channel();
+    let (res, ()) = tokio::join!(
+        nix::build(
+            &drv,
+            sender_log,
+            Some(sender_progress),
+            Some(sender_dependencies),
+            &limits,
+        ),
+        async {
+            while let Some(log) = receiver_dependencies.recv().await {
+                let _ = sender.send(Msg::DependencyLog(build.build.id, log));
+            }
+        },
+    );
+    match res {
+        Ok(_) => Ok(()),
+        Err(nix::Error::TimedOut) => Err(Failure::TimedOut),
+        Err(nix::Error::BuildFailed { transient: true }) => Err(Failure::Transient),
+        Err(_) => Err(Failure::Failed),
     }
-    let _ = nix::build(&drv, sender_log).await.ok()?;
-    Some(())
 }
 
 async fn abort_thread(
@@ -196,10 +722,11 @@ async fn main_thread(
                     }
                 }
             }
-            Msg::Build(drv, handle_sender) => {
+            Msg::Build(drv, requester, handle_sender) => {
                 let (abort_sender, abort_receiver) = oneshot::channel();
                 let (res_sender, res_receiver) = oneshot::channel();
                 let id = if let Some(build) = state.builds.get_mut(&drv) {
+                    raise_priority(&drv, &requester);
                     build.senders.push(res_sender);
                     build.active_waiters = build.active_waiters + 1;
                     build.build.build.id
@@ -209,19 +736,22 @@ async fn main_thread(
                     match maybe_build {
                         Some(build) => {
                             if TaskStatusKind::from(&build.task.status()) == TaskStatusKind::Success
-                                && nix::is_built(&drv).await?
+                                && outputs_status(&drv)
+                                    .await?
+                                    .iter()
+                                    .all(|status| status.valid)
                             {
-                                let _ = res_sender.send(Some(Some(())));
+                                let _ = res_sender.send(Some(Ok(())));
                                 build.build.id
                             } else {
                                 state
-                                    .new_build(drv, &sender, abort_receiver, res_sender)
+                                    .new_build(drv, requester, &sender, abort_receiver, res_sender)
                                     .await?
                             }
                         }
                         None => {
                             state
-                                .new_build(drv, &sender, abort_receiver, res_sender)
+                                .new_build(drv, requester, &sender, abort_receiver, res_sender)
                                 .await?
                         }
                     }
@@ -233,13 +763,55 @@ async fn main_thread(
                 };
                 let _ = handle_sender.send(handle);
             }
+            Msg::DependencyLog(parent, log) => {
+                if let Err(e) = state.dependency_log(parent, log) {
+                    tracing::error!("failed to record the log of a dependency: {}", e);
+                }
+            }
             Msg::Finished(drv, res) => {
+                let settings = &crate::Settings::get().builds;
+                if let (Some(Err(Failure::Transient)), Some(build)) = (&res, state.builds.get(&drv))
+                {
+                    let attempt = build.build.build.attempt as u32;
+                    if attempt <= settings.max_retries && build.active_waiters > 0 {
+                        let delay = retry_delay(settings.retry_delay, attempt);
+                        tracing::info!(
+                            "build of {} failed with a transient error, retrying in {:?}",
+                            drv,
+                            delay
+                        );
+                        let sender = sender.clone();
+                        state.join_set.spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            let _ = sender.send(Msg::Retry(drv));
+                        });
+                        continue;
+                    }
+                }
                 if let Some(build) = state.builds.remove(&drv) {
                     for sender in build.senders {
                         let _ = sender.send(res.clone());
                     }
                 }
             }
+            Msg::Retry(drv) => {
+                if let Some(mut build) = state.builds.remove(&drv) {
+                    if build.active_waiters == 0 {
+                        // every run waiting for the build was canceled during
+                        // the delay
+                        for sender in build.senders {
+                            let _ = sender.send(None);
+                        }
+                    } else if let Err(e) = state.retry(drv.clone(), &mut build, &sender) {
+                        tracing::error!("failed to retry the build of {}: {}", drv, e);
+                        for sender in build.senders {
+                            let _ = sender.send(Some(Err(Failure::Transient)));
+                        }
+                    } else {
+                        state.builds.insert(drv, build);
+                    }
+                }
+            }
             Msg::Shutdown => break,
         }
     }
@@ -275,9 +847,11 @@ impl Builder {
         Self { sender, watch }
     }
 
-    pub fn run(&self, drv: DrvPath) -> BuildHandle {
+    pub fn run(&self, drv: DrvPath, requester: Requester) -> BuildHandle {
         let (handle_sender, handle_receiver) = oneshot::channel();
-        self.sender.send(Msg::Build(drv, handle_sender)).unwrap(); // FIXME
+        self.sender
+            .send(Msg::Build(drv, requester, handle_sender))
+            .unwrap(); // FIXME
         handle_receiver.blocking_recv().unwrap() // FIXME
     }
 
@@ -288,3 +862,140 @@ impl Builder {
 }
 
 pub static BUILDS: Lazy<Builder> = Lazy::new(Builder::new);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_maximum() {
+        let first = Duration::from_secs(10);
+        let delays: Vec<u64> = (1..=4)
+            .map(|attempt| retry_delay(first, attempt).as_secs())
+            .collect();
+        assert_eq!(delays, vec![10, 20, 40, 80]);
+        assert_eq!(retry_delay(first, 20), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(first, 40), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(first, u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn orphan_dependency_is_recorded() {
+        let mut state = State {
+            conn: crate::test_conn(),
+            builds: HashMap::new(),
+            join_set: JoinSet::new(),
+        };
+        let parent = state
+            .insert_build(
+                &DrvPath::new("/nix/store/00000000000000000000000000000000-parent.drv"),
+                None,
+            )
+            .unwrap();
+        let (log, lines) = mpsc::unbounded_channel();
+        let drv = DrvPath::new("/nix/store/00000000000000000000000000000000-dependency.drv");
+        state
+            .dependency_log(
+                parent.build.id,
+                nix::DependencyLog {
+                    drv: drv.clone(),
+                    lines,
+                },
+            )
+            .unwrap();
+        // Nix never finishes the dependency, so that its task does not record
+        // its status in the database of the server
+        std::mem::forget(log);
+
+        let child = schema::builds::table
+            .filter(schema::builds::drv.eq(drv.to_string()))
+            .select(schema::builds::id)
+            .first::<i32>(&mut state.conn)
+            .unwrap();
+        let dependencies = schema::build_dependencies::table
+            .filter(schema::build_dependencies::build_id.eq(parent.build.id))
+            .select(schema::build_dependencies::dependency_id)
+            .load::<i32>(&mut state.conn)
+            .unwrap();
+        assert_eq!(dependencies, vec![child]);
+    }
+
+    fn wait(queue: &mut Queue, project: &str, rerun: bool) -> oneshot::Receiver<Slot> {
+        let (start, receiver) = oneshot::channel();
+        queue.seq += 1;
+        queue.waiting.push(Waiting {
+            drv: DrvPath::new(&format!(
+                "/nix/store/00000000000000000000000000000000-{}.drv",
+                queue.seq
+            )),
+            requester: Requester {
+                project: project.to_string(),
+                priority: Priority { rerun, jobset: 0 },
+                limits: nix::BuildLimits::default(),
+            },
+            seq: queue.seq,
+            start,
+        });
+        receiver
+    }
+
+    /// Returns whether the build got a slot, disarming the slot so that
+    /// dropping it does not touch the global queue
+    fn started(receiver: &mut oneshot::Receiver<Slot>) -> bool {
+        receiver
+            .try_recv()
+            .map(|mut slot| slot.project.take())
+            .is_ok()
+    }
+
+    #[test]
+    fn queue_respects_the_limit() {
+        let mut queue = Queue::default();
+        let mut receivers: Vec<_> = (0..3).map(|_| wait(&mut queue, "a", false)).collect();
+        queue.start_next(2);
+        let slots: Vec<bool> = receivers.iter_mut().map(started).collect();
+        assert_eq!(slots, vec![true, true, false]);
+        assert_eq!(queue.running.get("a"), Some(&2));
+
+        queue.start_next(2);
+        assert!(!started(&mut receivers[2]));
+
+        queue.release("a".to_string());
+        queue.start_next(2);
+        assert!(started(&mut receivers[2]));
+        assert!(queue.waiting.is_empty());
+    }
+
+    #[test]
+    fn queue_starts_reruns_first() {
+        let mut queue = Queue::default();
+        let mut normal = wait(&mut queue, "a", false);
+        let mut rerun = wait(&mut queue, "a", true);
+        queue.start_next(1);
+        assert!(started(&mut rerun));
+        assert!(!started(&mut normal));
+    }
+
+    #[test]
+    fn queue_shares_slots_between_projects() {
+        let mut queue = Queue::default();
+        let mut a1 = wait(&mut queue, "a", false);
+        let mut a2 = wait(&mut queue, "a", false);
+        let mut b = wait(&mut queue, "b", false);
+        queue.start_next(1);
+        assert!(started(&mut a1));
+        queue.start_next(2);
+        assert!(started(&mut b));
+        assert!(!started(&mut a2));
+    }
+
+    #[test]
+    fn queue_skips_canceled_builds() {
+        let mut queue = Queue::default();
+        drop(wait(&mut queue, "a", false));
+        let mut next = wait(&mut queue, "b", false);
+        queue.start_next(1);
+        assert!(started(&mut next));
+        assert_eq!(queue.running.get("a"), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::build_manager;
 use crate::error::Error;
 use crate::models;
 use crate::nix;
@@ -8,6 +9,24 @@ use crate::Conn;
 use typhon_types::*;
 
 use diesel::prelude::*;
+use uuid::Uuid;
+
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+
+impl models::Build {
+    /// Returns the build whose failure prevented this one from running
+    pub fn failed_dependency(&self, conn: &mut Conn) -> Result<Option<handles::Build>, Error> {
+        let Some(id) = self.failed_dependency_id else {
+            return Ok(None);
+        };
+        let uuid = schema::builds::table
+            .find(id)
+            .select(schema::builds::uuid)
+            .first::<String>(conn)?;
+        Ok(Some(handles::build(Uuid::from_str(&uuid).unwrap())))
+    }
+}
 
 #[derive(Clone)]
 pub struct Build {
@@ -30,24 +49,73 @@ impl Build {
     }
 
     pub fn handle(&self) -> handles::Build {
-        use std::str::FromStr;
-        use uuid::Uuid;
         handles::build(Uuid::from_str(&self.build.uuid).unwrap())
     }
 
-    pub fn info(&self) -> responses::BuildInfo {
-        responses::BuildInfo {
+    pub fn info(&self, conn: &mut Conn) -> Result<responses::BuildInfo, Error> {
+        Ok(responses::BuildInfo {
             handle: self.handle(),
+            attempt: self.build.attempt as u32,
             drv: self.build.drv.clone(),
+            queue_position: build_manager::queue_position(&nix::DrvPath::new(&self.build.drv)),
             status: self.task.status(),
+            progress: build_manager::progress(&self.handle()),
+            failed_dependency: self.build.failed_dependency(conn)?,
+        })
+    }
+
+    /// Returns the builds of the dependencies of this build, as recorded
+    /// when it ran
+    fn dependencies(&self, conn: &mut Conn) -> Result<Vec<Self>, Error> {
+        Ok(schema::build_dependencies::table
+            .inner_join(
+                schema::builds::table
+                    .on(schema::builds::id.eq(schema::build_dependencies::dependency_id)),
+            )
+            .inner_join(schema::tasks::table.on(schema::tasks::id.eq(schema::builds::task_id)))
+            .filter(schema::build_dependencies::build_id.eq(self.build.id))
+            .order(schema::build_dependencies::id)
+            .select((schema::builds::all_columns, schema::tasks::all_columns))
+            .load::<(models::Build, models::Task)>(conn)?
+            .into_iter()
+            .map(|(build, task)| Self {
+                task: tasks::Task { task },
+                build,
+            })
+            .collect())
+    }
+
+    /// Returns the graph of the builds this build depends on, transitively
+    pub fn graph(&self, conn: &mut Conn) -> Result<responses::BuildGraph, Error> {
+        let mut nodes = Vec::new();
+        let mut seen = HashSet::from([self.build.id]);
+        let mut queue = VecDeque::from([self.clone()]);
+        while let Some(build) = queue.pop_front() {
+            let dependencies = build.dependencies(conn)?;
+            nodes.push(responses::BuildGraphNode {
+                build: build.info(conn)?,
+                dependencies: dependencies.iter().map(Self::handle).collect(),
+            });
+            for dependency in dependencies {
+                if seen.insert(dependency.build.id) {
+                    queue.push_back(dependency);
+                }
+            }
         }
+        Ok(responses::BuildGraph {
+            root: self.handle(),
+            nodes,
+        })
     }
 
     pub fn last(conn: &mut Conn, drv: &nix::DrvPath) -> Result<Option<Self>, Error> {
         Ok(schema::builds::table
             .inner_join(schema::tasks::table)
             .filter(schema::builds::drv.eq(drv.to_string()))
-            .order(schema::builds::time_created.desc())
+            .order((
+                schema::builds::time_created.desc(),
+                schema::builds::id.desc(),
+            ))
             .first(conn)
             .optional()?
             .map(|(build, task)| Self {
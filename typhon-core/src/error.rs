@@ -10,16 +10,25 @@ pub enum Error {
     ActionNotFound(handles::Action),
     BuildNotFound(handles::Build),
     RunNotFound(handles::Run),
+    BadEmailAddress(String),
     BadProjectDecl,
     BadJobsetDecl(String),
     EvaluationNotFound(handles::Evaluation),
     IllegalProjectHandle(handles::Project),
+    IllegalUserName(String),
+    InvalidBuildLimit(u64),
+    InvalidPassword(String),
+    InvalidRole(i32),
+    InvalidTokenKinds(String),
     JobAlreadyRunning(handles::Job),
+    JobNotEvaluated(handles::Job),
     JobNotFound(handles::Job),
     JobsetNotFound(handles::Jobset),
     LogNotFound(handles::Log),
+    LogStorageError(std::io::Error),
     NixError(nix::Error),
     ProjectAlreadyExists(handles::Project),
+    ProjectBeingDeleted(handles::Project),
     ProjectNotFound(handles::Project),
     Todo,
     UnexpectedDatabaseError(diesel::result::Error),
@@ -27,6 +36,13 @@ pub enum Error {
     LoginError,
     TaskError(task_manager::Error),
     BadWebhookOutput,
+    BadSubscriptionUrl(String),
+    SubscriptionAlreadyExists(String),
+    SubscriptionNotFound(String),
+    TokenAlreadyExists(String),
+    TokenNotFound(String),
+    UserAlreadyExists(String),
+    UserNotFound(String),
 }
 
 impl Error {
@@ -36,6 +52,9 @@ impl Error {
             ActionError(actions::Error::Unexpected)
             | UnexpectedDatabaseError(_)
             | UnexpectedTimeError(_)
+            | LogStorageError(_)
+            | InvalidRole(_)
+            | InvalidTokenKinds(_)
             | TaskError(_)
             | Todo => true,
             _ => false,
@@ -52,14 +71,27 @@ impl std::fmt::Display for Error {
             ActionNotFound(h) => write!(f, "Action not found: {}", h),
             BuildNotFound(h) => write!(f, "Build not found: {}", h),
             RunNotFound(h) => write!(f, "Run not found: {}", h),
+            BadEmailAddress(email) => write!(f, "Bad email address: {}", email),
             BadProjectDecl => write!(f, "Bad project declaration"),
             BadJobsetDecl(s) => write!(f, "Bad jobset declaration: {}", s),
             IllegalProjectHandle(handle) => {
                 write!(f, "The project name [{}] is illegal. Legal project names are sequences of alphanumerical characters that may contain dashes [-] or underscores [_].", handle.name)
             }
+            IllegalUserName(name) => {
+                write!(f, "The user name [{}] is illegal. Legal user names are sequences of alphanumerical characters that may contain dashes [-] or underscores [_].", name)
+            }
+            InvalidBuildLimit(limit) => write!(f, "Invalid build limit: {} seconds", limit),
+            InvalidPassword(e) => write!(f, "Invalid password: {}", e),
+            InvalidRole(role) => write!(f, "Invalid role in the database: {}", role),
+            InvalidTokenKinds(kinds) => {
+                write!(f, "Invalid token request kinds in the database: {}", kinds)
+            }
             JobAlreadyRunning(job_handle) => {
                 write!(f, "Job {} is already running", job_handle)
             }
+            JobNotEvaluated(job_handle) => {
+                write!(f, "Job {} failed to evaluate", job_handle)
+            }
             JobNotFound(job_handle) => {
                 write!(f, "Job {} not found", job_handle)
             }
@@ -69,12 +101,16 @@ impl std::fmt::Display for Error {
             LogNotFound(log_handle) => {
                 write!(f, "Log {} not found", log_handle)
             }
+            LogStorageError(e) => write!(f, "Log storage error: {}", e),
             EvaluationNotFound(evaluation_handle) => {
                 write!(f, "Evaluation {} not found", evaluation_handle)
             }
             ProjectAlreadyExists(project_handle) => {
                 write!(f, "Project {} already exists", project_handle)
             }
+            ProjectBeingDeleted(project_handle) => {
+                write!(f, "Project {} is being deleted", project_handle)
+            }
             ProjectNotFound(project_handle) => write!(f, "Project {} not found", project_handle),
             NixError(e) => write!(f, "Nix error: {}", e),
             LoginError => write!(f, "Login error"),
@@ -83,6 +119,13 @@ impl std::fmt::Display for Error {
             UnexpectedTimeError(e) => write!(f, "Time error: {}", e),
             TaskError(e) => write!(f, "Task error: {}", e),
             BadWebhookOutput => write!(f, "Bad webhook output"),
+            BadSubscriptionUrl(url) => write!(f, "Bad subscription URL: {}", url),
+            SubscriptionAlreadyExists(name) => write!(f, "Subscription {} already exists", name),
+            SubscriptionNotFound(name) => write!(f, "Subscription {} not found", name),
+            TokenAlreadyExists(name) => write!(f, "Token {} already exists", name),
+            TokenNotFound(name) => write!(f, "Token {} not found", name),
+            UserAlreadyExists(name) => write!(f, "User {} already exists", name),
+            UserNotFound(name) => write!(f, "User {} not found", name),
         }
     }
 }
@@ -99,6 +142,12 @@ impl From<time::error::ComponentRange> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::LogStorageError(e)
+    }
+}
+
 impl From<nix::Error> for Error {
     fn from(e: nix::Error) -> Error {
         Error::NixError(e)
@@ -124,6 +173,9 @@ impl Into<typhon_types::responses::ResponseError> for Error {
             ActionError(actions::Error::Unexpected)
             | UnexpectedDatabaseError(_)
             | UnexpectedTimeError(_)
+            | LogStorageError(_)
+            | InvalidRole(_)
+            | InvalidTokenKinds(_)
             | TaskError(_)
             | Todo => InternalError,
             EvaluationNotFound(_)
@@ -133,17 +185,30 @@ impl Into<typhon_types::responses::ResponseError> for Error {
             | ActionNotFound(_)
             | BuildNotFound(_)
             | RunNotFound(_)
-            | LogNotFound(_) => ResourceNotFound(format!("{}", self)),
+            | LogNotFound(_)
+            | SubscriptionNotFound(_)
+            | TokenNotFound(_)
+            | UserNotFound(_) => ResourceNotFound(format!("{}", self)),
             AccessDenied
             | ActionError(_)
+            | BadEmailAddress(_)
             | BadProjectDecl
             | BadJobsetDecl(_)
             | IllegalProjectHandle(_)
+            | IllegalUserName(_)
+            | InvalidBuildLimit(_)
+            | InvalidPassword(_)
             | JobAlreadyRunning(_)
+            | JobNotEvaluated(_)
             | NixError(_)
             | ProjectAlreadyExists(_)
+            | ProjectBeingDeleted(_)
             | LoginError
-            | BadWebhookOutput => BadRequest(format!("{}", self)),
+            | BadWebhookOutput
+            | BadSubscriptionUrl(_)
+            | SubscriptionAlreadyExists(_)
+            | TokenAlreadyExists(_)
+            | UserAlreadyExists(_) => BadRequest(format!("{}", self)),
         }
     }
 }
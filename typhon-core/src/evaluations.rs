@@ -10,6 +10,7 @@ use crate::POOL;
 
 use std::collections::HashMap;
 use typhon_types::data::TaskStatusKind;
+use typhon_types::responses::LogRecord;
 use typhon_types::*;
 
 use diesel::prelude::*;
@@ -25,16 +26,39 @@ pub struct Evaluation {
     pub project: models::Project,
 }
 
+impl models::Evaluation {
+    /// Returns the previous evaluation of the same jobset, if any
+    pub fn previous(&self, conn: &mut Conn) -> Result<Option<models::Evaluation>, Error> {
+        Ok(schema::evaluations::table
+            .filter(schema::evaluations::project_id.eq(self.project_id))
+            .filter(schema::evaluations::jobset_name.eq(&self.jobset_name))
+            .filter(
+                schema::evaluations::time_created.lt(self.time_created).or(
+                    schema::evaluations::time_created
+                        .eq(self.time_created)
+                        .and(schema::evaluations::id.lt(self.id)),
+                ),
+            )
+            .order((
+                schema::evaluations::time_created.desc(),
+                schema::evaluations::id.desc(),
+            ))
+            .first::<models::Evaluation>(conn)
+            .optional()?)
+    }
+}
+
 #[ext_trait::extension(pub trait ExtraRunInfo)]
 impl responses::RunInfo {
     fn new(
+        conn: &mut Conn,
         project_handle: &handles::Project,
         job_handle: &handles::Job,
         run: models::Run,
         begin: Option<(models::Action, models::Task)>,
         build: Option<(models::Build, models::Task)>,
         end: Option<(models::Action, models::Task)>,
-    ) -> Self {
+    ) -> Result<responses::RunInfo, Error> {
         let to_action_info =
             |(action, task): (models::Action, models::Task)| responses::ActionInfo {
                 handle: handles::action(Uuid::from_str(&action.uuid).unwrap()),
@@ -44,19 +68,34 @@ impl responses::RunInfo {
                 project: project_handle.clone(),
                 status: task.status(),
             };
-        responses::RunInfo {
+        let build = match build {
+            Some((build, task)) => {
+                let handle = handles::build(Uuid::from_str(&build.uuid).unwrap());
+                Some(responses::BuildInfo {
+                    progress: crate::build_manager::progress(&handle),
+                    handle,
+                    attempt: build.attempt as u32,
+                    queue_position: crate::build_manager::queue_position(&nix::DrvPath::new(
+                        &build.drv,
+                    )),
+                    status: task.status(),
+                    failed_dependency: build.failed_dependency(conn)?,
+                    drv: build.drv,
+                })
+            }
+            None => None,
+        };
+        Ok(responses::RunInfo {
             handle: handles::Run {
                 job: job_handle.clone(),
                 num: run.num as u32,
             },
+            status: run.status(),
+            previous_status: run.previous_status.map(|status| status.try_into().unwrap()),
             begin: begin.map(to_action_info),
-            build: build.map(|(build, task)| responses::BuildInfo {
-                handle: handles::build(Uuid::from_str(&build.uuid).unwrap()),
-                drv: build.drv,
-                status: task.status(),
-            }),
+            build,
             end: end.map(to_action_info),
-        }
+        })
     }
 }
 
@@ -64,28 +103,48 @@ impl responses::RunInfo {
 impl responses::JobInfo {
     /// Reshape raw database data into a structured `JobInfo`
     fn new(
+        conn: &mut Conn,
         project_handle: &handles::Project,
         eval_handle: &handles::Evaluation,
+        eval_time: responses::TimeRange,
         job: models::Job,
-        run: models::Run,
+        run: Option<models::Run>,
         begin: Option<(models::Action, models::Task)>,
         build: Option<(models::Build, models::Task)>,
         end: Option<(models::Action, models::Task)>,
-    ) -> Self {
+    ) -> Result<responses::JobInfo, Error> {
         let job_handle = handles::Job {
             evaluation: eval_handle.clone(),
             system: job.system.clone(),
             name: job.name.clone(),
         };
-        Self {
+        let outputs = job.outputs();
+        let last_run = match run {
+            Some(run) => Some(responses::RunInfo::new(
+                conn,
+                project_handle,
+                &job_handle,
+                run,
+                begin,
+                build,
+                end,
+            )?),
+            None => None,
+        };
+        Ok(Self {
             handle: job_handle.clone(),
             dist: job.dist,
             drv: job.drv,
+            eval_error: job.eval_error.map(|message| responses::JobEvalError {
+                message,
+                time: eval_time,
+            }),
             out: job.out,
+            outputs,
             system: job.system,
-            last_run: responses::RunInfo::new(project_handle, &job_handle, run, begin, build, end),
+            last_run,
             run_count: job.tries as u32,
-        }
+        })
     }
 }
 
@@ -94,12 +153,95 @@ impl Evaluation {
         self.task.cancel()
     }
 
-    pub fn finish(self, r: Option<Result<nix::NewJobs, nix::Error>>) -> TaskStatusKind {
+    /// Compares the jobs of the evaluation with the ones of `base`
+    pub fn diff(&self, conn: &mut Conn, base: &Self) -> Result<responses::EvaluationDiff, Error> {
+        fn load(
+            conn: &mut Conn,
+            evaluation_id: i32,
+        ) -> Result<HashMap<responses::JobSystemName, (String, TaskStatusKind)>, Error> {
+            Ok(schema::jobs::table
+                .left_join(
+                    schema::runs::table.on(schema::runs::job_id
+                        .eq(schema::jobs::id)
+                        .and(schema::runs::num.eq(schema::jobs::tries))),
+                )
+                .filter(schema::jobs::evaluation_id.eq(evaluation_id))
+                .select((
+                    schema::jobs::system,
+                    schema::jobs::name,
+                    schema::jobs::drv,
+                    schema::jobs::eval_error,
+                    schema::runs::status.nullable(),
+                ))
+                .load::<(String, String, String, Option<String>, Option<i32>)>(conn)?
+                .into_iter()
+                .map(|(system, name, drv, eval_error, status)| {
+                    let status = match (eval_error, status) {
+                        (Some(_), _) => TaskStatusKind::Failure,
+                        (None, status) => status
+                            .map(|status| status.try_into().unwrap())
+                            .unwrap_or_default(),
+                    };
+                    (responses::JobSystemName { system, name }, (drv, status))
+                })
+                .collect())
+        }
+
+        let jobs = load(conn, self.evaluation.id)?;
+        let base_jobs = load(conn, base.evaluation.id)?;
+
+        let mut diff = responses::EvaluationDiff {
+            base: base.handle(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            rebuilt: Vec::new(),
+            unchanged: Vec::new(),
+            transitions: Vec::new(),
+        };
+        for (job, (drv, status)) in jobs.iter() {
+            match base_jobs.get(job) {
+                Some((base_drv, base_status)) => {
+                    if drv == base_drv {
+                        diff.unchanged.push(job.clone());
+                    } else {
+                        diff.rebuilt.push(job.clone());
+                    }
+                    if status != base_status {
+                        diff.transitions.push(responses::StatusTransition {
+                            job: job.clone(),
+                            before: *base_status,
+                            after: *status,
+                        });
+                    }
+                }
+                None => diff.added.push(job.clone()),
+            }
+        }
+        for job in base_jobs.keys() {
+            if !jobs.contains_key(job) {
+                diff.removed.push(job.clone());
+            }
+        }
+
+        let key = |job: &responses::JobSystemName| (job.system.clone(), job.name.clone());
+        diff.added.sort_by_key(key);
+        diff.removed.sort_by_key(key);
+        diff.rebuilt.sort_by_key(key);
+        diff.unchanged.sort_by_key(key);
+        diff.transitions
+            .sort_by_key(|transition| key(&transition.job));
+
+        Ok(diff)
+    }
+
+    /// Creates the jobs that evaluated. The evaluation fails if some errors
+    /// are not the error of a job, since jobs may then be missing.
+    pub fn finish(self, r: Option<Result<nix::EvalJobs, nix::Error>>) -> TaskStatusKind {
         let mut conn = POOL.get().unwrap();
         match r {
-            Some(Ok(new_jobs)) => match self.create_new_jobs(&mut conn, new_jobs) {
-                Ok(()) => TaskStatusKind::Success,
-                Err(_) => TaskStatusKind::Failure,
+            Some(Ok(new_jobs)) => match self.create_new_jobs(&mut conn, new_jobs.jobs) {
+                Ok(()) if new_jobs.errors.is_empty() => TaskStatusKind::Success,
+                Ok(()) | Err(_) => TaskStatusKind::Failure,
             },
             Some(Err(_)) => TaskStatusKind::Failure,
             None => TaskStatusKind::Canceled,
@@ -134,16 +276,33 @@ impl Evaluation {
         filter_name: Option<String>,
         conn: &mut Conn,
     ) -> Result<HashMap<responses::JobSystemName, responses::JobInfo>, Error> {
-        let (begin_action, end_action, begin_task, build_task, end_task, subruns) = diesel::alias!(
+        let (begin_action, end_action, begin_task, build_task, end_task) = diesel::alias!(
             schema::actions as begin_action,
             schema::actions as end_action,
             schema::tasks as begin_task,
             schema::tasks as build_task,
             schema::tasks as end_task,
-            schema::runs as subruns,
         );
+        let eval_time = {
+            let (start, end) = schema::evaluations::table
+                .find(eval_id)
+                .inner_join(schema::tasks::table)
+                .select(schema::tasks::all_columns)
+                .first::<models::Task>(conn)?
+                .status()
+                .times();
+            let now = time::OffsetDateTime::now_utc();
+            responses::TimeRange {
+                start: start.unwrap_or(now),
+                end: end.unwrap_or(now),
+            }
+        };
         let mut query = schema::jobs::table
-            .inner_join(schema::runs::table)
+            .left_join(
+                schema::runs::table.on(schema::runs::job_id
+                    .eq(schema::jobs::id)
+                    .and(schema::runs::num.eq(schema::jobs::tries))),
+            )
             .left_join(
                 begin_action
                     .on(begin_action
@@ -165,13 +324,6 @@ impl Evaluation {
                         .eq(schema::runs::end_id))
                     .inner_join(end_task),
             )
-            .filter(
-                schema::runs::job_id.nullable().eq(subruns
-                    .filter(subruns.field(schema::runs::job_id).eq(schema::jobs::id))
-                    .group_by(subruns.field(schema::runs::job_id))
-                    .select(diesel::dsl::max(subruns.field(schema::runs::id)))
-                    .single_value()),
-            )
             .filter(schema::jobs::evaluation_id.eq(eval_id))
             .into_boxed();
         if let Some(system) = filter_system {
@@ -180,10 +332,10 @@ impl Evaluation {
         if let Some(name) = filter_name {
             query = query.filter(schema::jobs::name.eq(name));
         }
-        Ok(query
+        query
             .select((
                 schema::jobs::all_columns,
-                schema::runs::all_columns,
+                schema::runs::all_columns.nullable(),
                 (
                     begin_action.fields(schema::actions::all_columns),
                     begin_task.fields(schema::tasks::all_columns),
@@ -203,23 +355,25 @@ impl Evaluation {
             .load(conn)?
             .into_iter()
             .map(
-                |(job, run, begin, build, end): (models::Job, models::Run, _, _, _)| {
+                |(job, run, begin, build, end): (models::Job, Option<models::Run>, _, _, _)| {
                     let (system, name) = (job.system.clone(), job.name.clone());
-                    (
+                    Ok((
                         responses::JobSystemName { system, name },
                         responses::JobInfo::new(
+                            conn,
                             project_handle,
                             &eval_handle,
+                            eval_time,
                             job,
                             run,
                             begin,
                             build,
                             end,
-                        ),
-                    )
+                        )?,
+                    ))
                 },
             )
-            .collect())
+            .collect()
     }
 
     pub fn info(&self, conn: &mut Conn) -> Result<responses::EvaluationInfo, Error> {
@@ -240,6 +394,7 @@ impl Evaluation {
                 HashMap::new()
             },
             jobset_name: self.evaluation.jobset_name.clone(),
+            previous: self.previous(conn)?,
             project: handles::project(self.project.name.clone()),
             status: self.task.status(),
             time_created: time::OffsetDateTime::from_unix_timestamp(self.evaluation.time_created)?,
@@ -247,20 +402,49 @@ impl Evaluation {
         })
     }
 
+    /// Returns the previous evaluation of the same jobset, if any
+    pub fn previous(&self, conn: &mut Conn) -> Result<Option<handles::Evaluation>, Error> {
+        Ok(self
+            .evaluation
+            .previous(conn)?
+            .map(|evaluation| handles::evaluation(Uuid::from_str(&evaluation.uuid).unwrap())))
+    }
+
     pub async fn run(
         self,
-        sender: mpsc::UnboundedSender<String>,
-    ) -> Result<nix::NewJobs, nix::Error> {
+        sender: mpsc::UnboundedSender<LogRecord>,
+    ) -> Result<nix::EvalJobs, nix::Error> {
         let res = nix::eval_jobs(&self.evaluation.url, self.evaluation.flake).await;
         match &res {
+            Ok(jobs) => {
+                for e in &jobs.errors {
+                    let mut lines = e.split("\n");
+                    if let Some(line) = lines.next() {
+                        let _ = sender.send(LogRecord::typhon(line));
+                    }
+                    for line in lines {
+                        let _ = sender.send(LogRecord::output(line));
+                    }
+                }
+                for ((system, name), job) in &jobs.jobs {
+                    if let Err(e) = job {
+                        let _ = sender.send(LogRecord::typhon(format!(
+                            "Job {}.{} failed to evaluate:",
+                            system, name
+                        )));
+                        for line in e.split("\n") {
+                            let _ = sender.send(LogRecord::output(line));
+                        }
+                    }
+                }
+            }
             Err(e) => {
                 for line in e.to_string().split("\n") {
                     // TODO: hide internal error messages?
                     // TODO: error management
-                    let _ = sender.send(line.to_string());
+                    let _ = sender.send(LogRecord::output(line));
                 }
             }
-            _ => (),
         }
         res
     }
@@ -269,19 +453,42 @@ impl Evaluation {
         let created_runs = conn.transaction::<Vec<crate::runs::Run>, Error, _>(|conn| {
             let created_jobs: Vec<crate::jobs::Job> = new_jobs
                 .into_iter()
-                .map(|((system, name), (drv, dist))| {
+                .map(|((system, name), job)| {
+                    let (drv, out, outputs, dist, eval_error) = match &job {
+                        Ok(job) => (
+                            job.derivation.path.to_string(),
+                            job.derivation.main_output().cloned().unwrap_or_default(),
+                            serde_json::to_string(&job.derivation.outputs).unwrap(),
+                            job.dist,
+                            None,
+                        ),
+                        // jobs that failed to evaluate have no derivation
+                        Err(e) => (
+                            String::new(),
+                            String::new(),
+                            "{}".to_string(),
+                            false,
+                            Some(e.as_str()),
+                        ),
+                    };
+                    // a limit too large to be stored is as good as no limit,
+                    // which is a limit of zero
+                    let seconds = |t: u64| i64::try_from(t).unwrap_or(0);
+                    let (timeout, max_silent) = match &job {
+                        Ok(job) => (job.timeout.map(seconds), job.max_silent.map(seconds)),
+                        Err(_) => (None, None),
+                    };
                     let new_job = models::NewJob {
                         dist,
-                        drv: &drv.path.to_string(),
+                        drv: &drv,
+                        eval_error,
                         evaluation_id: self.evaluation.id,
+                        max_silent,
                         name: &name,
-                        out: drv
-                            .outputs
-                            .iter()
-                            .last()
-                            .expect("TODO: derivations can have multiple outputs")
-                            .1,
+                        out: &out,
+                        outputs: &outputs,
                         system: &system,
+                        timeout,
                         tries: 0,
                     };
                     let job = diesel::insert_into(schema::jobs::table)
@@ -296,6 +503,7 @@ impl Evaluation {
                 .collect::<Result<_, Error>>()?;
             created_jobs
                 .into_iter()
+                .filter(|job| job.job.eval_error.is_none())
                 .map(|job| job.new_run(conn))
                 .collect()
         })?;
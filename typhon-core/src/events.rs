@@ -48,9 +48,15 @@ impl EventLogger {
         let _ = self.sender.send(Msg::Emit(event));
     }
 
-    pub fn listen(&self) -> Option<impl Stream<Item = Event>> {
-        let (sender, mut receiver) = mpsc::unbounded_channel();
+    /// Returns a channel receiving all the events emitted from now on
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<Event> {
+        let (sender, receiver) = mpsc::unbounded_channel();
         let _ = self.sender.send(Msg::Listen(sender));
+        receiver
+    }
+
+    pub fn listen(&self) -> Option<impl Stream<Item = Event>> {
+        let mut receiver = self.subscribe();
         Some(async_stream::stream! {
             yield Event::Ping;
             while let Some(e) = receiver.recv().await {
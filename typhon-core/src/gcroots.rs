@@ -36,7 +36,7 @@ impl From<nix::Error> for Error {
 }
 
 allow_columns_to_appear_in_same_group_by_clause!(
-    schema::jobs::out,
+    schema::jobs::outputs,
     schema::jobs::drv,
     schema::jobsets::name,
 );
@@ -49,9 +49,15 @@ fn update_aux(conn: &mut Conn) -> Result<(), Error> {
         .inner_join(
             schema::jobsets::table.on(schema::evaluations::jobset_name.eq(schema::jobsets::name)),
         )
-        .group_by((schema::jobs::out, schema::jobs::drv, schema::jobsets::name))
+        // jobs that failed to evaluate have no derivation
+        .filter(schema::jobs::eval_error.is_null())
+        .group_by((
+            schema::jobs::outputs,
+            schema::jobs::drv,
+            schema::jobsets::name,
+        ))
         .select((
-            schema::jobs::out,
+            schema::jobs::outputs,
             schema::jobs::drv,
             schema::jobsets::name,
             diesel::dsl::max(schema::evaluations::time_created),
@@ -61,7 +67,7 @@ fn update_aux(conn: &mut Conn) -> Result<(), Error> {
         .select(schema::projects::actions_path)
         .load::<Option<String>>(conn)?;
 
-    for (path, drv, _, _) in res_1.drain(..) {
+    for (outputs, drv, _, _) in res_1.drain(..) {
         if let Ok(deps) = nix::dependencies(&drv) {
             for dep in deps {
                 gcroots.insert(dep);
@@ -70,7 +76,8 @@ fn update_aux(conn: &mut Conn) -> Result<(), Error> {
             tracing::warn!("gcroots: missing derivation {}", drv);
         }
         gcroots.insert(drv);
-        gcroots.insert(path);
+        let outputs: nix::DrvOutputs = serde_json::from_str(&outputs).unwrap();
+        gcroots.extend(outputs.into_values());
     }
     for actions in res_2.drain(..) {
         if let Some(path) = actions {
@@ -2,11 +2,14 @@ use crate::error::Error;
 use crate::handles;
 use crate::log_event;
 use crate::models;
+use crate::nix;
+use crate::projects;
 use crate::responses;
 use crate::runs;
 use crate::schema;
 use crate::Conn;
 
+use typhon_types::data::TaskStatusKind;
 use typhon_types::*;
 
 use diesel::prelude::*;
@@ -19,6 +22,13 @@ pub struct Job {
     pub project: models::Project,
 }
 
+impl models::Job {
+    /// Returns the outputs of the job, by name
+    pub fn outputs(&self) -> nix::DrvOutputs {
+        serde_json::from_str(&self.outputs).unwrap()
+    }
+}
+
 impl Job {
     pub fn get(conn: &mut Conn, handle: &handles::Job) -> Result<Self, Error> {
         let (job, (evaluation, project)) = schema::jobs::table
@@ -67,8 +77,13 @@ impl Job {
 
     /** Create a new run in the database, without running it. */
     pub fn new_run(&self, conn: &mut Conn) -> Result<runs::Run, Error> {
+        projects::check_not_deleting(&self.project)?;
         let run = conn.transaction::<models::Run, Error, _>(|conn| {
-            let num = self.job.tries + 1;
+            let tries = schema::jobs::table
+                .find(self.job.id)
+                .select(schema::jobs::tries)
+                .first::<i32>(conn)?;
+            let num = tries + 1;
             diesel::update(&self.job)
                 .set(schema::jobs::tries.eq(num))
                 .execute(conn)?;
@@ -95,11 +110,34 @@ impl Job {
     }
 
     pub fn rerun(&self, conn: &mut Conn) -> Result<(), Error> {
-        // TODO
-        // We should only allow rerunning a job when no other run is pending for
-        // that job. But we first need to rework runs, as it is currently hard
-        // to know wether a run is finished or not.
-        self.new_run(conn)?;
+        if self.job.eval_error.is_some() {
+            return Err(Error::JobNotEvaluated(self.handle()));
+        }
+        let run = conn.transaction::<runs::Run, Error, _>(|conn| {
+            // the last run is read again in the transaction, since another
+            // rerun may have started since this job was loaded
+            let last_run = schema::runs::table
+                .filter(schema::runs::job_id.eq(self.job.id))
+                .order(schema::runs::num.desc())
+                .first::<models::Run>(conn)
+                .optional()?;
+            if last_run.is_some_and(|run| run.status_kind() == TaskStatusKind::Pending) {
+                return Err(Error::JobAlreadyRunning(self.handle()));
+            }
+            self.new_run(conn)
+        })?;
+        if let Err(e) = run.run(conn) {
+            // the run is already recorded as pending: it is marked as failed,
+            // or else later reruns of the job would be refused
+            diesel::update(&run.run)
+                .set((
+                    schema::runs::status.eq(i32::from(TaskStatusKind::Failure)),
+                    schema::runs::time_finished.eq(OffsetDateTime::now_utc().unix_timestamp()),
+                ))
+                .execute(conn)?;
+            log_event(Event::RunUpdated(run.handle()));
+            return Err(e);
+        }
         Ok(())
     }
 }
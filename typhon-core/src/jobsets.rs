@@ -3,11 +3,14 @@ use crate::evaluations;
 use crate::gcroots;
 use crate::models;
 use crate::nix;
+use crate::projects;
 use crate::schema;
 use crate::Conn;
 use crate::{handles, responses};
 use crate::{log_event, Event};
 
+use typhon_types::data::Schedule;
+
 use diesel::prelude::*;
 use serde::Deserialize;
 use time::OffsetDateTime;
@@ -22,6 +25,16 @@ pub struct Jobset {
 pub struct JobsetDecl {
     pub flake: bool,
     pub url: String,
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// Interval in seconds at which the URL is locked again, a new
+    /// evaluation is started when the locked URL changes
+    #[serde(default)]
+    pub poll: Option<u64>,
+    /// Priority of the builds of the jobset, higher priorities are built
+    /// first
+    #[serde(default)]
+    pub priority: i32,
 }
 
 impl Jobset {
@@ -33,6 +46,7 @@ impl Jobset {
     pub fn evaluate(&self, conn: &mut Conn, force: bool) -> Result<handles::Evaluation, Error> {
         use crate::tasks;
 
+        projects::check_not_deleting(&self.project)?;
         let url = nix::lock(&self.jobset.url)?;
 
         let preexisting = schema::evaluations::table
@@ -58,9 +72,40 @@ impl Jobset {
         JobsetDecl {
             flake: self.jobset.flake,
             url: self.jobset.url.clone(),
+            schedule: self
+                .jobset
+                .schedule
+                .as_ref()
+                .map(|schedule| serde_json::from_str(schedule).unwrap()),
+            poll: self.jobset.poll.map(|seconds| seconds as u64),
+            priority: self.jobset.priority,
         }
     }
 
+    /// Locks the URL of the jobset and evaluates it if the locked URL differs
+    /// from the one of the latest evaluation of the jobset
+    pub fn poll(&self, conn: &mut Conn) -> Result<Option<handles::Evaluation>, Error> {
+        projects::check_not_deleting(&self.project)?;
+        let url = nix::lock(&self.jobset.url)?;
+
+        let latest = schema::evaluations::table
+            .filter(schema::evaluations::project_id.eq(self.project.id))
+            .filter(schema::evaluations::jobset_name.eq(&self.jobset.name))
+            .order((
+                schema::evaluations::time_created.desc(),
+                schema::evaluations::id.desc(),
+            ))
+            .select(schema::evaluations::url)
+            .first::<String>(conn)
+            .optional()?;
+
+        if latest.as_ref() == Some(&url) {
+            return Ok(None);
+        }
+
+        Ok(Some(self.new_evaluation(conn, &url)?.handle()))
+    }
+
     pub fn get(conn: &mut Conn, handle: &handles::Jobset) -> Result<Self, Error> {
         let (jobset, project) = schema::jobsets::table
             .inner_join(schema::projects::table)
@@ -81,12 +126,20 @@ impl Jobset {
         }
     }
 
-    pub fn info(&self) -> responses::JobsetInfo {
-        responses::JobsetInfo {
+    pub fn info(&self) -> Result<responses::JobsetInfo, Error> {
+        Ok(responses::JobsetInfo {
             handle: self.handle(),
             flake: self.jobset.flake,
             url: self.jobset.url.clone(),
-        }
+            schedule: self.decl().schedule,
+            poll: self.decl().poll,
+            priority: self.jobset.priority,
+            next_evaluation: self
+                .jobset
+                .time_next_evaluation
+                .map(OffsetDateTime::from_unix_timestamp)
+                .transpose()?,
+        })
     }
 
     fn new_evaluation(
@@ -97,6 +150,7 @@ impl Jobset {
         use crate::tasks;
         use uuid::{timestamp, Uuid};
 
+        projects::check_not_deleting(&self.project)?;
         let evaluation = conn.transaction::<evaluations::Evaluation, Error, _>(|conn| {
             let task = tasks::Task::new(conn)?;
             let time_created = OffsetDateTime::now_utc().unix_timestamp();
@@ -1,6 +1,7 @@
 #![feature(impl_trait_in_fn_trait_return)]
 
 mod actions;
+mod agents;
 mod builds;
 mod evaluations;
 mod events;
@@ -8,16 +9,21 @@ mod gcroots;
 mod jobs;
 mod jobsets;
 mod models;
-mod nix;
+mod notifications;
 mod projects;
 mod runs;
+mod scheduler;
 mod schema;
 mod search;
+mod subscriptions;
 mod tasks;
+mod tokens;
+mod users;
 
 pub mod build_manager;
 pub mod error;
 pub mod logs;
+pub mod nix;
 pub mod task_manager;
 use search::search;
 
@@ -27,6 +33,10 @@ pub use typhon_types::{
 };
 
 pub use crate::actions::webhooks;
+pub use crate::build_manager::BuildSettings;
+pub use crate::logs::LogSettings;
+pub use crate::nix::EvalSettings;
+pub use crate::notifications::SmtpSettings;
 
 use actions::Action;
 use builds::Build;
@@ -52,6 +62,9 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug)]
 pub struct Settings {
     pub password: PasswordHash<'static>,
+    pub eval: EvalSettings,
+    pub builds: BuildSettings,
+    pub logs: LogSettings,
 }
 
 const _: () = {
@@ -106,10 +119,13 @@ pub static LOGS: Lazy<logs::live::Cache<i32>> = Lazy::new(logs::live::Cache::new
 pub static EVENT_LOGGER: Lazy<events::EventLogger> = Lazy::new(events::EventLogger::new);
 pub static CURRENT_SYSTEM: Lazy<String> = Lazy::new(nix::current_system);
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// The identity a request is made with
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum User {
     Admin,
+    Account(String),
     Anonymous,
+    Token(i32),
 }
 
 impl User {
@@ -126,22 +142,158 @@ impl User {
             User::Anonymous
         }
     }
+    /// Authenticates an API token. This queries the database, so it must not
+    /// be called from an async context.
+    pub fn from_token(token: &str) -> Self {
+        let mut conn = match POOL.get() {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::error!("token authentication raised error: {:?}", e);
+                return User::Anonymous;
+            }
+        };
+        match tokens::Token::authenticate(&mut conn, token) {
+            Ok(Some(token)) => User::Token(token.token.id),
+            Ok(None) => User::Anonymous,
+            Err(e) => {
+                tracing::error!("token authentication raised error: {:?}", e);
+                User::Anonymous
+            }
+        }
+    }
+}
+
+impl From<data::User> for User {
+    fn from(user: data::User) -> Self {
+        match user {
+            data::User::Admin => User::Admin,
+            data::User::Account { name, .. } => User::Account(name),
+        }
+    }
+}
+
+/// Returns the project a request acts upon, along with the minimal role
+/// needed to perform it. Requests that are not scoped to a project
+/// return `None` and are reserved to administrators.
+fn required_role(
+    conn: &mut Conn,
+    req: &requests::Request,
+) -> Result<Option<(handles::Project, data::Role)>, Error> {
+    use data::Role;
+    use requests::*;
+    Ok(match req {
+        Request::Project(
+            h,
+            Project::Delete
+            | Project::Refresh
+            | Project::SetBuildLimits(_)
+            | Project::SetDecl(_)
+            | Project::SetNotifications(_)
+            | Project::SetRole { .. },
+        ) => Some((h.clone(), Role::Maintainer)),
+        Request::Project(h, Project::UpdateJobsets) => Some((h.clone(), Role::Operator)),
+        Request::Jobset(h, Jobset::Evaluate(_)) => Some((h.project.clone(), Role::Operator)),
+        Request::Evaluation(h, Evaluation::Cancel) => {
+            let evaluation = evaluations::Evaluation::get(conn, h)?;
+            Some((handles::project(evaluation.project.name), Role::Operator))
+        }
+        Request::Job(h, Job::Rerun) => {
+            let evaluation = evaluations::Evaluation::get(conn, &h.evaluation)?;
+            Some((handles::project(evaluation.project.name), Role::Operator))
+        }
+        Request::Run(h, Run::Cancel) => {
+            let evaluation = evaluations::Evaluation::get(conn, &h.job.evaluation)?;
+            Some((handles::project(evaluation.project.name), Role::Operator))
+        }
+        Request::CreateSubscription { decl, .. } => decl
+            .project
+            .clone()
+            .map(|project| (project, Role::Maintainer)),
+        Request::DeleteSubscription { name } | Request::Deliveries { name } => {
+            match subscriptions::Subscription::get(conn, name) {
+                Ok(subscription) => subscription
+                    .project
+                    .map(|project| (handles::project(project.name), Role::Maintainer)),
+                Err(Error::SubscriptionNotFound(_)) => None,
+                Err(e) => return Err(e),
+            }
+        }
+        Request::Subscriptions { project } => {
+            project.clone().map(|project| (project, Role::Maintainer))
+        }
+        _ => None,
+    })
 }
 
-pub fn authorize_request(user: &User, req: &requests::Request) -> bool {
+pub fn authorize_request(
+    conn: &mut Conn,
+    user: &User,
+    req: &requests::Request,
+) -> Result<bool, Error> {
     use requests::*;
     match req {
         Request::Search { .. }
         | Request::Project(_, Project::Info)
         | Request::Jobset(_, Jobset::Info)
-        | Request::Evaluation(_, Evaluation::Info)
+        | Request::Evaluation(_, Evaluation::Diff(_) | Evaluation::Info)
         | Request::Job(_, Job::Info)
         | Request::Run(_, Run::Info)
-        | Request::Build(_, Build::Info)
+        | Request::Build(_, Build::Graph | Build::Info)
         | Request::Action(_, Action::Info)
         | Request::Login { .. }
-        | Request::User => true,
-        _ => user.is_admin(),
+        | Request::User => return Ok(true),
+        _ => (),
+    }
+    let account = match user {
+        User::Admin => None,
+        User::Anonymous => return Ok(false),
+        User::Account(name) => match users::User::get(conn, name) {
+            Ok(account) => Some(account),
+            Err(Error::UserNotFound(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        },
+        User::Token(id) => {
+            let token = match tokens::Token::get_by_id(conn, *id)? {
+                Some(token) => token,
+                None => return Ok(false),
+            };
+            // tokens cannot be used to manage tokens
+            if let Request::CreateToken { .. } | Request::RevokeToken { .. } | Request::Tokens = req
+            {
+                return Ok(false);
+            }
+            let project = required_role(conn, req)?.map(|(project, _)| project);
+            if !token.allows(req.kind(), project.as_ref())? {
+                return Ok(false);
+            }
+            match token.token.user_id {
+                Some(id) => Some(users::User::get_by_id(conn, id)?),
+                None => None,
+            }
+        }
+    };
+    let account = match account {
+        Some(account) if !account.user.admin => account,
+        _ => return Ok(true),
+    };
+    if let Request::CreateToken { .. } | Request::RevokeToken { .. } | Request::Tokens = req {
+        return Ok(true);
+    }
+    Ok(match required_role(conn, req)? {
+        Some((project, role)) => account
+            .role(conn, &project)?
+            .is_some_and(|user_role| user_role >= role),
+        None => false,
+    })
+}
+
+/// Returns the id of the account owning the tokens of a user, or `None`
+/// for the administrator
+fn tokens_owner(conn: &mut Conn, user: &User) -> Result<Option<i32>, Error> {
+    match user {
+        User::Account(name) => Ok(Some(users::User::get(conn, name)?.user.id)),
+        User::Admin => Ok(None),
+        User::Anonymous | User::Token(_) => Err(Error::AccessDenied),
     }
 }
 
@@ -150,7 +302,7 @@ pub fn handle_request_aux(
     user: &User,
     req: &requests::Request,
 ) -> Result<Response, Error> {
-    if !authorize_request(user, req) {
+    if !authorize_request(conn, user, req)? {
         return Err(Error::AccessDenied);
     }
     Ok(match req {
@@ -166,9 +318,17 @@ pub fn handle_request_aux(
         requests::Request::Project(project_handle, req) => {
             let project = Project::get(conn, &project_handle)?;
             match req {
+                requests::Project::Delete => project.delete()?,
                 requests::Project::Info => return Ok(Response::ProjectInfo(project.info(conn)?)),
                 requests::Project::Refresh => project.refresh(conn)?,
+                requests::Project::SetBuildLimits(decl) => project.set_build_limits(conn, decl)?,
                 requests::Project::SetDecl(decl) => project.set_decl(conn, decl)?,
+                requests::Project::SetNotifications(decl) => {
+                    project.set_notifications(conn, decl)?
+                }
+                requests::Project::SetRole { user, role } => {
+                    users::User::get(conn, user)?.set_role(conn, &project.project, *role)?
+                }
                 requests::Project::UpdateJobsets => project.update_jobsets(conn)?,
             };
             Response::Ok
@@ -180,7 +340,7 @@ pub fn handle_request_aux(
                     let evaluation_handle = jobset.evaluate(conn, *force)?;
                     Response::JobsetEvaluate(evaluation_handle)
                 }
-                requests::Jobset::Info => Response::JobsetInfo(jobset.info()),
+                requests::Jobset::Info => Response::JobsetInfo(jobset.info()?),
             }
         }
         requests::Request::Evaluation(evaluation_handle, req) => {
@@ -190,6 +350,10 @@ pub fn handle_request_aux(
                     evaluation.cancel();
                     Response::Ok
                 }
+                requests::Evaluation::Diff(base_handle) => {
+                    let base = Evaluation::get(conn, base_handle)?;
+                    Response::EvaluationDiff(evaluation.diff(conn, &base)?)
+                }
                 requests::Evaluation::Info => Response::EvaluationInfo(evaluation.info(conn)?),
             }
         }
@@ -206,7 +370,8 @@ pub fn handle_request_aux(
         requests::Request::Build(build_handle, req) => {
             let build = Build::get(conn, &build_handle)?;
             match req {
-                requests::Build::Info => Response::BuildInfo(build.info()),
+                requests::Build::Graph => Response::BuildGraph(build.graph(conn)?),
+                requests::Build::Info => Response::BuildInfo(build.info(conn)?),
             }
         }
         requests::Request::Action(action_handle, req) => {
@@ -218,24 +383,112 @@ pub fn handle_request_aux(
         requests::Request::Run(run_handle, req) => {
             let run = Run::get(conn, &run_handle)?;
             match req {
-                //requests::Run::Cancel => {
-                //    run.cancel();
-                //    Response::Ok
-                //}
-                requests::Run::Info => Response::RunInfo(run.info()),
+                requests::Run::Cancel => {
+                    run.cancel();
+                    Response::Ok
+                }
+                requests::Run::Info => Response::RunInfo(run.info(conn)?),
             }
         }
-        requests::Request::Login { password } => {
+        requests::Request::CreateUser { name, decl } => {
+            users::User::create(conn, name, decl)?;
+            Response::Ok
+        }
+        requests::Request::DeleteUser { name } => {
+            users::User::get(conn, name)?.delete(conn)?;
+            Response::Ok
+        }
+        requests::Request::Login {
+            username: None,
+            password,
+        } => {
             if verify_password(password.as_bytes()) {
-                Response::Ok
+                Response::User(Some(data::User::Admin))
             } else {
                 Err(Error::LoginError)?
             }
         }
+        requests::Request::Login {
+            username: Some(name),
+            password,
+        } => match users::User::get(conn, name) {
+            Ok(account) if account.verify_password(password.as_bytes()) => {
+                Response::User(Some(account.info(conn)?))
+            }
+            Ok(_) | Err(Error::UserNotFound(_)) => Err(Error::LoginError)?,
+            Err(e) => Err(e)?,
+        },
         requests::Request::User => Response::User(match user {
             User::Admin => Some(data::User::Admin),
+            User::Account(name) => match users::User::get(conn, name) {
+                Ok(account) => Some(account.info(conn)?),
+                Err(Error::UserNotFound(_)) => None,
+                Err(e) => Err(e)?,
+            },
             User::Anonymous => None,
+            User::Token(id) => match tokens::Token::get_by_id(conn, *id)? {
+                Some(token) => match token.token.user_id {
+                    Some(id) => Some(users::User::get_by_id(conn, id)?.info(conn)?),
+                    None => Some(data::User::Admin),
+                },
+                None => None,
+            },
         }),
+        requests::Request::CreateToken { name, decl } => {
+            let owner = tokens_owner(conn, user)?;
+            Response::Token(tokens::Token::create(conn, owner, name, decl)?)
+        }
+        requests::Request::RevokeToken { name } => {
+            let owner = tokens_owner(conn, user)?;
+            tokens::Token::get(conn, owner, name)?.delete(conn)?;
+            Response::Ok
+        }
+        requests::Request::Tokens => {
+            let owner = tokens_owner(conn, user)?;
+            Response::Tokens(
+                tokens::Token::list(conn, owner)?
+                    .iter()
+                    .map(tokens::Token::info)
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        requests::Request::CreateSubscription { name, decl } => {
+            subscriptions::Subscription::create(conn, name, decl)?;
+            Response::Ok
+        }
+        requests::Request::DeleteSubscription { name } => {
+            subscriptions::Subscription::get(conn, name)?.delete(conn)?;
+            Response::Ok
+        }
+        requests::Request::Subscriptions { project } => Response::Subscriptions(
+            subscriptions::Subscription::list(conn, project.as_ref())?
+                .iter()
+                .map(subscriptions::Subscription::info)
+                .collect::<Result<_, _>>()?,
+        ),
+        requests::Request::Deliveries { name } => {
+            Response::Deliveries(subscriptions::Subscription::get(conn, name)?.deliveries(conn)?)
+        }
+        requests::Request::Agent { name, req } => match req {
+            requests::Agent::Poll {
+                systems,
+                features,
+                capacity,
+                running,
+            } => Response::AgentPoll(agents::poll(name, systems, features, *capacity, running)),
+            requests::Agent::Log { build, records } => {
+                agents::log(name, build, records)?;
+                Response::Ok
+            }
+            requests::Agent::Progress { build, progress } => {
+                agents::progress(name, build, progress)?;
+                Response::Ok
+            }
+            requests::Agent::Finish { build, result } => {
+                agents::finish(name, build, *result)?;
+                Response::Ok
+            }
+        },
     })
 }
 
@@ -263,7 +516,9 @@ pub fn log_event(event: Event) {
     EVENT_LOGGER.log(event);
 }
 
-pub fn log(handle: handles::Log) -> Result<Option<impl Stream<Item = String>>, Error> {
+pub fn log(
+    handle: handles::Log,
+) -> Result<Option<impl Stream<Item = responses::LogRecord>>, Error> {
     let mut conn = POOL.get().unwrap();
     match handle {
         handles::Log::Evaluation(handle) => evaluations::Evaluation::get(&mut conn, &handle)?
@@ -276,6 +531,13 @@ pub fn log(handle: handles::Log) -> Result<Option<impl Stream<Item = String>>, E
     }
 }
 
+/// Moves the logs stored in the database to compressed files in `dir`, and
+/// returns their number. Typhon must not be running.
+pub fn migrate_logs(dir: std::path::PathBuf) -> Result<usize, Error> {
+    let storage = logs::storage::Filesystem::new(dir)?;
+    logs::storage::migrate(&mut POOL.get().unwrap(), &storage)
+}
+
 pub fn webhook(
     project_handle: handles::Project,
     input: actions::webhooks::Input,
@@ -314,10 +576,15 @@ pub async fn shutdown() {
     // exists no other similar assumption at the moment, but I chose to shut
     // down everything in sequence anyway to try to avoid future problems.
     eprintln!("Typhon is shutting down...");
+    scheduler::SCHEDULER.shutdown().await;
     build_manager::BUILDS.shutdown().await;
     RUNS.shutdown().await;
     TASKS.shutdown().await;
     LOGS.shutdown().await;
+    subscriptions::DISPATCHER.shutdown().await;
+    if let Some(notifier) = notifications::NOTIFIER.get() {
+        notifier.shutdown().await;
+    }
     EVENT_LOGGER.shutdown().await;
     eprintln!("Good bye!");
 }
@@ -341,10 +608,237 @@ fn pool() -> DbPool {
     pool
 }
 
-pub fn init(password: &String) {
+/// A pool holding a single connection to an empty in-memory database, for
+/// the tests
+#[cfg(test)]
+pub(crate) fn test_pool() -> DbPool {
+    let manager = diesel::r2d2::ConnectionManager::<SqliteConnection>::new(":memory:");
+    let pool = diesel::r2d2::Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(ConnectionCustomizer {}))
+        .build(manager)
+        .unwrap();
+    pool.get()
+        .unwrap()
+        .run_pending_migrations(MIGRATIONS)
+        .unwrap();
+    pool
+}
+
+/// A connection to an empty in-memory database, for the tests
+#[cfg(test)]
+pub(crate) fn test_conn() -> Conn {
+    test_pool().get().unwrap()
+}
+
+pub fn init(
+    password: &String,
+    eval: EvalSettings,
+    builds: BuildSettings,
+    logs: LogSettings,
+    smtp: Option<SmtpSettings>,
+) {
     let password = Box::leak(Box::new(password.clone()));
     let password = PasswordHash::new(password).expect("Unable to parse the password hash");
-    Settings::init(Settings { password });
+    Settings::init(Settings {
+        password,
+        eval,
+        builds,
+        logs,
+    });
     // Force database migrations
     let _ = once_cell::sync::Lazy::force(&POOL);
+    let mut conn = POOL.get().unwrap();
+    Run::cancel_stale(&mut conn)
+        .unwrap_or_else(|e| tracing::error!("failed to cancel stale runs: {:?}", e));
+    // Start the scheduler
+    let _ = once_cell::sync::Lazy::force(&scheduler::SCHEDULER);
+    // Start delivering events to subscriptions
+    let _ = once_cell::sync::Lazy::force(&subscriptions::DISPATCHER);
+    // Start sending email notifications
+    if let Some(smtp) = smtp {
+        notifications::init(smtp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::Role;
+    use requests::Request;
+
+    fn project(conn: &mut Conn, name: &str) -> models::Project {
+        diesel::insert_into(schema::projects::table)
+            .values(&models::NewProject {
+                flake: true,
+                key: "",
+                name,
+                url: "github:typhon-ci/typhon",
+            })
+            .get_result(conn)
+            .unwrap()
+    }
+
+    fn account(conn: &mut Conn, name: &str, admin: bool) -> User {
+        let decl = requests::UserDecl {
+            password: "password".to_string(),
+            admin,
+        };
+        users::User::create(conn, &name.to_string(), &decl).unwrap();
+        User::Account(name.to_string())
+    }
+
+    fn set_role(conn: &mut Conn, user: &User, project: &models::Project, role: Role) {
+        let User::Account(name) = user else {
+            unreachable!()
+        };
+        users::User::get(conn, name)
+            .unwrap()
+            .set_role(conn, project, Some(role))
+            .unwrap();
+    }
+
+    fn delete(project: &str) -> Request {
+        Request::Project(
+            handles::project(project.to_string()),
+            requests::Project::Delete,
+        )
+    }
+
+    fn evaluate(project: &str) -> Request {
+        Request::Jobset(
+            handles::jobset((project.to_string(), "main".to_string())),
+            requests::Jobset::Evaluate(false),
+        )
+    }
+
+    fn info(project: &str) -> Request {
+        Request::Project(
+            handles::project(project.to_string()),
+            requests::Project::Info,
+        )
+    }
+
+    #[test]
+    fn required_roles() {
+        let conn = &mut test_conn();
+        let role = |conn: &mut Conn, req| required_role(conn, &req).unwrap().map(|(_, r)| r);
+        assert_eq!(role(conn, delete("p")), Some(Role::Maintainer));
+        assert_eq!(role(conn, evaluate("p")), Some(Role::Operator));
+        assert_eq!(
+            role(
+                conn,
+                Request::Project(
+                    handles::project("p".to_string()),
+                    requests::Project::UpdateJobsets
+                )
+            ),
+            Some(Role::Operator),
+        );
+        assert_eq!(role(conn, info("p")), None);
+        assert_eq!(
+            role(
+                conn,
+                Request::DeleteUser {
+                    name: "nobody".to_string()
+                }
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn authorize_roles() {
+        let conn = &mut test_conn();
+        let p = project(conn, "p");
+        project(conn, "q");
+        let operator = account(conn, "operator", false);
+        set_role(conn, &operator, &p, Role::Operator);
+        let maintainer = account(conn, "maintainer", false);
+        set_role(conn, &maintainer, &p, Role::Maintainer);
+        let nobody = account(conn, "nobody", false);
+        let admin = account(conn, "admin", true);
+
+        let mut authorized =
+            |user: &User, req: Request| authorize_request(conn, user, &req).unwrap();
+        for user in [&User::Anonymous, &nobody, &operator, &maintainer, &admin] {
+            assert!(authorized(user, info("p")));
+        }
+        assert!(!authorized(&User::Anonymous, evaluate("p")));
+        assert!(!authorized(&nobody, evaluate("p")));
+        assert!(authorized(&operator, evaluate("p")));
+        assert!(!authorized(&operator, evaluate("q")));
+        assert!(!authorized(&operator, delete("p")));
+        assert!(authorized(&maintainer, evaluate("p")));
+        assert!(authorized(&maintainer, delete("p")));
+        assert!(!authorized(&maintainer, delete("q")));
+        assert!(authorized(&admin, delete("q")));
+        assert!(authorized(&User::Admin, delete("q")));
+        assert!(!authorized(
+            &maintainer,
+            Request::DeleteUser {
+                name: "nobody".to_string()
+            }
+        ));
+        assert!(authorized(
+            &admin,
+            Request::DeleteUser {
+                name: "nobody".to_string()
+            }
+        ));
+    }
+
+    fn token(conn: &mut Conn, owner: Option<&User>, decl: requests::TokenDecl) -> User {
+        let user_id = owner.map(|user| tokens_owner(conn, user).unwrap().unwrap());
+        let secret = tokens::Token::create(conn, user_id, &"token".to_string(), &decl).unwrap();
+        let token = tokens::Token::authenticate(conn, &secret).unwrap().unwrap();
+        User::Token(token.token.id)
+    }
+
+    #[test]
+    fn authorize_tokens() {
+        let conn = &mut test_conn();
+        let p = project(conn, "p");
+        project(conn, "q");
+        let operator = account(conn, "operator", false);
+        set_role(conn, &operator, &p, Role::Operator);
+
+        // a token grants at most the roles of its owner
+        let operator_token = token(
+            conn,
+            Some(&operator),
+            requests::TokenDecl {
+                project: None,
+                kinds: None,
+                expires: None,
+            },
+        );
+        // a token of the administrator restricted to a project and to
+        // evaluations
+        let admin_token = token(
+            conn,
+            None,
+            requests::TokenDecl {
+                project: Some(handles::project("p".to_string())),
+                kinds: Some(vec![requests::RequestKind::JobsetEvaluate]),
+                expires: None,
+            },
+        );
+
+        let mut authorized =
+            |user: &User, req: Request| authorize_request(conn, user, &req).unwrap();
+        assert!(authorized(&operator_token, evaluate("p")));
+        assert!(!authorized(&operator_token, evaluate("q")));
+        assert!(!authorized(&operator_token, delete("p")));
+        assert!(!authorized(&operator_token, Request::Tokens));
+        assert!(authorized(&admin_token, evaluate("p")));
+        assert!(!authorized(&admin_token, evaluate("q")));
+        assert!(!authorized(&admin_token, delete("p")));
+        assert!(!authorized(
+            &admin_token,
+            Request::DeleteUser {
+                name: "operator".to_string()
+            }
+        ));
+    }
 }
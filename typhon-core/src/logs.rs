@@ -1,6 +1,53 @@
+pub mod storage;
+
+use crate::RUNTIME;
+
+use typhon_types::responses::LogRecord;
+
+use futures_core::stream::Stream;
+use tokio::sync::mpsc;
+
+use std::path::PathBuf;
+
+/// Settings of the storage of logs
+#[derive(Clone, Debug, Default)]
+pub struct LogSettings {
+    /// Directory where the logs of finished tasks are stored as compressed
+    /// files. Logs are stored in the database if unset.
+    pub dir: Option<PathBuf>,
+}
+
+/// Serializes records as NDJSON, one record per line
+pub fn to_ndjson(records: &[LogRecord]) -> String {
+    records
+        .iter()
+        .map(|record| serde_json::to_string(record).unwrap() + "\n")
+        .collect()
+}
+
+/// Streams the records of a stored log, decoding them on a blocking thread
+pub fn stream(records: storage::Records) -> impl Stream<Item = LogRecord> {
+    let (sender, mut receiver) = mpsc::channel(1024);
+    RUNTIME.spawn_blocking(move || {
+        for record in records {
+            // the stream was dropped
+            if sender.blocking_send(record).is_err() {
+                break;
+            }
+        }
+    });
+    async_stream::stream! {
+        while let Some(record) = receiver.recv().await {
+            yield record;
+        }
+    }
+}
+
 pub mod live {
     use crate::RUNTIME;
 
+    use typhon_types::responses::LogRecord;
+
     use tokio::sync::mpsc;
     use tokio::sync::oneshot;
     use tokio::sync::watch;
@@ -11,18 +58,18 @@ pub mod live {
     enum Msg<Id> {
         Remove {
             id: Id,
-            dump_sender: oneshot::Sender<Option<String>>,
+            dump_sender: oneshot::Sender<Option<Vec<LogRecord>>>,
         },
         Init {
             id: Id,
         },
         Line {
             id: Id,
-            line: String,
+            line: LogRecord,
         },
         Listen {
             id: Id,
-            lines_sender: mpsc::UnboundedSender<String>,
+            lines_sender: mpsc::UnboundedSender<LogRecord>,
             not_found_sender: oneshot::Sender<bool>,
         },
         Shutdown,
@@ -42,22 +89,24 @@ pub mod live {
             let (sender, mut receiver) = mpsc::unbounded_channel();
             let (watch_send, watch) = watch::channel(());
             RUNTIME.spawn(async move {
-                type Listeners = Vec<mpsc::UnboundedSender<String>>;
-                let mut state: HashMap<Id, (Vec<String>, Listeners)> = HashMap::new();
+                type Listeners = Vec<mpsc::UnboundedSender<LogRecord>>;
+                let mut state: HashMap<Id, (Vec<LogRecord>, Listeners)> = HashMap::new();
                 while let Some(msg) = receiver.recv().await {
                     match msg {
                         Msg::Remove { id, dump_sender } => {
                             dump_sender
-                                .send(state.remove(&id).map(|(lines, _)| lines.join("\n")))
+                                .send(state.remove(&id).map(|(lines, _)| lines))
                                 .unwrap();
                         }
                         Msg::Init { id } => {
                             state.insert(id.clone(), (Vec::new(), Vec::new()));
                         }
                         Msg::Line { id, line } => {
-                            let (lines, ref mut listeners) = state
-                                .get_mut(&id)
-                                .expect("log channels need to be initialized before sending lines");
+                            // lines can still arrive for the log of a task that
+                            // just finished, they are dropped
+                            let Some((lines, ref mut listeners)) = state.get_mut(&id) else {
+                                continue;
+                            };
                             lines.push(line.clone());
 
                             *listeners = listeners
@@ -89,7 +138,7 @@ pub mod live {
             Self { sender, watch }
         }
 
-        pub fn remove(&self, id: &Id) -> Option<String> {
+        pub fn remove(&self, id: &Id) -> Option<Vec<LogRecord>> {
             let (dump_sender, remove_receiver) = oneshot::channel();
             self.sender
                 .send(Msg::Remove {
@@ -104,7 +153,10 @@ pub mod live {
             self.sender.send(Msg::Init { id: id.clone() }).unwrap();
         }
 
-        pub fn listen(&self, id: &Id) -> Option<impl futures_core::stream::Stream<Item = String>> {
+        pub fn listen(
+            &self,
+            id: &Id,
+        ) -> Option<impl futures_core::stream::Stream<Item = LogRecord>> {
             let (lines_sender, mut lines_receiver) = mpsc::unbounded_channel();
             let (not_found_sender, not_found_receiver) = oneshot::channel();
             self.sender
@@ -126,7 +178,7 @@ pub mod live {
             }
         }
 
-        pub fn send_line(&self, id: &Id, line: String) {
+        pub fn send_line(&self, id: &Id, line: LogRecord) {
             self.sender
                 .send(Msg::Line {
                     id: id.clone(),
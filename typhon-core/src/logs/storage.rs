@@ -0,0 +1,351 @@
+//! Storage of the logs of finished tasks. Live logs are kept in memory by
+//! [`super::live::Cache`], and handed to the storage when their task ends.
+
+use crate::error::Error;
+use crate::models;
+use crate::schema;
+use crate::Conn;
+
+use typhon_types::responses::LogRecord;
+
+use diesel::prelude::*;
+use once_cell::sync::Lazy;
+use time::OffsetDateTime;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+
+/// The records of a stored log, decoded as they are consumed
+pub type Records = Box<dyn Iterator<Item = LogRecord> + Send>;
+
+/// A place where the logs of finished tasks are stored
+pub trait Storage: Send + Sync {
+    /// Stores the log `log_id` of a task that just finished
+    fn write(&self, conn: &mut Conn, log_id: i32, records: &[LogRecord]) -> Result<(), Error>;
+
+    /// Reads the log `log_id`, which is empty if it was never written
+    fn read(&self, conn: &mut Conn, log_id: i32) -> Result<Records, Error>;
+
+    /// Deletes the log `log_id`, whose task is being deleted
+    fn delete(&self, conn: &mut Conn, log_id: i32) -> Result<(), Error>;
+}
+
+/// Reads a line of a log stored as plain text, which encoded phases as
+/// `@nix` lines
+fn parse_legacy(time: OffsetDateTime, line: &str) -> LogRecord {
+    let phase = line
+        .strip_prefix("@nix ")
+        .and_then(|json| serde_json::from_str::<serde_json::Value>(json).ok())
+        .filter(|json| json["action"] == "setPhase")
+        .and_then(|json| json["phase"].as_str().map(String::from));
+    let record = match phase {
+        Some(phase) => LogRecord::phase(phase),
+        None => LogRecord::output(line),
+    };
+    LogRecord { time, ..record }
+}
+
+/// Stores logs as NDJSON in the `logs` table
+pub struct Database;
+
+impl Storage for Database {
+    fn write(&self, conn: &mut Conn, log_id: i32, records: &[LogRecord]) -> Result<(), Error> {
+        diesel::update(schema::logs::table.find(log_id))
+            .set(schema::logs::records.eq(super::to_ndjson(records)))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    fn read(&self, conn: &mut Conn, log_id: i32) -> Result<Records, Error> {
+        let log = schema::logs::table
+            .find(log_id)
+            .first::<models::Log>(conn)?;
+        if let Some(records) = log.records {
+            let records: Vec<LogRecord> = records
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect();
+            return Ok(Box::new(records.into_iter()));
+        }
+        let Some(stderr) = log.stderr else {
+            return Ok(Box::new(std::iter::empty()));
+        };
+        // logs stored as plain text have no timestamps, their lines are dated
+        // with the start of their task
+        let time = schema::tasks::table
+            .filter(schema::tasks::log_id.eq(log_id))
+            .select(schema::tasks::time_started)
+            .first::<Option<i64>>(conn)
+            .optional()?
+            .flatten()
+            .map(OffsetDateTime::from_unix_timestamp)
+            .transpose()?
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        let records: Vec<LogRecord> = stderr
+            .split('\n')
+            .map(|line| parse_legacy(time, line))
+            .collect();
+        Ok(Box::new(records.into_iter()))
+    }
+
+    fn delete(&self, conn: &mut Conn, log_id: i32) -> Result<(), Error> {
+        diesel::update(schema::logs::table.find(log_id))
+            .set((
+                schema::logs::records.eq(None::<String>),
+                schema::logs::stderr.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+/// Stores logs as zstd-compressed NDJSON files, one per log. Logs missing
+/// from the directory are read from the database, where they were stored
+/// before they are moved with [`migrate`].
+pub struct Filesystem {
+    pub dir: PathBuf,
+}
+
+impl Filesystem {
+    /// Opens the directory of the logs, creating it if needed
+    pub fn new(dir: PathBuf) -> Result<Self, Error> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, log_id: i32) -> PathBuf {
+        self.dir.join(format!("{}.ndjson.zst", log_id))
+    }
+}
+
+impl Storage for Filesystem {
+    fn write(&self, _conn: &mut Conn, log_id: i32, records: &[LogRecord]) -> Result<(), Error> {
+        // the log is written aside then renamed, so that it is never read
+        // half-written
+        let path = self.path(log_id);
+        let tmp = path.with_extension("tmp");
+        let mut encoder = zstd::Encoder::new(File::create(&tmp)?, 0)?;
+        for record in records {
+            writeln!(encoder, "{}", serde_json::to_string(record).unwrap())?;
+        }
+        encoder.finish()?.sync_all()?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn read(&self, conn: &mut Conn, log_id: i32) -> Result<Records, Error> {
+        let file = match File::open(self.path(log_id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Database.read(conn, log_id),
+            Err(e) => return Err(e.into()),
+        };
+        let lines = BufReader::new(zstd::Decoder::new(file)?).lines();
+        Ok(Box::new(
+            lines
+                .map_while(Result::ok)
+                .filter_map(|line| serde_json::from_str(&line).ok()),
+        ))
+    }
+
+    fn delete(&self, _conn: &mut Conn, log_id: i32) -> Result<(), Error> {
+        match std::fs::remove_file(self.path(log_id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+static STORAGE: Lazy<Box<dyn Storage>> = Lazy::new(|| match &crate::Settings::get().logs.dir {
+    Some(dir) => {
+        Box::new(Filesystem::new(dir.clone()).expect("failed to create the directory of the logs"))
+    }
+    None => Box::new(Database),
+});
+
+/// The storage of the logs, as set in the settings
+pub fn storage() -> &'static dyn Storage {
+    STORAGE.as_ref()
+}
+
+/// Moves the logs stored in the database to `storage`, and returns their
+/// number. The database is vacuumed afterwards to give the space back.
+pub fn migrate(conn: &mut Conn, storage: &Filesystem) -> Result<usize, Error> {
+    let ids = schema::logs::table
+        .filter(
+            schema::logs::records
+                .is_not_null()
+                .or(schema::logs::stderr.is_not_null()),
+        )
+        .select(schema::logs::id)
+        .load::<i32>(conn)?;
+    for &log_id in &ids {
+        let records: Vec<LogRecord> = Database.read(conn, log_id)?.collect();
+        storage.write(conn, log_id, &records)?;
+        diesel::update(schema::logs::table.find(log_id))
+            .set((
+                schema::logs::records.eq(None::<String>),
+                schema::logs::stderr.eq(None::<String>),
+            ))
+            .execute(conn)?;
+    }
+    diesel::sql_query("VACUUM").execute(conn)?;
+    Ok(ids.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use typhon_types::responses::LogStream;
+
+    fn new_log(conn: &mut Conn, stderr: Option<&str>) -> i32 {
+        diesel::insert_into(schema::logs::table)
+            .values(schema::logs::stderr.eq(stderr))
+            .get_result::<models::Log>(conn)
+            .unwrap()
+            .id
+    }
+
+    #[test]
+    fn legacy_lines() {
+        let time = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        assert_eq!(
+            parse_legacy(time, "unpacking sources"),
+            LogRecord {
+                time,
+                ..LogRecord::output("unpacking sources")
+            },
+        );
+        assert_eq!(
+            parse_legacy(time, r#"@nix {"action":"setPhase","phase":"buildPhase"}"#),
+            LogRecord {
+                time,
+                ..LogRecord::phase("buildPhase")
+            },
+        );
+        // other Nix messages and malformed ones are kept as they are
+        for line in [r#"@nix {"action":"msg","msg":"hello"}"#, "@nix {"] {
+            let record = parse_legacy(time, line);
+            assert_eq!(record.stream, LogStream::Output);
+            assert_eq!(record.text, line);
+        }
+    }
+
+    #[test]
+    fn legacy_log_without_task() {
+        let mut conn = crate::test_conn();
+        let log_id = new_log(
+            &mut conn,
+            Some("unpacking sources\n@nix {\"action\":\"setPhase\",\"phase\":\"buildPhase\"}"),
+        );
+        let records: Vec<LogRecord> = Database.read(&mut conn, log_id).unwrap().collect();
+        let time = OffsetDateTime::UNIX_EPOCH;
+        assert_eq!(
+            records,
+            vec![
+                LogRecord {
+                    time,
+                    ..LogRecord::output("unpacking sources")
+                },
+                LogRecord {
+                    time,
+                    ..LogRecord::phase("buildPhase")
+                },
+            ],
+        );
+    }
+
+    /// A storage in a fresh directory, removed when dropped
+    struct TempStorage(Filesystem);
+
+    impl TempStorage {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("typhon-logs-{}", uuid::Uuid::now_v7()));
+            Self(Filesystem::new(dir).unwrap())
+        }
+    }
+
+    impl Drop for TempStorage {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.dir);
+        }
+    }
+
+    fn records() -> Vec<LogRecord> {
+        let time = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        vec![
+            LogRecord {
+                time,
+                ..LogRecord::phase("buildPhase")
+            },
+            LogRecord {
+                time,
+                phase: Some("buildPhase".to_string()),
+                activity: Some(42),
+                ..LogRecord::output("compiling")
+            },
+            LogRecord {
+                time,
+                ..LogRecord::typhon("build finished")
+            },
+        ]
+    }
+
+    #[test]
+    fn filesystem_round_trip() {
+        let storage = TempStorage::new();
+        let mut conn = crate::test_conn();
+        let log_id = new_log(&mut conn, None);
+        storage.0.write(&mut conn, log_id, &records()).unwrap();
+
+        let path = storage.0.path(log_id);
+        assert!(!path.with_extension("tmp").exists());
+        let lines: Vec<LogRecord> =
+            std::io::read_to_string(zstd::Decoder::new(File::open(&path).unwrap()).unwrap())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+        assert_eq!(lines, records());
+        let read: Vec<LogRecord> = storage.0.read(&mut conn, log_id).unwrap().collect();
+        assert_eq!(read, records());
+
+        storage.0.delete(&mut conn, log_id).unwrap();
+        assert!(!path.exists());
+        assert_eq!(storage.0.read(&mut conn, log_id).unwrap().count(), 0);
+        // deleting a missing log is not an error
+        storage.0.delete(&mut conn, log_id).unwrap();
+    }
+
+    #[test]
+    fn migrate_moves_logs_out_of_the_database() {
+        let storage = TempStorage::new();
+        let mut conn = crate::test_conn();
+        let ndjson = new_log(&mut conn, None);
+        Database.write(&mut conn, ndjson, &records()).unwrap();
+        let legacy = new_log(&mut conn, Some("unpacking sources"));
+        let empty = new_log(&mut conn, None);
+        let legacy_records: Vec<LogRecord> = Database.read(&mut conn, legacy).unwrap().collect();
+
+        // logs not migrated yet are read from the database
+        let read: Vec<LogRecord> = storage.0.read(&mut conn, ndjson).unwrap().collect();
+        assert_eq!(read, records());
+        let read: Vec<LogRecord> = storage.0.read(&mut conn, legacy).unwrap().collect();
+        assert_eq!(read, legacy_records);
+
+        assert_eq!(migrate(&mut conn, &storage.0).unwrap(), 2);
+        let log = schema::logs::table
+            .find(ndjson)
+            .first::<models::Log>(&mut conn)
+            .unwrap();
+        assert_eq!((log.records, log.stderr), (None, None));
+        assert!(!storage.0.path(empty).exists());
+
+        let read: Vec<LogRecord> = storage.0.read(&mut conn, ndjson).unwrap().collect();
+        assert_eq!(read, records());
+        let read: Vec<LogRecord> = storage.0.read(&mut conn, legacy).unwrap().collect();
+        assert_eq!(read, legacy_records);
+        assert_eq!(migrate(&mut conn, &storage.0).unwrap(), 0);
+    }
+}
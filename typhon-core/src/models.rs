@@ -1,12 +1,18 @@
 use crate::schema::actions;
+use crate::schema::build_dependencies;
 use crate::schema::builds;
+use crate::schema::deliveries;
 use crate::schema::evaluations;
 use crate::schema::jobs;
 use crate::schema::jobsets;
 use crate::schema::logs;
 use crate::schema::projects;
+use crate::schema::roles;
 use crate::schema::runs;
+use crate::schema::subscriptions;
 use crate::schema::tasks;
+use crate::schema::tokens;
+use crate::schema::users;
 
 use diesel::prelude::*;
 
@@ -14,6 +20,8 @@ use diesel::prelude::*;
 #[diesel(table_name = projects)]
 pub struct Project {
     pub actions_path: Option<String>,
+    pub build_max_silent: Option<i64>,
+    pub build_timeout: Option<i64>,
     pub description: String,
     pub flake: bool,
     pub homepage: String,
@@ -21,6 +29,8 @@ pub struct Project {
     pub key: String,
     pub last_refresh_task_id: Option<i32>,
     pub name: String,
+    pub notify_emails: String,
+    pub notify_maintainers: bool,
     pub title: String,
     pub url: String,
     pub url_locked: String,
@@ -42,7 +52,12 @@ pub struct Jobset {
     pub flake: bool,
     pub id: i32,
     pub name: String,
+    pub poll: Option<i32>,
+    pub priority: i32,
     pub project_id: i32,
+    pub schedule: Option<String>,
+    pub time_next_evaluation: Option<i64>,
+    pub time_next_poll: Option<i64>,
     pub url: String,
 }
 
@@ -51,7 +66,12 @@ pub struct Jobset {
 pub struct NewJobset<'a> {
     pub flake: bool,
     pub name: &'a str,
+    pub poll: Option<i32>,
+    pub priority: i32,
     pub project_id: i32,
+    pub schedule: Option<&'a str>,
+    pub time_next_evaluation: Option<i64>,
+    pub time_next_poll: Option<i64>,
     pub url: &'a str,
 }
 
@@ -90,11 +110,15 @@ pub struct NewEvaluation<'a> {
 pub struct Job {
     pub dist: bool,
     pub drv: String,
+    pub eval_error: Option<String>,
     pub evaluation_id: i32,
     pub id: i32,
+    pub max_silent: Option<i64>,
     pub name: String,
     pub out: String,
+    pub outputs: String,
     pub system: String,
+    pub timeout: Option<i64>,
     pub tries: i32,
 }
 
@@ -103,10 +127,14 @@ pub struct Job {
 pub struct NewJob<'a> {
     pub dist: bool,
     pub drv: &'a str,
+    pub eval_error: Option<&'a str>,
     pub evaluation_id: i32,
+    pub max_silent: Option<i64>,
     pub name: &'a str,
     pub out: &'a str,
+    pub outputs: &'a str,
     pub system: &'a str,
+    pub timeout: Option<i64>,
     pub tries: i32,
 }
 
@@ -114,13 +142,16 @@ pub struct NewJob<'a> {
 #[diesel(table_name = logs)]
 pub struct Log {
     pub id: i32,
+    /// Records of the log as NDJSON
+    pub records: Option<String>,
+    /// Log of a task that finished before logs were stored as records
     pub stderr: Option<String>,
 }
 
 #[derive(Debug, Insertable)]
 #[diesel(table_name = logs)]
 pub struct NewLog<'a> {
-    pub stderr: Option<&'a str>, // FIXME
+    pub records: Option<&'a str>,
 }
 
 #[derive(Debug, Queryable, Clone, Identifiable, Selectable)]
@@ -145,8 +176,11 @@ pub struct NewTask {
 #[diesel(table_name = builds)]
 #[diesel(belongs_to(Task))]
 pub struct Build {
+    pub attempt: i32,
     pub drv: String,
+    pub failed_dependency_id: Option<i32>,
     pub id: i32,
+    pub previous_id: Option<i32>,
     pub task_id: i32,
     pub time_created: i64,
     pub uuid: String,
@@ -155,12 +189,21 @@ pub struct Build {
 #[derive(Debug, Insertable)]
 #[diesel(table_name = builds)]
 pub struct NewBuild<'a> {
+    pub attempt: i32,
     pub drv: &'a str,
+    pub previous_id: Option<i32>,
     pub task_id: i32,
     pub time_created: i64,
     pub uuid: &'a str,
 }
 
+#[derive(Debug, Insertable)]
+#[diesel(table_name = build_dependencies)]
+pub struct NewBuildDependency {
+    pub build_id: i32,
+    pub dependency_id: i32,
+}
+
 #[derive(Debug, Queryable, Clone, Identifiable, Selectable)]
 #[diesel(table_name = actions)]
 #[diesel(belongs_to(Project))]
@@ -199,7 +242,11 @@ pub struct Run {
     pub id: i32,
     pub job_id: i32,
     pub num: i32,
+    pub previous_status: Option<i32>,
+    pub status: i32,
     pub time_created: i64,
+    pub time_finished: Option<i64>,
+    pub time_started: Option<i64>,
 }
 
 #[derive(Debug, Insertable)]
@@ -209,3 +256,112 @@ pub struct NewRun {
     pub num: i32,
     pub time_created: i64,
 }
+
+#[derive(Debug, Queryable, Clone, Identifiable, Selectable)]
+#[diesel(table_name = users)]
+pub struct User {
+    pub admin: bool,
+    pub id: i32,
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = users)]
+pub struct NewUser<'a> {
+    pub admin: bool,
+    pub name: &'a str,
+    pub password: &'a str,
+}
+
+#[derive(Debug, Queryable, Clone, Identifiable, Selectable)]
+#[diesel(table_name = roles)]
+#[diesel(belongs_to(Project))]
+#[diesel(belongs_to(User))]
+pub struct Role {
+    pub id: i32,
+    pub project_id: i32,
+    pub role: i32,
+    pub user_id: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = roles)]
+pub struct NewRole {
+    pub project_id: i32,
+    pub role: i32,
+    pub user_id: i32,
+}
+
+#[derive(Debug, Queryable, Clone, Identifiable, Selectable)]
+#[diesel(table_name = tokens)]
+#[diesel(belongs_to(Project))]
+#[diesel(belongs_to(User))]
+pub struct Token {
+    pub hash: String,
+    pub id: i32,
+    pub kinds: Option<String>,
+    pub name: String,
+    pub project_id: Option<i32>,
+    pub time_created: i64,
+    pub time_expires: Option<i64>,
+    pub time_last_used: Option<i64>,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = tokens)]
+pub struct NewToken<'a> {
+    pub hash: &'a str,
+    pub kinds: Option<&'a str>,
+    pub name: &'a str,
+    pub project_id: Option<i32>,
+    pub time_created: i64,
+    pub time_expires: Option<i64>,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Debug, Queryable, Clone, Identifiable, Selectable)]
+#[diesel(table_name = subscriptions)]
+#[diesel(belongs_to(Project))]
+pub struct Subscription {
+    pub id: i32,
+    pub name: String,
+    pub project_id: Option<i32>,
+    pub secret: String,
+    pub time_created: i64,
+    pub url: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = subscriptions)]
+pub struct NewSubscription<'a> {
+    pub name: &'a str,
+    pub project_id: Option<i32>,
+    pub secret: &'a str,
+    pub time_created: i64,
+    pub url: &'a str,
+}
+
+#[derive(Debug, Queryable, Clone, Identifiable, Selectable)]
+#[diesel(table_name = deliveries)]
+#[diesel(belongs_to(Subscription))]
+pub struct Delivery {
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub event: String,
+    pub id: i32,
+    pub response_code: Option<i32>,
+    pub status: i32,
+    pub subscription_id: i32,
+    pub time_created: i64,
+    pub time_finished: Option<i64>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = deliveries)]
+pub struct NewDelivery<'a> {
+    pub event: &'a str,
+    pub subscription_id: i32,
+    pub time_created: i64,
+}
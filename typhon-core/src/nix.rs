@@ -1,3 +1,5 @@
+use typhon_types::responses::{BuildProgress, LogRecord};
+
 use async_trait::async_trait;
 use serde_json::Value;
 use tokio::io::AsyncBufReadExt;
@@ -6,7 +8,10 @@ use tokio::io::BufReader;
 use tokio::process::Command;
 use tokio::sync::mpsc;
 
-use std::{collections::HashMap, ffi::OsStr, process::Stdio};
+use std::{collections::HashMap, ffi::OsStr, process::Stdio, time::Duration};
+
+pub mod drv;
+pub mod store;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
@@ -31,7 +36,15 @@ pub enum Error {
         stderr: String,
     },
     ExpectedDrvGotAttrset(Expr),
-    BuildFailed,
+    BuildFailed {
+        /// Whether the failure is likely to go away by building again
+        transient: bool,
+    },
+    TimedOut,
+    ReadDrv {
+        path: String,
+        error: String,
+    },
 }
 
 impl std::fmt::Display for Error {
@@ -58,7 +71,6 @@ const RUNNING_NIX_FAILED: &str = "command Nix failed to run";
 trait CommandExtTrait {
     fn nix<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(args: I) -> Self;
     async fn sync_stdout(&mut self) -> Result<String, Error>;
-    async fn sync_stderr(&mut self) -> Result<String, Error>;
 }
 
 #[async_trait]
@@ -83,63 +95,254 @@ impl CommandExtTrait for Command {
             Ok(stdout)
         }
     }
-    async fn sync_stderr(&mut self) -> Result<String, Error> {
-        let nix_output = self.output().await.expect(RUNNING_NIX_FAILED);
-        let stdout = String::from_utf8(nix_output.stdout)?;
-        let stderr = String::from_utf8(nix_output.stderr)?;
+}
 
-        if !nix_output.status.success() {
-            Err(Error::NixCommand {
-                cmd: format!("{:?}", self),
-                stdout,
-                stderr,
-            })
-        } else {
-            Ok(stderr)
+/// Error messages of Nix caused by a failure of the machine or of the network
+/// rather than by the derivation itself. They are only looked for in the
+/// first line of the errors, since Nix appends the last lines of the log of
+/// a failed builder, which may contain anything.
+const TRANSIENT_ERRORS: &[&str] = &[
+    // the builder was killed, e.g. by the OOM killer
+    "failed due to signal 9",
+    "Cannot allocate memory",
+    // a substituter could not be reached
+    "unable to download",
+    "Couldn't resolve host name",
+    "Connection refused",
+    "Connection reset by peer",
+    "Timeout was reached",
+    "HTTP error 5",
+];
+
+/// Tells whether a failed build is worth retrying, from the exit status of
+/// Nix and the error messages it reported
+fn is_transient(status: std::process::ExitStatus, errors: &[String]) -> bool {
+    match status.code() {
+        // Nix itself was killed by a signal
+        None => true,
+        Some(_) => errors.iter().any(|error| {
+            let message = error.lines().next().unwrap_or_default();
+            TRANSIENT_ERRORS
+                .iter()
+                .any(|pattern| message.contains(pattern))
+        }),
+    }
+}
+
+/// Follows the progress of a build from the messages of Nix
+#[derive(Default)]
+struct ProgressTracker {
+    activities: HashMap<messages::Id, messages::ActivityType>,
+    /// Bytes downloaded and to download by the running file transfers
+    transfers: HashMap<messages::Id, (u64, u64)>,
+    /// Bytes downloaded by the finished file transfers
+    transferred: u64,
+    /// Bytes to download, as announced by Nix
+    to_download: u64,
+    progress: BuildProgress,
+}
+
+impl ProgressTracker {
+    /// Updates the progress with a message of Nix, and tells whether it
+    /// changed. `drv_id` is the activity of the build of the derivation
+    /// itself.
+    fn update(&mut self, drv_id: Option<messages::Id>, message: &messages::Message) -> bool {
+        use messages::*;
+        let before = self.progress.clone();
+        match &message.body {
+            MessageBody::Start { .. } => {
+                self.activities.insert(message.id, ActivityType::Build);
+            }
+            MessageBody::StartActivity { activity } => {
+                self.activities.insert(message.id, *activity);
+            }
+            MessageBody::Phase { phase } if drv_id == Some(message.id) => {
+                self.progress.phase = Some(phase.clone());
+            }
+            MessageBody::Progress { done, expected } => match self.activities.get(&message.id) {
+                Some(ActivityType::Builds) => {
+                    self.progress.built = *done;
+                    self.progress.to_build = *expected;
+                }
+                Some(ActivityType::FileTransfer) => {
+                    self.transfers.insert(message.id, (*done, *expected));
+                }
+                _ => (),
+            },
+            MessageBody::SetExpected {
+                activity: ActivityType::FileTransfer,
+                expected,
+            } => self.to_download = *expected,
+            MessageBody::Stop => {
+                if let Some((done, _)) = self.transfers.remove(&message.id) {
+                    self.transferred += done;
+                }
+                self.activities.remove(&message.id);
+            }
+            _ => (),
         }
+        let (running, expected) = self
+            .transfers
+            .values()
+            .fold((0, 0), |(running, expected), (done, to_do)| {
+                (running + done, expected + to_do)
+            });
+        self.progress.downloaded = self.transferred + running;
+        self.progress.to_download = self.to_download.max(self.transferred + expected);
+        self.progress != before
     }
 }
 
+/// The log of a derivation that Nix builds while building another one
+#[derive(Debug)]
+pub struct DependencyLog {
+    pub drv: DrvPath,
+    /// The lines of the log, followed by `None` once Nix is done with the
+    /// derivation. The channel closes without `None` if Nix is interrupted.
+    pub lines: mpsc::UnboundedReceiver<Option<LogRecord>>,
+}
+
+/// Forwards the build logs and the progress of a derivation and returns the
+/// error messages of Nix. The logs of the other derivations Nix builds are
+/// sent to `dependencies` if given. Fails if Nix stays silent for longer than
+/// `max_silent`.
 async fn handle_logs(
     path: &DrvPath,
-    buffer: BufReader<tokio::process::ChildStderr>,
-    sender: mpsc::UnboundedSender<String>,
-) {
+    buffer: impl tokio::io::AsyncBufRead + Unpin,
+    sender: mpsc::UnboundedSender<LogRecord>,
+    progress: Option<mpsc::UnboundedSender<BuildProgress>>,
+    dependencies: Option<mpsc::UnboundedSender<DependencyLog>>,
+    max_silent: Option<Duration>,
+) -> Result<Vec<String>, Error> {
     let mut lines = buffer.lines();
     use messages::*;
     let mut drv_id: Option<Id> = None;
-    while let Some(line) = lines.next_line().await.unwrap() {
-        if let Some(Message { id, body }) = parse(line) {
+    let mut dependency_logs: HashMap<Id, mpsc::UnboundedSender<Option<LogRecord>>> = HashMap::new();
+    let mut phases: HashMap<Id, String> = HashMap::new();
+    let mut errors = Vec::new();
+    let mut tracker = ProgressTracker::default();
+    loop {
+        let line = match max_silent {
+            Some(max_silent) => tokio::time::timeout(max_silent, lines.next_line())
+                .await
+                .map_err(|_| Error::TimedOut)?,
+            None => lines.next_line().await,
+        };
+        let Some(line) = line.unwrap() else {
+            break;
+        };
+        if let Some(message) = parse(line) {
+            if tracker.update(drv_id, &message) {
+                if let Some(progress) = &progress {
+                    let _ = progress.send(tracker.progress.clone());
+                }
+            }
+            let Message { id, body } = message;
             match body {
                 MessageBody::Start { drv } => {
-                    if *path == DrvPath::new(&drv) {
+                    let drv = DrvPath::new(&drv);
+                    if *path == drv {
                         drv_id = Some(id);
+                    } else if let Some(dependencies) = &dependencies {
+                        let (log, lines) = mpsc::unbounded_channel();
+                        let _ = dependencies.send(DependencyLog { drv, lines });
+                        dependency_logs.insert(id, log);
                     }
                 }
                 MessageBody::Phase { phase } => {
+                    phases.insert(id, phase.clone());
+                    let line = LogRecord {
+                        activity: Some(id),
+                        ..LogRecord::phase(phase)
+                    };
                     if drv_id == Some(id) {
-                        let line = format!(
-                            "@nix {} \"action\": \"setPhase\", \"phase\": \"{}\" {}",
-                            "{", phase, "}"
-                        );
                         let _ = sender.send(line);
+                    } else if let Some(log) = dependency_logs.get(&id) {
+                        let _ = log.send(Some(line));
                     }
                 }
                 MessageBody::BuildLogLine { line } => {
+                    let line = LogRecord {
+                        phase: phases.get(&id).cloned(),
+                        activity: Some(id),
+                        ..LogRecord::output(line)
+                    };
                     if drv_id == Some(id) {
                         let _ = sender.send(line);
+                    } else if let Some(log) = dependency_logs.get(&id) {
+                        let _ = log.send(Some(line));
+                    }
+                }
+                MessageBody::Error { msg } => errors.push(msg),
+                MessageBody::Stop => {
+                    phases.remove(&id);
+                    if let Some(log) = dependency_logs.remove(&id) {
+                        let _ = log.send(None);
                     }
                 }
                 _ => (),
             }
         }
     }
+    Ok(errors)
+}
+
+/// Limits on the duration of a build. As with Nix, a limit of zero means no
+/// limit.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildLimits {
+    /// Maximum duration of the whole build
+    pub timeout: Option<Duration>,
+    /// Maximum duration without any output from Nix
+    pub max_silent: Option<Duration>,
+}
+
+impl BuildLimits {
+    /// Returns the limits without the ones set to zero
+    fn enforced(&self) -> Self {
+        Self {
+            timeout: self.timeout.filter(|t| !t.is_zero()),
+            max_silent: self.max_silent.filter(|t| !t.is_zero()),
+        }
+    }
+}
+
+/// Runs a build, failing with [`Error::TimedOut`] if it takes longer than
+/// `timeout`
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    build: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, build)
+            .await
+            .unwrap_or(Err(Error::TimedOut)),
+        None => build.await,
+    }
 }
 
-/// Runs `nix build` on a derivation path
+/// Runs `nix build` on a derivation path, sending its progress to `progress`
+/// and the logs of the dependencies Nix builds to `dependencies` if given.
+/// Nix is killed and [`Error::TimedOut`] is returned if the build exceeds its
+/// limits.
 pub async fn build(
     path: &DrvPath,
-    sender: mpsc::UnboundedSender<String>,
+    sender: mpsc::UnboundedSender<LogRecord>,
+    progress: Option<mpsc::UnboundedSender<BuildProgress>>,
+    dependencies: Option<mpsc::UnboundedSender<DependencyLog>>,
+    limits: &BuildLimits,
+) -> Result<DrvOutputs, Error> {
+    let limits = limits.enforced();
+    let build = build_aux(path, sender, progress, dependencies, limits.max_silent);
+    with_timeout(limits.timeout, build).await
+}
+
+async fn build_aux(
+    path: &DrvPath,
+    sender: mpsc::UnboundedSender<LogRecord>,
+    progress: Option<mpsc::UnboundedSender<BuildProgress>>,
+    dependencies: Option<mpsc::UnboundedSender<DependencyLog>>,
+    max_silent: Option<Duration>,
 ) -> Result<DrvOutputs, Error> {
     let mut child = Command::nix([
         "build",
@@ -154,7 +357,16 @@ pub async fn build(
     .stderr(Stdio::piped())
     .spawn()
     .expect(RUNNING_NIX_FAILED);
-    handle_logs(path, BufReader::new(child.stderr.take().unwrap()), sender).await;
+    // on error, `child` is dropped, which kills Nix
+    let errors = handle_logs(
+        path,
+        BufReader::new(child.stderr.take().unwrap()),
+        sender,
+        progress,
+        dependencies,
+        max_silent,
+    )
+    .await?;
     let mut stdout = String::new();
     child
         .stdout
@@ -163,12 +375,8 @@ pub async fn build(
         .read_to_string(&mut stdout)
         .await
         .unwrap();
-    let success = child
-        .wait()
-        .await
-        .map(|status| status.success())
-        .unwrap_or(false);
-    if success {
+    let status = child.wait().await.expect(RUNNING_NIX_FAILED);
+    if status.success() {
         if let [obj] = serde_json::from_str::<Value>(stdout.as_str())
             .unwrap()
             .as_array()
@@ -207,8 +415,34 @@ pub async fn build(
             })
         }
     } else {
-        Err(Error::BuildFailed)
+        Err(Error::BuildFailed {
+            transient: is_transient(status, &errors),
+        })
+    }
+}
+
+/// A store to copy paths from or to
+#[derive(Clone, Copy, Debug)]
+pub enum Copy<'a> {
+    From(&'a str),
+    To(&'a str),
+}
+
+/// Runs `nix copy` on store paths and their closures. With `derivation` set,
+/// `.drv` paths are copied themselves rather than their outputs.
+pub async fn copy(paths: &[String], store: Copy<'_>, derivation: bool) -> Result<(), Error> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let mut cmd = match store {
+        Copy::From(url) => Command::nix(["copy", "--from", url]),
+        Copy::To(url) => Command::nix(["copy", "--to", url]),
+    };
+    if derivation {
+        cmd.arg("--derivation");
     }
+    cmd.args(paths).sync_stdout().await?;
+    Ok(())
 }
 
 /// Runs `nix show-derivation [expr]` and parse its stdout as JSON.
@@ -295,11 +529,37 @@ impl Derivation {
             ),
         })
     }
+
+    /// Returns the path of the main output of the derivation: `out` if it
+    /// exists, the first output by name otherwise
+    pub fn main_output(&self) -> Option<&String> {
+        self.outputs.get("out").or_else(|| {
+            self.outputs
+                .iter()
+                .min_by_key(|(name, _)| *name)
+                .map(|(_, path)| path)
+        })
+    }
 }
 
 /// Here, we assume [expr] evaluates to a derivation, not an attrset
-/// of derivations.
+/// of derivations. Paths to `.drv` files are parsed directly.
 pub async fn derivation(expr: Expr) -> Result<Derivation, Error> {
+    if let Expr::Path(path) = &expr {
+        if path.ends_with(".drv") {
+            let path = DrvPath::new(path);
+            let drv = drv::read(&path)?;
+            return Ok(Derivation {
+                path,
+                outputs: drv
+                    .outputs
+                    .iter()
+                    .filter(|(_, output)| !output.path.is_empty())
+                    .map(|(name, output)| (name.clone(), output.path.clone()))
+                    .collect(),
+            });
+        }
+    }
     let json = derivation_json(&expr).await?;
     if let [(path, derivation)] = *json
         .as_object()
@@ -335,33 +595,169 @@ pub async fn eval(url: &str, path: &str, flake: bool) -> Result<serde_json::Valu
     )?)
 }
 
-pub type NewJobs = HashMap<(String, String), (Derivation, bool)>;
-
-pub async fn eval_jobs(url: &str, flake: bool) -> Result<NewJobs, Error> {
-    let json = eval(url, "typhonJobs", flake).await?;
-    let mut jobs: HashMap<(String, String), (Derivation, bool)> = HashMap::new();
-    for system in json.as_object().unwrap().keys() {
-        for name in json[system].as_object().unwrap().keys() {
-            jobs.insert(
-                (system.clone(), name.clone()),
-                (
-                    derivation(Expr::Flake {
-                        flake,
-                        url: url.to_string(),
-                        path: format!("typhonJobs.{system}.{name}"),
-                    })
-                    .await?,
-                    eval(
-                        url,
-                        &format!("typhonJobs.{system}.{name}.passthru.typhonDist"),
-                        flake,
-                    )
-                    .await
-                    .map(|json| json.as_bool().unwrap_or(false))
-                    .unwrap_or(false),
-                ),
-            );
+/// A job that was successfully evaluated
+#[derive(Clone, Debug)]
+pub struct EvaluatedJob {
+    pub derivation: Derivation,
+    /// The `typhonDist` flag of the job
+    pub dist: bool,
+    /// The `meta.timeout` of the job, in seconds
+    pub timeout: Option<u64>,
+    /// The `meta.maxSilent` of the job, in seconds
+    pub max_silent: Option<u64>,
+}
+
+/// The jobs of an evaluation, or the error raised while evaluating them
+pub type NewJobs = HashMap<(String, String), Result<EvaluatedJob, String>>;
+
+/// The output of `nix-eval-jobs`
+#[derive(Clone, Debug, Default)]
+pub struct EvalJobs {
+    pub jobs: NewJobs,
+    /// The errors that are not the error of a job: the errors raised while
+    /// evaluating a whole system, and the lines of the output that could not
+    /// be read
+    pub errors: Vec<String>,
+}
+
+/// Settings of the evaluator
+#[derive(Clone, Debug)]
+pub struct EvalSettings {
+    /// Number of workers evaluating jobs in parallel
+    pub workers: usize,
+    /// Memory size in MiB after which a worker is restarted
+    pub max_memory_size: usize,
+}
+
+/// A line of the output of `nix-eval-jobs`
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EvalJobsLine {
+    attr_path: Vec<String>,
+    drv_path: Option<String>,
+    #[serde(default)]
+    outputs: HashMap<String, Option<String>>,
+    error: Option<String>,
+    extra_value: Option<Value>,
+}
+
+/// Reads the `typhonDist` flag and the build limits of each job while
+/// `nix-eval-jobs` evaluates it, so that they end up in the `extraValue` field
+/// of the output
+const APPLY_EXTRA: &str = "job: {
+  dist = job.passthru.typhonDist or false;
+  timeout = job.meta.timeout or null;
+  maxSilent = job.meta.maxSilent or null;
+}";
+
+/// Records a line of the output of `nix-eval-jobs`
+fn read_eval_jobs_line(line: &str, jobs: &mut EvalJobs) {
+    let line: EvalJobsLine = match serde_json::from_str(line) {
+        Ok(line) => line,
+        Err(e) => {
+            jobs.errors.push(format!(
+                "Unreadable output of nix-eval-jobs ({}): {}",
+                e, line
+            ));
+            return;
         }
+    };
+    let (system, name) = match line.attr_path.as_slice() {
+        [system, name] => (system.clone(), name.clone()),
+        path => {
+            jobs.errors.push(format!(
+                "Attribute typhonJobs.{} failed to evaluate:\n{}",
+                path.join("."),
+                line.error.unwrap_or("not a derivation".to_string()),
+            ));
+            return;
+        }
+    };
+    let job = match (line.error, line.drv_path) {
+        (None, Some(drv_path)) => {
+            let derivation = Derivation {
+                path: DrvPath::new(&drv_path),
+                outputs: line
+                    .outputs
+                    .into_iter()
+                    .filter_map(|(name, path)| Some((name, path?)))
+                    .collect(),
+            };
+            let extra = line.extra_value.unwrap_or_default();
+            Ok(EvaluatedJob {
+                derivation,
+                dist: extra["dist"].as_bool().unwrap_or(false),
+                timeout: extra["timeout"].as_u64(),
+                max_silent: extra["maxSilent"].as_u64(),
+            })
+        }
+        (e, _) => Err(e.unwrap_or("no derivation".to_string())),
+    };
+    jobs.jobs.insert((system, name), job);
+}
+
+/// Evaluates all the jobs of a project with `nix-eval-jobs`, which streams
+/// one JSON line per job from a pool of workers. A job that fails to evaluate
+/// does not fail the whole evaluation: its error is recorded instead, and so
+/// are the errors that are not the error of a job.
+pub async fn eval_jobs(url: &str, flake: bool) -> Result<EvalJobs, Error> {
+    let settings = &crate::Settings::get().eval;
+    let mut cmd = Command::new("nix-eval-jobs");
+    cmd.kill_on_drop(true)
+        .args(["--force-recurse", "--apply", APPLY_EXTRA])
+        .args(["--workers", &settings.workers.to_string()])
+        .args(["--max-memory-size", &settings.max_memory_size.to_string()]);
+    if flake {
+        cmd.args(["--flake", &format!("{}#typhonJobs", url)]);
+    } else {
+        cmd.args([
+            "--flake",
+            &format!("{}#typhonJobs", env!("TYPHON_FLAKE")),
+            "--override-input",
+            "x",
+            url,
+        ]);
+    }
+    let command_error = |cmd: &Command, e: std::io::Error| Error::NixCommand {
+        cmd: format!("{:?}", cmd),
+        stdout: String::new(),
+        stderr: e.to_string(),
+    };
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| command_error(&cmd, e))?;
+    let stderr = {
+        let mut stderr = child.stderr.take().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = String::new();
+            let _ = stderr.read_to_string(&mut buffer).await;
+            buffer
+        })
+    };
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut jobs = EvalJobs::default();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| command_error(&cmd, e))?
+    {
+        read_eval_jobs_line(&line, &mut jobs);
+    }
+    let success = child
+        .wait()
+        .await
+        .map(|status| status.success())
+        .unwrap_or(false);
+    let stderr = stderr.await.unwrap_or_default();
+    if !success {
+        return Err(Error::NixCommand {
+            cmd: format!("{:?}", cmd),
+            stdout: String::new(),
+            stderr,
+        });
     }
     Ok(jobs)
 }
@@ -434,84 +830,40 @@ pub fn lock(url: &String) -> Result<String, Error> {
     Ok(stdout)
 }
 
+/// Returns the store paths a derivation needs to be built: the outputs of its
+/// input derivations and its input sources
 pub fn dependencies(drv: &String) -> Result<Vec<String>, Error> {
-    use std::process::Command;
-
     let mut dependencies: Vec<String> = Vec::new();
 
-    let mut cmd = Command::new("nix");
-    cmd.args(["derivation", "show", &drv]);
-    let output = cmd.output().unwrap();
-    let stdout = String::from_utf8(output.stdout)?;
-    let stderr = String::from_utf8(output.stderr)?;
-    if !output.status.success() {
-        return Err(Error::NixCommand {
-            cmd: format!("{:?}", cmd),
-            stdout,
-            stderr,
-        });
-    }
-
-    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
-
-    let input_drvs = json[&drv]["inputDrvs"].as_object().unwrap();
-    for (drv, json) in input_drvs {
-        let outputs = json["outputs"].as_array().unwrap();
-        let mut cmd = Command::new("nix");
-        cmd.args(["derivation", "show", &drv]);
-        let output = cmd.output().unwrap();
-        let stdout = String::from_utf8(output.stdout)?;
-        let stderr = String::from_utf8(output.stderr)?;
-        if !output.status.success() {
-            return Err(Error::NixCommand {
-                cmd: format!("{:?}", cmd),
-                stdout,
-                stderr,
-            });
-        }
-        let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let derivation = drv::read(&DrvPath::new(drv))?;
+    for (input, outputs) in &derivation.input_drvs {
+        let input = drv::read(&DrvPath::new(input))?;
         for output in outputs {
-            dependencies.push(
-                json[&drv]["outputs"][output.as_str().unwrap()]["path"]
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-            )
+            let path = input
+                .outputs
+                .get(output)
+                .ok_or_else(|| Error::UnexpectedOutput {
+                    context: format!(
+                        "While reading the dependencies of {}: no output {}",
+                        drv, output
+                    ),
+                })?;
+            dependencies.push(path.path.clone());
         }
     }
-
-    let input_srcs = json[drv]["inputSrcs"].as_array().unwrap();
-    for src in input_srcs {
-        dependencies.push(src.as_str().unwrap().to_string());
-    }
+    dependencies.extend(derivation.input_srcs.iter().cloned());
 
     Ok(dependencies)
 }
 
-pub async fn is_cached(drv: &DrvPath) -> Result<bool, Error> {
-    let output = Command::nix(["build", "--dry-run"])
-        .arg(format!("{}^*", drv))
-        .sync_stderr()
-        .await?;
-    Ok(!output.contains(&drv.to_string()))
-}
-
-pub async fn is_built(drv: &DrvPath) -> Result<bool, Error> {
-    let output = Command::nix(["build", "--dry-run"])
-        .arg(format!("{}^*", drv))
-        .sync_stderr()
-        .await?;
-    Ok(output.is_empty())
-}
-
 /// This module parses https://github.com/NixOS/nix/blob/7474a90db69813d051ab1bef35c7d0ab958d9ccd/src/libutil/logging.hh
 mod messages {
     use serde_repr::*;
 
     /// Comes from https://github.com/NixOS/nix/blob/7474a90db69813d051ab1bef35c7d0ab958d9ccd/src/libutil/logging.hh
-    #[derive(Serialize_repr, Deserialize_repr, Debug, Clone)]
+    #[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     #[repr(u8)]
-    enum ActivityType {
+    pub enum ActivityType {
         Unknown = 0,
         CopyPath = 100,
         FileTransfer = 101,
@@ -553,9 +905,32 @@ mod messages {
     }
     #[derive(Debug, Clone)]
     pub enum MessageBody {
-        Start { drv: String },
-        Phase { phase: String },
-        BuildLogLine { line: String },
+        Start {
+            drv: String,
+        },
+        /// Start of an activity other than a build
+        StartActivity {
+            activity: ActivityType,
+        },
+        Phase {
+            phase: String,
+        },
+        BuildLogLine {
+            line: String,
+        },
+        Progress {
+            done: u64,
+            expected: u64,
+        },
+        /// Number of things to do for the activities of a type, e.g. bytes
+        /// to download for [`ActivityType::FileTransfer`]
+        SetExpected {
+            activity: ActivityType,
+            expected: u64,
+        },
+        Error {
+            msg: String,
+        },
         Stop,
     }
 
@@ -564,6 +939,7 @@ mod messages {
         let typ = o["type"].clone();
         let fields = o["fields"].clone();
         let first_field = serde_json::from_value::<String>(fields[0].clone()).ok();
+        let int_field = |i: usize| fields[i].as_u64();
         let id = o["id"].clone().as_u64();
         let body = match o["action"].as_str()? {
             "result" => {
@@ -573,6 +949,14 @@ mod messages {
                     ResultType::SetPhase => MessageBody::Phase {
                         phase: first_field?,
                     },
+                    ResultType::Progress => MessageBody::Progress {
+                        done: int_field(0)?,
+                        expected: int_field(1)?,
+                    },
+                    ResultType::SetExpected => MessageBody::SetExpected {
+                        activity: serde_json::from_value::<ActivityType>(fields[0].clone()).ok()?,
+                        expected: int_field(1)?,
+                    },
                     _ => None?,
                 }
             }
@@ -580,12 +964,188 @@ mod messages {
                 let kind = serde_json::from_value::<ActivityType>(typ).ok()?;
                 match kind {
                     ActivityType::Build => MessageBody::Start { drv: first_field? },
-                    _ => None?,
+                    activity => MessageBody::StartActivity { activity },
                 }
             }
             "stop" => MessageBody::Stop,
+            // messages are not attached to an activity
+            "msg" if o["level"].as_u64() == Some(0) => {
+                return Some(Message {
+                    id: 0,
+                    body: MessageBody::Error {
+                        msg: o["msg"].as_str()?.to_string(),
+                    },
+                })
+            }
             _ => None?,
         };
         Some(Message { id: id?, body })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncWriteExt;
+
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exit_code(code: i32) -> std::process::ExitStatus {
+        std::process::ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn transient_errors() {
+        let errors = |errors: &[&str]| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        // Nix was killed
+        assert!(is_transient(
+            std::process::ExitStatus::from_raw(9),
+            &errors(&[])
+        ));
+        assert!(is_transient(
+            exit_code(1),
+            &errors(&[
+                "error: unable to download 'https://cache.nixos.org/nar/x.nar.xz': \
+                       Couldn't resolve host name (6)"
+            ])
+        ));
+        assert!(is_transient(
+            exit_code(100),
+            &errors(&[
+                "error: builder for '/nix/store/x-hello.drv' failed due to signal 9 (Killed)"
+            ])
+        ));
+        assert!(!is_transient(
+            exit_code(100),
+            &errors(&["error: builder for '/nix/store/x-hello.drv' failed with exit code 1"])
+        ));
+        // the tail of the log of the builder is not an error of Nix
+        assert!(!is_transient(
+            exit_code(100),
+            &errors(&[
+                "error: builder for '/nix/store/x-hello.drv' failed with exit code 1;\n\
+                       last 2 log lines:\n\
+                       > running tests\n\
+                       > connect: Connection refused"
+            ])
+        ));
+    }
+
+    #[test]
+    fn eval_jobs_lines() {
+        let mut jobs = EvalJobs::default();
+        for line in [
+            r#"{"attrPath":["x86_64-linux","hello"],"drvPath":"/nix/store/00000000000000000000000000000000-hello.drv","outputs":{"out":"/nix/store/00000000000000000000000000000000-hello"},"extraValue":{"dist":true,"timeout":60,"maxSilent":null}}"#,
+            r#"{"attrPath":["x86_64-linux","broken"],"error":"error: assertion failed"}"#,
+            r#"{"attrPath":["aarch64-linux"],"error":"error: infinite recursion encountered"}"#,
+            "not json",
+        ] {
+            read_eval_jobs_line(line, &mut jobs);
+        }
+
+        assert_eq!(jobs.jobs.len(), 2);
+        let hello = jobs.jobs[&("x86_64-linux".to_string(), "hello".to_string())]
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            hello.derivation.path,
+            DrvPath::new("/nix/store/00000000000000000000000000000000-hello.drv")
+        );
+        assert!(hello.dist);
+        assert_eq!(hello.timeout, Some(60));
+        assert_eq!(hello.max_silent, None);
+        assert_eq!(
+            jobs.jobs[&("x86_64-linux".to_string(), "broken".to_string())]
+                .as_ref()
+                .unwrap_err(),
+            "error: assertion failed"
+        );
+
+        assert_eq!(jobs.errors.len(), 2);
+        assert_eq!(
+            jobs.errors[0],
+            "Attribute typhonJobs.aarch64-linux failed to evaluate:\n\
+             error: infinite recursion encountered"
+        );
+        assert!(jobs.errors[1].starts_with("Unreadable output of nix-eval-jobs"));
+        assert!(jobs.errors[1].ends_with(": not json"));
+    }
+
+    #[test]
+    fn zero_is_no_limit() {
+        let limits = BuildLimits {
+            timeout: Some(Duration::ZERO),
+            max_silent: Some(Duration::from_secs(60)),
+        };
+        assert_eq!(
+            limits.enforced(),
+            BuildLimits {
+                timeout: None,
+                max_silent: Some(Duration::from_secs(60)),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn build_timeout() {
+        let slow = tokio::time::sleep(Duration::from_secs(60));
+        assert_eq!(
+            with_timeout(Some(Duration::from_millis(10)), async {
+                slow.await;
+                Ok(())
+            })
+            .await,
+            Err(Error::TimedOut)
+        );
+        assert_eq!(with_timeout(None, async { Ok(1) }).await, Ok(1));
+        assert_eq!(
+            with_timeout(Some(Duration::from_secs(60)), async { Ok(1) }).await,
+            Ok(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn build_max_silent() {
+        let path = DrvPath::new("/nix/store/00000000000000000000000000000000-hello.drv");
+        let error = r#"@nix {"action":"msg","level":0,"msg":"error: boom"}"#;
+
+        // Nix prints an error, then stays silent
+        let (mut write, read) = tokio::io::duplex(1024);
+        write
+            .write_all(format!("{}\n", error).as_bytes())
+            .await
+            .unwrap();
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let res = handle_logs(
+            &path,
+            BufReader::new(read),
+            sender,
+            None,
+            None,
+            Some(Duration::from_millis(10)),
+        )
+        .await;
+        assert_eq!(res, Err(Error::TimedOut));
+
+        // Nix exits after printing an error
+        drop(write);
+        let (mut write, read) = tokio::io::duplex(1024);
+        write
+            .write_all(format!("{}\n", error).as_bytes())
+            .await
+            .unwrap();
+        drop(write);
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let res = handle_logs(
+            &path,
+            BufReader::new(read),
+            sender,
+            None,
+            None,
+            Some(Duration::from_secs(60)),
+        )
+        .await;
+        assert_eq!(res, Ok(vec!["error: boom".to_string()]));
+    }
+}
@@ -0,0 +1,295 @@
+//! Parser of the ATerm serialization of derivations, i.e. the `.drv` files of
+//! the Nix store. Reading them directly is much cheaper than spawning `nix
+//! derivation show`.
+
+use super::{DrvPath, Error};
+
+use once_cell::sync::Lazy;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Number of parsed derivations kept in memory
+const CACHE_SIZE: usize = 4096;
+
+/// An output of a derivation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DrvOutput {
+    /// The store path of the output, empty for content-addressed derivations
+    pub path: String,
+    pub hash_algo: String,
+    pub hash: String,
+}
+
+/// A derivation, as stored in a `.drv` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Drv {
+    pub outputs: BTreeMap<String, DrvOutput>,
+    /// The derivations this one depends on, with the outputs it uses
+    pub input_drvs: BTreeMap<String, Vec<String>>,
+    pub input_srcs: Vec<String>,
+    pub system: String,
+    pub builder: String,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, expected: &str) -> String {
+        format!("expected {} at offset {}", expected, self.pos)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.input[self.pos..].starts_with(token.as_bytes()) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", token)))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("`\"`")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    let c = self
+                        .input
+                        .get(self.pos + 1)
+                        .copied()
+                        .ok_or_else(|| self.error("an escaped character"))?;
+                    bytes.push(match c {
+                        b'n' => b'\n',
+                        b'r' => b'\r',
+                        b't' => b'\t',
+                        c => c,
+                    });
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    bytes.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("a UTF-8 string"))
+    }
+
+    /// Parses a comma-separated list of items between brackets
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        self.expect("[")?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                _ => return Err(self.error("`,` or `]`")),
+            }
+        }
+    }
+
+    fn pair<A, B>(
+        &mut self,
+        first: impl FnOnce(&mut Self) -> Result<A, String>,
+        second: impl FnOnce(&mut Self) -> Result<B, String>,
+    ) -> Result<(A, B), String> {
+        self.expect("(")?;
+        let a = first(self)?;
+        self.expect(",")?;
+        let b = second(self)?;
+        self.expect(")")?;
+        Ok((a, b))
+    }
+
+    fn derivation(&mut self) -> Result<Drv, String> {
+        self.expect("Derive(")?;
+        let outputs = self.list(|p| {
+            p.expect("(")?;
+            let name = p.string()?;
+            p.expect(",")?;
+            let path = p.string()?;
+            p.expect(",")?;
+            let hash_algo = p.string()?;
+            p.expect(",")?;
+            let hash = p.string()?;
+            p.expect(")")?;
+            Ok((
+                name,
+                DrvOutput {
+                    path,
+                    hash_algo,
+                    hash,
+                },
+            ))
+        })?;
+        self.expect(",")?;
+        let input_drvs = self.list(|p| p.pair(Self::string, |p| p.list(Self::string)))?;
+        self.expect(",")?;
+        let input_srcs = self.list(Self::string)?;
+        self.expect(",")?;
+        let system = self.string()?;
+        self.expect(",")?;
+        let builder = self.string()?;
+        self.expect(",")?;
+        let args = self.list(Self::string)?;
+        self.expect(",")?;
+        let env = self.list(|p| p.pair(Self::string, Self::string))?;
+        self.expect(")")?;
+        if self.pos != self.input.len() {
+            return Err(self.error("the end of the derivation"));
+        }
+        Ok(Drv {
+            outputs: outputs.into_iter().collect(),
+            input_drvs: input_drvs.into_iter().collect(),
+            input_srcs,
+            system,
+            builder,
+            args,
+            env: env.into_iter().collect(),
+        })
+    }
+}
+
+/// Parses the contents of a `.drv` file
+pub fn parse(input: &str) -> Result<Drv, String> {
+    Parser {
+        input: input.as_bytes(),
+        pos: 0,
+    }
+    .derivation()
+}
+
+#[derive(Default)]
+struct Cache {
+    drvs: HashMap<DrvPath, Arc<Drv>>,
+    order: VecDeque<DrvPath>,
+}
+
+/// Store derivations never change, so parsed ones can be kept as long as
+/// there is room for them
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(|| Mutex::new(Cache::default()));
+
+/// Reads and parses a derivation of the store, or returns it from the cache
+pub fn read(path: &DrvPath) -> Result<Arc<Drv>, Error> {
+    if let Some(drv) = CACHE.lock().unwrap().drvs.get(path) {
+        return Ok(drv.clone());
+    }
+    let contents = std::fs::read_to_string(path.to_string()).map_err(|e| Error::ReadDrv {
+        path: path.to_string(),
+        error: e.to_string(),
+    })?;
+    let drv = Arc::new(parse(&contents).map_err(|e| Error::UnexpectedOutput {
+        context: format!("While parsing the derivation {}: {}", path, e),
+    })?);
+    let mut cache = CACHE.lock().unwrap();
+    if cache.drvs.insert(path.clone(), drv.clone()).is_none() {
+        cache.order.push_back(path.clone());
+        if cache.order.len() > CACHE_SIZE {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.drvs.remove(&oldest);
+            }
+        }
+    }
+    Ok(drv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The derivation of GNU hello, with several outputs and escapes in its
+    /// environment
+    const HELLO: &str = r#"Derive([("man","/nix/store/6ygb1a4hlrw3zpv7cb8wjq1mkaz3kkrl-hello-2.12.1-man","",""),("out","/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1","","")],[("/nix/store/6xhhj5q7fi8jgnqiv9vjsfhr0jsr0b5l-hello-2.12.1.tar.gz.drv",["out"]),("/nix/store/k6fh4wv1d8bdyh7vh6bnp3mbcws6kimv-bash-5.2-p15.drv",["out"]),("/nix/store/x8zvnvl5jdcvrb0lg1z1v4shfnr8q4gx-stdenv-linux.drv",["out"])],["/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"],"x86_64-linux","/nix/store/5lr5n3qa4day8l1ivbwlcby2nknczqkq-bash-5.2-p15/bin/bash",["-e","/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"],[("builder","/nix/store/5lr5n3qa4day8l1ivbwlcby2nknczqkq-bash-5.2-p15/bin/bash"),("doInstallCheck","1"),("man","/nix/store/6ygb1a4hlrw3zpv7cb8wjq1mkaz3kkrl-hello-2.12.1-man"),("name","hello-2.12.1"),("out","/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1"),("outputs","out man"),("postInstallCheck","stat \"${!outputBin}/bin/hello\"\n"),("src","/nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz"),("stdenv","/nix/store/c8dj9mr6kps8jx5kl4h5hzlzfjq1xqka-stdenv-linux"),("system","x86_64-linux"),("win","C:\\hello.exe")])"#;
+
+    /// A content-addressed derivation, whose output path is only known once
+    /// it is built
+    const CA: &str = r#"Derive([("out","","r:sha256","")],[],[],"x86_64-linux","/bin/sh",["-c","echo hi > $out"],[("__contentAddressed","1"),("builder","/bin/sh"),("name","hi"),("out","/1rz4g4znpzjwh1xymhjpm42vipw92pr73vdgl6xs1hycac8kf2n9"),("outputHashAlgo","sha256"),("outputHashMode","recursive"),("system","x86_64-linux")])"#;
+
+    #[test]
+    fn parse_multiple_outputs() {
+        let drv = parse(HELLO).unwrap();
+        assert_eq!(drv.outputs.keys().collect::<Vec<_>>(), vec!["man", "out"],);
+        assert_eq!(
+            drv.outputs["out"].path,
+            "/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1",
+        );
+        assert_eq!(drv.input_drvs.len(), 3);
+        assert_eq!(
+            drv.input_drvs["/nix/store/k6fh4wv1d8bdyh7vh6bnp3mbcws6kimv-bash-5.2-p15.drv"],
+            vec!["out"],
+        );
+        assert_eq!(drv.system, "x86_64-linux");
+        assert_eq!(drv.args.len(), 2);
+    }
+
+    #[test]
+    fn parse_escapes() {
+        let drv = parse(HELLO).unwrap();
+        assert_eq!(
+            drv.env["postInstallCheck"],
+            "stat \"${!outputBin}/bin/hello\"\n",
+        );
+        assert_eq!(drv.env["win"], "C:\\hello.exe");
+    }
+
+    #[test]
+    fn parse_content_addressed() {
+        let drv = parse(CA).unwrap();
+        assert_eq!(
+            drv.outputs["out"],
+            DrvOutput {
+                path: String::new(),
+                hash_algo: "r:sha256".to_string(),
+                hash: String::new(),
+            },
+        );
+        assert!(drv.input_drvs.is_empty());
+        assert!(drv.input_srcs.is_empty());
+    }
+
+    #[test]
+    fn parse_empty_lists() {
+        let drv = parse(r#"Derive([],[],[],"","",[],[])"#).unwrap();
+        assert!(drv.outputs.is_empty());
+        assert!(drv.args.is_empty());
+        assert!(drv.env.is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_trailing_garbage() {
+        assert!(parse(&format!("{}x", CA)).is_err());
+        assert!(parse(&format!("{}\n", CA)).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_truncated_input() {
+        assert!(parse(&CA[..CA.len() - 1]).is_err());
+        assert!(parse(r#"Derive([("out","/nix/store/a"#).is_err());
+    }
+}
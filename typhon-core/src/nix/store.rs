@@ -0,0 +1,288 @@
+//! Batched queries on the Nix store. Queries made concurrently are merged, so
+//! that a few Nix invocations answer all of them at once.
+
+use super::{CommandExtTrait, Error};
+use crate::RUNTIME;
+
+use once_cell::sync::Lazy;
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot, OnceCell};
+use tokio::task::JoinSet;
+
+use std::collections::{HashMap, HashSet};
+
+/// Maximum number of paths passed to a single `nix-store` invocation
+const CHUNK_SIZE: usize = 512;
+
+/// The status of a store path
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PathStatus {
+    /// The path is present in the local store
+    pub valid: bool,
+    /// The path can be fetched from a substituter
+    pub substitutable: bool,
+}
+
+impl PathStatus {
+    /// The path is present or can be fetched without building it
+    pub fn available(&self) -> bool {
+        self.valid || self.substitutable
+    }
+}
+
+struct Query {
+    paths: Vec<String>,
+    reply: oneshot::Sender<Result<HashMap<String, PathStatus>, Error>>,
+}
+
+async fn nix_store(args: &[&str], paths: &[String]) -> Result<String, Error> {
+    let mut cmd = Command::new("nix-store");
+    cmd.kill_on_drop(true).args(args).args(paths);
+    let output = cmd.output().await.expect("command nix-store failed to run");
+    let stdout = String::from_utf8(output.stdout)?;
+    let stderr = String::from_utf8(output.stderr)?;
+    if !output.status.success() {
+        return Err(Error::NixCommand {
+            cmd: format!("{:?}", cmd),
+            stdout,
+            stderr,
+        });
+    }
+    Ok(stdout)
+}
+
+/// Returns the paths that are not valid in the local store
+async fn invalid(paths: &[String]) -> Result<HashSet<String>, Error> {
+    let mut invalid = HashSet::new();
+    for chunk in paths.chunks(CHUNK_SIZE) {
+        let stdout = nix_store(&["--check-validity", "--print-invalid"], chunk).await?;
+        invalid.extend(stdout.lines().map(String::from));
+    }
+    Ok(invalid)
+}
+
+/// The substituters configured for the local store
+static SUBSTITUTERS: OnceCell<Vec<String>> = OnceCell::const_new();
+
+async fn substituters() -> Result<&'static Vec<String>, Error> {
+    SUBSTITUTERS
+        .get_or_try_init(|| async {
+            let stdout = Command::nix(["show-config", "--json"])
+                .sync_stdout()
+                .await?;
+            let config: serde_json::Value = serde_json::from_str(&stdout)?;
+            Ok(config["substituters"]["value"]
+                .as_array()
+                .map(|substituters| {
+                    substituters
+                        .iter()
+                        .filter_map(|substituter| substituter.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default())
+        })
+        .await
+}
+
+/// Maximum number of single paths queried from a substituter at once, when a
+/// query of several paths failed
+const MAX_PATH_QUERIES: usize = 8;
+
+/// Returns the paths that a store has, from the output of `nix path-info
+/// --json`, which lists missing paths as `null` since Nix 2.19 and with
+/// `"valid": false` before
+fn parse_path_info(stdout: &str) -> Result<HashSet<String>, Error> {
+    let present = match serde_json::from_str(stdout)? {
+        serde_json::Value::Object(infos) => infos
+            .into_iter()
+            .filter(|(_, info)| !info.is_null())
+            .map(|(path, _)| path)
+            .collect(),
+        serde_json::Value::Array(infos) => infos
+            .into_iter()
+            .filter(|info| info["valid"] != serde_json::Value::Bool(false))
+            .filter_map(|info| info["path"].as_str().map(String::from))
+            .collect(),
+        _ => HashSet::new(),
+    };
+    Ok(present)
+}
+
+/// Returns the paths of `paths` that `store` has
+async fn path_info(store: &str, paths: &[String]) -> Result<HashSet<String>, Error> {
+    let stdout = Command::nix(["path-info", "--json", "--store", store])
+        .args(paths)
+        .sync_stdout()
+        .await?;
+    parse_path_info(&stdout)
+}
+
+/// Returns the paths of `paths` that `store` has, querying them one by one,
+/// at most [`MAX_PATH_QUERIES`] at a time. Paths whose query fails are
+/// considered missing.
+async fn path_info_each(store: &'static str, paths: &[String]) -> HashSet<String> {
+    let mut present = HashSet::new();
+    let mut queries = JoinSet::new();
+    for path in paths {
+        if queries.len() >= MAX_PATH_QUERIES {
+            if let Some(Ok(found)) = queries.join_next().await {
+                present.extend(found);
+            }
+        }
+        let path = path.clone();
+        queries.spawn(async move {
+            path_info(store, std::slice::from_ref(&path))
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::debug!("failed to query {} from {}: {}", path, store, e);
+                    HashSet::new()
+                })
+        });
+    }
+    while let Some(found) = queries.join_next().await {
+        if let Ok(found) = found {
+            present.extend(found);
+        }
+    }
+    present
+}
+
+/// Returns the paths that a substituter can provide. Older versions of Nix
+/// fail on missing paths instead of reporting them, so the paths of a failed
+/// query are asked one by one.
+async fn substitutable(paths: &[String]) -> Result<HashSet<String>, Error> {
+    let mut substitutable = HashSet::new();
+    for store in substituters().await? {
+        let missing: Vec<String> = paths
+            .iter()
+            .filter(|path| !substitutable.contains(*path))
+            .cloned()
+            .collect();
+        for chunk in missing.chunks(CHUNK_SIZE) {
+            match path_info(store, chunk).await {
+                Ok(present) => substitutable.extend(present),
+                Err(_) => substitutable.extend(path_info_each(store, chunk).await),
+            }
+        }
+    }
+    Ok(substitutable)
+}
+
+async fn answer(paths: &HashSet<String>) -> Result<HashMap<String, PathStatus>, Error> {
+    let paths: Vec<String> = paths.iter().cloned().collect();
+    let invalid_paths: Vec<String> = invalid(&paths).await?.into_iter().collect();
+    let substitutable = if invalid_paths.is_empty() {
+        HashSet::new()
+    } else {
+        substitutable(&invalid_paths).await?
+    };
+    let invalid_paths: HashSet<String> = invalid_paths.into_iter().collect();
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let status = PathStatus {
+                valid: !invalid_paths.contains(&path),
+                substitutable: substitutable.contains(&path),
+            };
+            (path, status)
+        })
+        .collect())
+}
+
+static QUERIES: Lazy<mpsc::UnboundedSender<Query>> = Lazy::new(|| {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Query>();
+    RUNTIME.spawn(async move {
+        while let Some(query) = receiver.recv().await {
+            // the queries made while the previous batch was answered form the
+            // next batch
+            let mut batch = vec![query];
+            while let Ok(query) = receiver.try_recv() {
+                batch.push(query);
+            }
+            let paths: HashSet<String> = batch
+                .iter()
+                .flat_map(|query| query.paths.iter().cloned())
+                .collect();
+            match answer(&paths).await {
+                Ok(statuses) => {
+                    for query in batch {
+                        let statuses = query
+                            .paths
+                            .iter()
+                            .map(|path| (path.clone(), statuses[path]))
+                            .collect();
+                        let _ = query.reply.send(Ok(statuses));
+                    }
+                }
+                // a bad path fails the whole batch, so each query is answered
+                // on its own for the others to succeed
+                Err(e) if batch.len() > 1 => {
+                    tracing::debug!("store query failed, retrying each query: {}", e);
+                    for query in batch {
+                        let paths = query.paths.iter().cloned().collect();
+                        let _ = query.reply.send(answer(&paths).await);
+                    }
+                }
+                Err(e) => {
+                    for query in batch {
+                        let _ = query.reply.send(Err(e.clone()));
+                    }
+                }
+            }
+        }
+    });
+    sender
+});
+
+/// Queries the status of store paths. Content-addressed outputs whose path is
+/// not known yet (empty paths) are neither valid nor substitutable.
+pub async fn query(paths: Vec<String>) -> Result<HashMap<String, PathStatus>, Error> {
+    let (known, unknown): (Vec<String>, Vec<String>) =
+        paths.into_iter().partition(|path| !path.is_empty());
+    let mut statuses = if known.is_empty() {
+        HashMap::new()
+    } else {
+        let (reply, receiver) = oneshot::channel();
+        let _ = QUERIES.send(Query {
+            paths: known,
+            reply,
+        });
+        receiver.await.expect("the store query task stopped")?
+    };
+    statuses.extend(
+        unknown
+            .into_iter()
+            .map(|path| (path, PathStatus::default())),
+    );
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESENT: &str = "/nix/store/00000000000000000000000000000000-present";
+    const MISSING: &str = "/nix/store/11111111111111111111111111111111-missing";
+
+    #[test]
+    fn path_info_object() {
+        let stdout = serde_json::json!({
+            PRESENT: { "narSize": 1024 },
+            MISSING: null,
+        })
+        .to_string();
+        let present = parse_path_info(&stdout).unwrap();
+        assert_eq!(present, HashSet::from([PRESENT.to_string()]));
+    }
+
+    #[test]
+    fn path_info_array() {
+        let stdout = serde_json::json!([
+            { "path": PRESENT, "narSize": 1024 },
+            { "path": MISSING, "valid": false },
+        ])
+        .to_string();
+        let present = parse_path_info(&stdout).unwrap();
+        assert_eq!(present, HashSet::from([PRESENT.to_string()]));
+    }
+}
@@ -2,8 +2,10 @@ use crate::actions;
 use crate::error::Error;
 use crate::gcroots;
 use crate::jobsets;
+use crate::logs;
 use crate::models;
 use crate::nix;
+use crate::scheduler;
 use crate::schema;
 use crate::tasks;
 use crate::Conn;
@@ -11,19 +13,261 @@ use crate::CURRENT_SYSTEM;
 use crate::POOL;
 use crate::{handles, responses};
 use crate::{log_event, Event};
+use crate::{RUNS, RUNTIME, TASKS};
 
 use typhon_types::data::TaskStatusKind;
+use typhon_types::requests::{BuildLimitsDecl, NotificationsDecl};
 use typhon_types::responses::ProjectMetadata;
 use typhon_types::*;
 
 use age::secrecy::ExposeSecret;
 use diesel::prelude::*;
+use once_cell::sync::Lazy;
 use serde::Deserialize;
 use time::OffsetDateTime;
 use tokio::sync::oneshot;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::Mutex;
+
+/// The state of a project deletion
+#[derive(Clone, Debug)]
+enum Deletion {
+    InProgress,
+    /// The deletion stopped with this error, and the project was kept
+    Failed(String),
+}
+
+/// Projects being deleted, or whose deletion failed, by name
+static DELETING: Lazy<Mutex<HashMap<String, Deletion>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Refuses to create evaluations, runs, actions or refreshes for a project
+/// being deleted, since the deletion would miss them
+pub fn check_not_deleting(project: &models::Project) -> Result<(), Error> {
+    match DELETING.lock().unwrap().get(&project.name) {
+        Some(Deletion::InProgress) => Err(Error::ProjectBeingDeleted(handles::project(
+            project.name.clone(),
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Runs database queries from an async task
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce(&mut Conn) -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    RUNTIME
+        .spawn_blocking(move || f(&mut POOL.get().unwrap()))
+        .await
+        .unwrap()
+}
+
+/// Cancels tasks and waits for them to finish
+async fn cancel_tasks(ids: &[i32]) {
+    for id in ids {
+        TASKS.cancel(*id);
+    }
+    for id in ids {
+        TASKS.wait(id).await;
+    }
+}
+
+/// Returns the builds that only the runs `run_ids` need: the builds `roots`
+/// of these runs, along with their earlier attempts and their dependencies,
+/// minus the ones that other runs or builds still refer to
+fn builds_to_delete(conn: &mut Conn, run_ids: &[i32], roots: Vec<i32>) -> Result<Vec<i32>, Error> {
+    let mut builds: HashSet<i32> = HashSet::new();
+    let mut queue = roots;
+    while !queue.is_empty() {
+        builds.extend(queue.iter().copied());
+        let previous = schema::builds::table
+            .filter(schema::builds::id.eq_any(&queue))
+            .select(schema::builds::previous_id)
+            .load::<Option<i32>>(conn)?;
+        let dependencies = schema::build_dependencies::table
+            .filter(schema::build_dependencies::build_id.eq_any(&queue))
+            .select(schema::build_dependencies::dependency_id)
+            .load::<i32>(conn)?;
+        queue = previous
+            .into_iter()
+            .flatten()
+            .chain(dependencies)
+            .filter(|id| !builds.contains(id))
+            .collect::<HashSet<i32>>()
+            .into_iter()
+            .collect();
+    }
+
+    // a build that is kept also keeps the builds it refers to, which are
+    // found by the next iteration
+    loop {
+        let ids: Vec<i32> = builds.iter().copied().collect();
+        let used_by_runs = schema::runs::table
+            .filter(schema::runs::build_id.eq_any(&ids))
+            .filter(schema::runs::id.ne_all(run_ids))
+            .select(schema::runs::build_id.assume_not_null())
+            .load::<i32>(conn)?;
+        let used_by_builds = schema::build_dependencies::table
+            .filter(schema::build_dependencies::dependency_id.eq_any(&ids))
+            .filter(schema::build_dependencies::build_id.ne_all(&ids))
+            .select(schema::build_dependencies::dependency_id)
+            .load::<i32>(conn)?;
+        let retried_by_builds = schema::builds::table
+            .filter(schema::builds::previous_id.eq_any(&ids))
+            .filter(schema::builds::id.ne_all(&ids))
+            .select(schema::builds::previous_id.assume_not_null())
+            .load::<i32>(conn)?;
+        let kept: HashSet<i32> = used_by_runs
+            .into_iter()
+            .chain(used_by_builds)
+            .chain(retried_by_builds)
+            .collect();
+        if kept.is_empty() {
+            return Ok(ids);
+        }
+        builds.retain(|id| !kept.contains(id));
+    }
+}
+
+/// What a project owns, which is deleted along with it
+struct Owned {
+    evaluation_ids: Vec<i32>,
+    job_ids: Vec<i32>,
+    run_ids: Vec<i32>,
+    build_ids: Vec<i32>,
+    /// The tasks of the evaluations and of the last refresh
+    evaluation_task_ids: Vec<i32>,
+    /// The tasks of the actions and of the builds
+    other_task_ids: Vec<i32>,
+}
+
+impl Owned {
+    fn collect(conn: &mut Conn, project_id: i32) -> Result<Self, Error> {
+        let project = schema::projects::table
+            .find(project_id)
+            .first::<models::Project>(conn)?;
+        let evaluations = schema::evaluations::table
+            .filter(schema::evaluations::project_id.eq(project_id))
+            .load::<models::Evaluation>(conn)?;
+        let evaluation_ids: Vec<i32> = evaluations.iter().map(|e| e.id).collect();
+        let evaluation_task_ids: Vec<i32> = evaluations
+            .iter()
+            .map(|evaluation| evaluation.task_id)
+            .chain(project.last_refresh_task_id)
+            .collect();
+        let job_ids: Vec<i32> = schema::jobs::table
+            .filter(schema::jobs::evaluation_id.eq_any(&evaluation_ids))
+            .select(schema::jobs::id)
+            .load(conn)?;
+        let runs = schema::runs::table
+            .filter(schema::runs::job_id.eq_any(&job_ids))
+            .load::<models::Run>(conn)?;
+        let run_ids: Vec<i32> = runs.iter().map(|run| run.id).collect();
+        let roots: Vec<i32> = runs.iter().filter_map(|run| run.build_id).collect();
+        let build_ids = builds_to_delete(conn, &run_ids, roots)?;
+        let action_task_ids: Vec<i32> = schema::actions::table
+            .filter(schema::actions::project_id.eq(project_id))
+            .select(schema::actions::task_id)
+            .load(conn)?;
+        let build_task_ids: Vec<i32> = schema::builds::table
+            .filter(schema::builds::id.eq_any(&build_ids))
+            .select(schema::builds::task_id)
+            .load(conn)?;
+        Ok(Self {
+            evaluation_ids,
+            job_ids,
+            run_ids,
+            build_ids,
+            evaluation_task_ids,
+            other_task_ids: [action_task_ids, build_task_ids].concat(),
+        })
+    }
+}
+
+/// Returns the ids not in `canceled`, and adds them to it
+fn not_canceled(ids: &[i32], canceled: &mut HashSet<i32>) -> Vec<i32> {
+    ids.iter()
+        .copied()
+        .filter(|id| canceled.insert(*id))
+        .collect()
+}
+
+/// Deletes a project and everything it owns, in one transaction. What it
+/// owns is collected again in the transaction, and nothing is deleted if
+/// a task or a run was created since the last cancellation: `None` is
+/// returned then. Otherwise, the logs to delete from the storage are
+/// returned.
+fn delete_owned(
+    conn: &mut Conn,
+    project_id: i32,
+    canceled_tasks: &HashSet<i32>,
+    canceled_runs: &HashSet<i32>,
+) -> Result<Option<Vec<i32>>, Error> {
+    conn.transaction::<Option<Vec<i32>>, Error, _>(|conn| {
+        let owned = Owned::collect(conn, project_id)?;
+        let task_ids: Vec<i32> = [owned.evaluation_task_ids, owned.other_task_ids].concat();
+        if task_ids.iter().any(|id| !canceled_tasks.contains(id))
+            || owned.run_ids.iter().any(|id| !canceled_runs.contains(id))
+        {
+            return Ok(None);
+        }
+        let log_ids: Vec<i32> = schema::tasks::table
+            .filter(schema::tasks::id.eq_any(&task_ids))
+            .select(schema::tasks::log_id)
+            .load(conn)?;
+        diesel::delete(schema::runs::table.filter(schema::runs::id.eq_any(&owned.run_ids)))
+            .execute(conn)?;
+        diesel::delete(schema::jobs::table.filter(schema::jobs::id.eq_any(&owned.job_ids)))
+            .execute(conn)?;
+        diesel::delete(
+            schema::evaluations::table
+                .filter(schema::evaluations::id.eq_any(&owned.evaluation_ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(schema::actions::table.filter(schema::actions::project_id.eq(project_id)))
+            .execute(conn)?;
+        diesel::delete(
+            schema::build_dependencies::table
+                .filter(schema::build_dependencies::build_id.eq_any(&owned.build_ids)),
+        )
+        .execute(conn)?;
+        diesel::update(
+            schema::builds::table
+                .filter(schema::builds::failed_dependency_id.eq_any(&owned.build_ids)),
+        )
+        .set(schema::builds::failed_dependency_id.eq(None::<i32>))
+        .execute(conn)?;
+        diesel::delete(schema::builds::table.filter(schema::builds::id.eq_any(&owned.build_ids)))
+            .execute(conn)?;
+        diesel::delete(schema::jobsets::table.filter(schema::jobsets::project_id.eq(project_id)))
+            .execute(conn)?;
+        diesel::delete(schema::roles::table.filter(schema::roles::project_id.eq(project_id)))
+            .execute(conn)?;
+        diesel::delete(schema::tokens::table.filter(schema::tokens::project_id.eq(project_id)))
+            .execute(conn)?;
+        let subscription_ids = schema::subscriptions::table
+            .filter(schema::subscriptions::project_id.eq(project_id))
+            .select(schema::subscriptions::id)
+            .load::<i32>(conn)?;
+        diesel::delete(
+            schema::deliveries::table
+                .filter(schema::deliveries::subscription_id.eq_any(&subscription_ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            schema::subscriptions::table
+                .filter(schema::subscriptions::id.eq_any(&subscription_ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(schema::projects::table.find(project_id)).execute(conn)?;
+        diesel::delete(schema::tasks::table.filter(schema::tasks::id.eq_any(&task_ids)))
+            .execute(conn)?;
+        diesel::delete(schema::logs::table.filter(schema::logs::id.eq_any(&log_ids)))
+            .execute(conn)?;
+        Ok(Some(log_ids))
+    })
+}
 
 #[derive(Clone)]
 pub struct Project {
@@ -63,9 +307,89 @@ impl Project {
         }
     }
 
-    //pub fn delete(&self, _conn: &mut Conn) -> Result<(), Error> {
-    //    todo!()
-    //}
+    /// Deletes the project in the background: its tasks are canceled first,
+    /// then everything it owns is removed. [`Event::ProjectDeleted`] is
+    /// emitted once it is done. Meanwhile, nothing new can be created for
+    /// the project. If the deletion fails, the project is kept and the
+    /// error is shown in its info.
+    pub fn delete(&self) -> Result<(), Error> {
+        let name = self.project.name.clone();
+        {
+            let mut deleting = DELETING.lock().unwrap();
+            if let Some(Deletion::InProgress) = deleting.get(&name) {
+                return Ok(());
+            }
+            deleting.insert(name.clone(), Deletion::InProgress);
+        }
+        log_event(Event::ProjectUpdated(self.handle()));
+        let project = self.clone();
+        RUNTIME.spawn(async move {
+            let handle = project.handle();
+            match project.delete_aux().await {
+                Ok(()) => {
+                    DELETING.lock().unwrap().remove(&name);
+                    log_event(Event::ProjectDeleted(handle));
+                }
+                Err(e) => {
+                    tracing::error!("failed to delete project {}: {}", handle, e);
+                    DELETING
+                        .lock()
+                        .unwrap()
+                        .insert(name, Deletion::Failed(e.to_string()));
+                    log_event(Event::ProjectUpdated(handle));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn delete_aux(self) -> Result<(), Error> {
+        // cancel everything still running and wait for the tasks to finish,
+        // so that no task writes to the database once the project is gone;
+        // this is done in stages since evaluations create runs and runs
+        // create actions and builds, and it is repeated until the final
+        // transaction finds nothing left running
+        let id = self.project.id;
+        let mut canceled_tasks: HashSet<i32> = HashSet::new();
+        let mut canceled_runs: HashSet<i32> = HashSet::new();
+        loop {
+            let owned = blocking(move |conn| Owned::collect(conn, id)).await?;
+            cancel_tasks(&not_canceled(
+                &owned.evaluation_task_ids,
+                &mut canceled_tasks,
+            ))
+            .await;
+            let runs = not_canceled(&owned.run_ids, &mut canceled_runs);
+            for run in runs.iter() {
+                RUNS.cancel(*run);
+            }
+            for run in runs.iter() {
+                RUNS.wait(run).await;
+            }
+            let owned = blocking(move |conn| Owned::collect(conn, id)).await?;
+            cancel_tasks(&not_canceled(&owned.other_task_ids, &mut canceled_tasks)).await;
+
+            let (tasks, runs) = (canceled_tasks.clone(), canceled_runs.clone());
+            let deleted = blocking(move |conn| {
+                let Some(log_ids) = delete_owned(conn, id, &tasks, &runs)? else {
+                    return Ok(false);
+                };
+                // the stored logs are only deleted once the database no
+                // longer refers to them
+                for log_id in log_ids {
+                    if let Err(e) = logs::storage::storage().delete(conn, log_id) {
+                        tracing::warn!("failed to delete log {}: {}", log_id, e);
+                    }
+                }
+                gcroots::update(conn);
+                Ok(true)
+            })
+            .await?;
+            if deleted {
+                return Ok(());
+            }
+        }
+    }
 
     pub fn get(conn: &mut Conn, handle: &handles::Project) -> Result<Self, Error> {
         let (project, task): (models::Project, Option<models::Task>) = schema::projects::table
@@ -95,9 +419,16 @@ impl Project {
             .map_err(|_| Error::Todo)?
             .to_public()
             .to_string();
+        let deletion = DELETING.lock().unwrap().get(&self.project.name).cloned();
         Ok(responses::ProjectInfo {
             handle: self.handle(),
             actions_path: self.project.actions_path.clone(),
+            build_limits: self.build_limits(),
+            deleting: matches!(deletion, Some(Deletion::InProgress)),
+            deletion_error: match deletion {
+                Some(Deletion::Failed(e)) => Some(e),
+                _ => None,
+            },
             flake: self.project.flake,
             jobsets: jobsets_names,
             last_refresh: self.refresh_task.clone().map(|task| task.status()),
@@ -106,6 +437,7 @@ impl Project {
                 description: self.project.description.clone(),
                 homepage: self.project.homepage.clone(),
             },
+            notifications: self.notifications(),
             public_key,
             url: self.project.url.clone(),
             url_locked: self.project.url_locked.clone(),
@@ -121,6 +453,7 @@ impl Project {
     ) -> Result<actions::Action, Error> {
         use uuid::{timestamp, Uuid};
 
+        check_not_deleting(&self.project)?;
         conn.transaction::<actions::Action, Error, _>(|conn| {
             let task = tasks::Task::new(conn)?;
             let time_created = OffsetDateTime::now_utc().unix_timestamp();
@@ -150,6 +483,8 @@ impl Project {
     }
 
     pub fn refresh(&self, conn: &mut Conn) -> Result<(), Error> {
+        check_not_deleting(&self.project)?;
+
         #[derive(Deserialize)]
         struct TyphonProject {
             actions: Option<HashMap<String, String>>,
@@ -173,7 +508,11 @@ impl Project {
                 let actions_path = if let Some(x) = actions {
                     let drv = nix::derivation(nix::Expr::Path(x.clone())).await?;
                     // FIXME: this should spawn a build
-                    Some(nix::build(&drv.path, sender).await?["out"].clone())
+                    Some(
+                        nix::build(&drv.path, sender, None, None, &nix::BuildLimits::default())
+                            .await?["out"]
+                            .clone(),
+                    )
                     // TODO: check public key used to encrypt secrets
                 } else {
                     None
@@ -228,6 +567,59 @@ impl Project {
         Ok(())
     }
 
+    pub fn build_limits(&self) -> BuildLimitsDecl {
+        BuildLimitsDecl {
+            timeout: self.project.build_timeout.map(|t| t as u64),
+            max_silent: self.project.build_max_silent.map(|t| t as u64),
+        }
+    }
+
+    /// Sets the build limits of the project. A limit of zero is no limit.
+    pub fn set_build_limits(&self, conn: &mut Conn, decl: &BuildLimitsDecl) -> Result<(), Error> {
+        let seconds = |limit: Option<u64>| {
+            limit
+                .filter(|t| *t > 0)
+                .map(|t| i64::try_from(t).map_err(|_| Error::InvalidBuildLimit(t)))
+                .transpose()
+        };
+        let (timeout, max_silent) = (seconds(decl.timeout)?, seconds(decl.max_silent)?);
+        diesel::update(&self.project)
+            .set((
+                schema::projects::build_timeout.eq(timeout),
+                schema::projects::build_max_silent.eq(max_silent),
+            ))
+            .execute(conn)?;
+        log_event(Event::ProjectUpdated(self.handle()));
+        Ok(())
+    }
+
+    pub fn notifications(&self) -> NotificationsDecl {
+        NotificationsDecl {
+            emails: serde_json::from_str(&self.project.notify_emails).unwrap(),
+            maintainers: self.project.notify_maintainers,
+        }
+    }
+
+    pub fn set_notifications(
+        &self,
+        conn: &mut Conn,
+        decl: &NotificationsDecl,
+    ) -> Result<(), Error> {
+        for email in &decl.emails {
+            if email.parse::<lettre::Address>().is_err() {
+                return Err(Error::BadEmailAddress(email.clone()));
+            }
+        }
+        diesel::update(&self.project)
+            .set((
+                schema::projects::notify_emails.eq(serde_json::to_string(&decl.emails).unwrap()),
+                schema::projects::notify_maintainers.eq(decl.maintainers),
+            ))
+            .execute(conn)?;
+        log_event(Event::ProjectUpdated(self.handle()));
+        Ok(())
+    }
+
     pub fn update_jobsets(&self, conn: &mut Conn) -> Result<(), Error> {
         // run action `jobsets`
         let action = self.new_action(
@@ -353,7 +745,8 @@ impl Project {
             })
             .collect();
 
-        // delete obsolete jobsets
+        // keep the jobsets whose declaration did not change
+        let mut obsolete = Vec::new();
         let mut set = std::collections::HashSet::<String>::new();
         for jobset in current_jobsets.drain(..) {
             if decls
@@ -362,27 +755,236 @@ impl Project {
             {
                 set.insert(jobset.jobset.name);
             } else {
-                jobset.delete(&mut conn)?;
+                obsolete.push(jobset);
             }
         }
 
-        // create new jobsets
+        // check every new declaration before changing anything, so that an
+        // invalid one leaves the jobsets as they were
+        let now = OffsetDateTime::now_utc();
+        let mut new_jobsets = Vec::new();
         for (name, decl) in decls.iter() {
             if !set.contains(name) {
+                let schedule = decl
+                    .schedule
+                    .as_ref()
+                    .map(|schedule| serde_json::to_string(schedule).unwrap());
+                let time_next_evaluation = decl
+                    .schedule
+                    .as_ref()
+                    .map(|schedule| scheduler::next_time(schedule, now))
+                    .transpose()?
+                    .map(OffsetDateTime::unix_timestamp);
+                let poll = decl
+                    .poll
+                    .map(|seconds| {
+                        i32::try_from(seconds)
+                            .ok()
+                            .filter(|seconds| *seconds > 0)
+                            .ok_or(Error::BadJobsetDecl(format!(
+                                "invalid polling interval {}",
+                                seconds
+                            )))
+                    })
+                    .transpose()?;
+                new_jobsets.push((name, decl, schedule, time_next_evaluation, poll));
+            }
+        }
+
+        conn.transaction::<(), Error, _>(|conn| {
+            for jobset in obsolete {
+                jobset.delete(conn)?;
+            }
+            for (name, decl, schedule, time_next_evaluation, poll) in new_jobsets.iter() {
+                // poll right away, so that the first evaluation is not delayed
+                let time_next_poll = poll.map(|_| now.unix_timestamp());
                 let new_jobset = models::NewJobset {
                     flake: decl.flake,
                     name,
+                    poll: *poll,
+                    priority: decl.priority,
                     project_id: self.project.id,
+                    schedule: schedule.as_deref(),
+                    time_next_evaluation: *time_next_evaluation,
+                    time_next_poll,
                     url: &decl.url,
                 };
                 diesel::insert_into(schema::jobsets::table)
                     .values(&new_jobset)
-                    .execute(&mut conn)?;
+                    .execute(conn)?;
             }
-        }
+            Ok(())
+        })?;
+
+        scheduler::SCHEDULER.wake();
 
         gcroots::update(&mut conn);
 
         Ok(TaskStatusKind::Success)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(conn: &mut Conn, name: &str) -> Project {
+        let decl = requests::ProjectDecl {
+            flake: true,
+            url: "github:typhon-ci/typhon".to_string(),
+        };
+        Project::create(conn, &name.to_string(), &decl).unwrap();
+        Project::get(conn, &handles::project(name.to_string())).unwrap()
+    }
+
+    fn evaluation(conn: &mut Conn, project: &models::Project) -> models::Evaluation {
+        let task = tasks::Task::new(conn).unwrap();
+        diesel::insert_into(schema::evaluations::table)
+            .values(&models::NewEvaluation {
+                actions_path: None,
+                flake: true,
+                jobset_name: "main",
+                project_id: project.id,
+                task_id: task.task.id,
+                time_created: 0,
+                url: "github:typhon-ci/typhon",
+                uuid: &uuid::Uuid::now_v7().to_string(),
+            })
+            .get_result(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn nothing_is_created_while_deleting() {
+        let conn = &mut crate::test_conn();
+        let project = project(conn, "being-deleted");
+        diesel::insert_into(schema::jobsets::table)
+            .values(&models::NewJobset {
+                flake: true,
+                name: "main",
+                poll: None,
+                priority: 0,
+                project_id: project.project.id,
+                schedule: None,
+                time_next_evaluation: None,
+                time_next_poll: None,
+                url: "github:typhon-ci/typhon",
+            })
+            .execute(conn)
+            .unwrap();
+        let jobset = jobsets::Jobset::get(
+            conn,
+            &handles::jobset(("being-deleted".to_string(), "main".to_string())),
+        )
+        .unwrap();
+        DELETING
+            .lock()
+            .unwrap()
+            .insert("being-deleted".to_string(), Deletion::InProgress);
+
+        assert!(matches!(
+            jobset.evaluate(conn, false),
+            Err(Error::ProjectBeingDeleted(_))
+        ));
+        assert!(matches!(
+            jobset.poll(conn),
+            Err(Error::ProjectBeingDeleted(_))
+        ));
+        assert!(matches!(
+            project.refresh(conn),
+            Err(Error::ProjectBeingDeleted(_))
+        ));
+        assert!(project.info(conn).unwrap().deleting);
+
+        DELETING.lock().unwrap().insert(
+            "being-deleted".to_string(),
+            Deletion::Failed("Database error".to_string()),
+        );
+        let info = project.info(conn).unwrap();
+        assert!(!info.deleting);
+        assert_eq!(info.deletion_error, Some("Database error".to_string()));
+        DELETING.lock().unwrap().remove("being-deleted");
+    }
+
+    #[test]
+    fn evaluation_created_during_deletion() {
+        let conn = &mut crate::test_conn();
+        let project = project(conn, "p");
+        let first = evaluation(conn, &project.project);
+        let mut canceled_tasks = HashSet::from([first.task_id]);
+        // created after the tasks of the project were canceled
+        let second = evaluation(conn, &project.project);
+
+        assert_eq!(
+            delete_owned(conn, project.project.id, &canceled_tasks, &HashSet::new()).unwrap(),
+            None
+        );
+        assert_eq!(
+            schema::evaluations::table
+                .count()
+                .get_result::<i64>(conn)
+                .unwrap(),
+            2
+        );
+
+        canceled_tasks.insert(second.task_id);
+        let log_ids = delete_owned(conn, project.project.id, &canceled_tasks, &HashSet::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(log_ids.len(), 2);
+        assert_eq!(
+            schema::projects::table
+                .count()
+                .get_result::<i64>(conn)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            schema::evaluations::table
+                .count()
+                .get_result::<i64>(conn)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            schema::tasks::table
+                .count()
+                .get_result::<i64>(conn)
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn build_limits() {
+        let conn = &mut crate::test_conn();
+        let project = project(conn, "p");
+        project
+            .set_build_limits(
+                conn,
+                &BuildLimitsDecl {
+                    timeout: Some(0),
+                    max_silent: Some(60),
+                },
+            )
+            .unwrap();
+        let project = Project::get(conn, &project.handle()).unwrap();
+        assert_eq!(
+            project.build_limits(),
+            BuildLimitsDecl {
+                timeout: None,
+                max_silent: Some(60),
+            }
+        );
+        assert!(matches!(
+            project.set_build_limits(
+                conn,
+                &BuildLimitsDecl {
+                    timeout: Some(u64::MAX),
+                    max_silent: None,
+                },
+            ),
+            Err(Error::InvalidBuildLimit(u64::MAX))
+        ));
+    }
+}
@@ -12,12 +12,28 @@ use crate::POOL;
 use crate::RUNS;
 
 use typhon_types::data::TaskStatusKind;
+use typhon_types::responses::TaskStatus;
 use typhon_types::*;
 
 use diesel::prelude::*;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 use std::str::FromStr;
+use std::time::Duration;
+
+impl models::Run {
+    pub fn status_kind(&self) -> TaskStatusKind {
+        self.status.try_into().unwrap()
+    }
+    pub fn status(&self) -> TaskStatus {
+        let from_timestamp = |t| OffsetDateTime::from_unix_timestamp(t).unwrap();
+        self.status_kind().into_task_status(
+            self.time_started.map(from_timestamp),
+            self.time_finished.map(from_timestamp),
+        )
+    }
+}
 
 #[derive(Clone)]
 pub struct Run {
@@ -31,9 +47,27 @@ pub struct Run {
 }
 
 impl Run {
-    //pub fn cancel(&self) {
-    //    RUNS.cancel(self.run.id);
-    //}
+    /// Cancels the run. Dropping the run's build handle releases the build,
+    /// which is only aborted if no other run is waiting for it. The `end`
+    /// action is still executed, with a `canceled` status.
+    pub fn cancel(&self) {
+        RUNS.cancel(self.run.id);
+    }
+
+    /// Runs do not survive a restart: marks the runs left pending by a
+    /// previous instance as canceled
+    pub fn cancel_stale(conn: &mut Conn) -> Result<(), Error> {
+        diesel::update(
+            schema::runs::table.filter(schema::runs::status.eq(i32::from(TaskStatusKind::Pending))),
+        )
+        .set((
+            schema::runs::status.eq(i32::from(TaskStatusKind::Canceled)),
+            schema::runs::time_started.eq(None::<i64>),
+            schema::runs::time_finished.eq(None::<i64>),
+        ))
+        .execute(conn)?;
+        Ok(())
+    }
 
     pub fn get(conn: &mut Conn, handle: &handles::Run) -> Result<Self, Error> {
         let (begin_action, end_action, begin_task, build_task, end_task) = diesel::alias!(
@@ -143,7 +177,7 @@ impl Run {
         ))
     }
 
-    pub fn info(&self) -> responses::RunInfo {
+    pub fn info(&self, conn: &mut Conn) -> Result<responses::RunInfo, Error> {
         use crate::evaluations::ExtraRunInfo;
         let Run {
             run,
@@ -153,6 +187,7 @@ impl Run {
             ..
         } = self.clone();
         responses::RunInfo::new(
+            conn,
             &handles::project(self.project.name.clone()),
             &self.handle().job,
             run,
@@ -163,21 +198,51 @@ impl Run {
     }
 
     pub fn run(&self, conn: &mut Conn) -> Result<(), Error> {
-        use crate::build_manager::BUILDS;
+        use crate::build_manager::{Failure, Priority, Requester, BUILDS};
         use crate::nix;
         use crate::TASKS;
 
         // run the build
         let drv = nix::DrvPath::new(&self.job.drv);
-        let build_handle = BUILDS.run(drv);
+        let requester = Requester {
+            project: self.project.name.clone(),
+            priority: Priority {
+                // later runs of a job are manual reruns
+                rerun: self.run.num > 1,
+                jobset: schema::jobsets::table
+                    .filter(schema::jobsets::project_id.eq(self.project.id))
+                    .filter(schema::jobsets::name.eq(&self.evaluation.jobset_name))
+                    .select(schema::jobsets::priority)
+                    .first::<i32>(conn)
+                    .optional()?
+                    .unwrap_or(0),
+            },
+            // the limits of the job take precedence over the ones of the
+            // project, even when they are zero, which is no limit
+            limits: nix::BuildLimits {
+                timeout: self
+                    .job
+                    .timeout
+                    .or(self.project.build_timeout)
+                    .map(|t| Duration::from_secs(u64::try_from(t).unwrap_or(0))),
+                max_silent: self
+                    .job
+                    .max_silent
+                    .or(self.project.build_max_silent)
+                    .map(|t| Duration::from_secs(u64::try_from(t).unwrap_or(0))),
+            },
+        };
+        let build_handle = BUILDS.run(drv, requester);
 
         // run the 'begin' action
         let action_begin = self.spawn_action(conn, "begin", TaskStatusKind::Pending)?;
 
+        let start = OffsetDateTime::now_utc();
         diesel::update(&self.run)
             .set((
                 schema::runs::begin_id.eq(action_begin.action.id),
                 schema::runs::build_id.eq(build_handle.id),
+                schema::runs::time_started.eq(start.unix_timestamp()),
             ))
             .execute(conn)?;
         log_event(Event::RunUpdated(self.handle()));
@@ -187,29 +252,42 @@ impl Run {
             TASKS.wait(&action_begin.task.task.id).await;
             let res = build_handle.wait().await;
             match res {
-                Some(Some(())) => TaskStatusKind::Success,
-                Some(None) => TaskStatusKind::Failure,
+                Some(Ok(())) => TaskStatusKind::Success,
+                Some(Err(Failure::Failed | Failure::Transient | Failure::DependencyFailed(_))) => {
+                    TaskStatusKind::Failure
+                }
+                Some(Err(Failure::TimedOut)) => TaskStatusKind::TimedOut,
                 None => TaskStatusKind::Canceled,
             }
         };
 
-        // run the 'end' action
+        // run the 'end' action, then wait for it to record the run's status
         let finish_run = {
             let self_ = self.clone();
-            let finish_err = move |status| {
-                if let Some(status) = status {
+            let finish_err = {
+                let self_ = self.clone();
+                move |status: Option<TaskStatusKind>| {
+                    let status = status.unwrap_or(TaskStatusKind::Canceled);
                     let mut conn = POOL.get().unwrap();
                     let action_end = self_.spawn_action(&mut conn, "end", status)?;
                     diesel::update(&self_.run)
                         .set((schema::runs::end_id.eq(action_end.action.id),))
                         .execute(&mut conn)?;
                     log_event(Event::RunUpdated(self_.handle()));
+                    Ok::<_, Error>((action_end.task.task.id, status))
                 }
-                Ok::<_, Error>(())
             };
             move |status| {
-                finish_err(status).unwrap(); // FIXME
-                None::<()>
+                let (end_task_id, status) = finish_err(status).unwrap(); // FIXME
+                let wait_end = async move { TASKS.wait(&end_task_id).await };
+                let finish_end = move |res: Option<()>| {
+                    let mut conn = POOL.get().unwrap();
+                    self_
+                        .finish(&mut conn, start, res.map(|()| status))
+                        .unwrap(); // FIXME
+                    None::<()>
+                };
+                Some((wait_end, finish_end))
             }
         };
 
@@ -218,7 +296,103 @@ impl Run {
         Ok(())
     }
 
-    fn mk_input(&self, status: TaskStatusKind) -> Result<serde_json::Value, Error> {
+    /// Records the final status of the run, derived from the outcomes of
+    /// its `begin` action, its build (`status`) and its `end` action.
+    /// `status` is `None` if the run was canceled while waiting for the `end`
+    /// action.
+    fn finish(
+        &self,
+        conn: &mut Conn,
+        start: OffsetDateTime,
+        status: Option<TaskStatusKind>,
+    ) -> Result<(), Error> {
+        let run = Self::get(conn, &self.handle())?;
+        let status = match status {
+            Some(status) => [&run.begin, &run.end]
+                .into_iter()
+                .flatten()
+                .map(|action| action.task.status_kind())
+                .fold(status, TaskStatusKind::max),
+            None => TaskStatusKind::Canceled,
+        };
+        let previous = self.previous_status(conn)?;
+        let kind = status;
+        let status = status.into_task_status(Some(start), Some(OffsetDateTime::now_utc()));
+        let (_, finished) = status.times();
+        diesel::update(&self.run)
+            .set((
+                schema::runs::previous_status.eq(previous.map(i32::from)),
+                schema::runs::status.eq(i32::from(kind)),
+                schema::runs::time_finished.eq(finished.map(OffsetDateTime::unix_timestamp)),
+            ))
+            .execute(conn)?;
+        log_event(Event::RunUpdated(self.handle()));
+        if let Some(event) = self.job_event(previous, kind) {
+            log_event(event);
+        }
+        Ok(())
+    }
+
+    /// Returns the status of the job before this run: the status of the
+    /// latest run of the job that succeeded or failed, among its previous
+    /// runs and the runs of the same job in the earlier evaluations of the
+    /// jobset. Canceled and pending runs are skipped.
+    fn previous_status(&self, conn: &mut Conn) -> Result<Option<TaskStatusKind>, Error> {
+        use schema::{evaluations, jobs, runs};
+
+        let finished = [
+            TaskStatusKind::Success,
+            TaskStatusKind::Failure,
+            TaskStatusKind::TimedOut,
+        ]
+        .map(i32::from);
+        let evaluation = &self.evaluation;
+        let run = runs::table
+            .inner_join(jobs::table.inner_join(evaluations::table))
+            .filter(evaluations::project_id.eq(evaluation.project_id))
+            .filter(evaluations::jobset_name.eq(&evaluation.jobset_name))
+            .filter(jobs::system.eq(&self.job.system))
+            .filter(jobs::name.eq(&self.job.name))
+            .filter(runs::status.eq_any(finished))
+            .filter(
+                evaluations::time_created
+                    .lt(evaluation.time_created)
+                    .or(evaluations::time_created
+                        .eq(evaluation.time_created)
+                        .and(evaluations::id.lt(evaluation.id)))
+                    .or(runs::job_id.eq(self.job.id).and(runs::num.lt(self.run.num))),
+            )
+            .order((
+                evaluations::time_created.desc(),
+                evaluations::id.desc(),
+                runs::num.desc(),
+            ))
+            .select(runs::all_columns)
+            .first::<models::Run>(conn)
+            .optional()?;
+        Ok(run.map(|run| run.status_kind()))
+    }
+
+    /// Returns the event to emit when the job goes from status `previous` to
+    /// `status`, if it broke or got fixed
+    fn job_event(&self, previous: Option<TaskStatusKind>, status: TaskStatusKind) -> Option<Event> {
+        match (previous, status) {
+            (Some(TaskStatusKind::Success), TaskStatusKind::Failure | TaskStatusKind::TimedOut) => {
+                Some(Event::JobBroken(self.handle().job))
+            }
+            (Some(TaskStatusKind::Failure | TaskStatusKind::TimedOut), TaskStatusKind::Success) => {
+                Some(Event::JobFixed(self.handle().job))
+            }
+            _ => None,
+        }
+    }
+
+    fn mk_input(
+        &self,
+        conn: &mut Conn,
+        status: TaskStatusKind,
+    ) -> Result<serde_json::Value, Error> {
+        let previous_status = self.previous_status(conn)?;
         Ok(serde_json::json!({
             "drv": self.job.drv,
             "evaluation": self.evaluation.uuid,
@@ -226,6 +400,8 @@ impl Run {
             "job": self.job.name,
             "jobset": self.evaluation.jobset_name,
             "out": self.job.out,
+            "outputs": self.job.outputs(),
+            "previous_status": previous_status.map(|status| status.to_string()),
             "project": self.project.name,
             "status": status.to_string(),
             "system": self.job.system,
@@ -246,7 +422,7 @@ impl Run {
             project: self.project.clone(),
         };
 
-        let input = self.mk_input(status)?;
+        let input = self.mk_input(conn, status)?;
 
         let action = project.new_action(
             conn,
@@ -269,3 +445,124 @@ impl Run {
         Ok(action)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the runs of a job with the given statuses, one per
+    /// evaluation, and returns the last one
+    fn history(conn: &mut Conn, statuses: &[TaskStatusKind]) -> Run {
+        let project: models::Project = diesel::insert_into(schema::projects::table)
+            .values(&models::NewProject {
+                flake: true,
+                key: "",
+                name: "p",
+                url: "github:typhon-ci/typhon",
+            })
+            .get_result(conn)
+            .unwrap();
+        let mut last = None;
+        for (i, status) in statuses.iter().enumerate() {
+            let task = tasks::Task::new(conn).unwrap();
+            let evaluation: models::Evaluation = diesel::insert_into(schema::evaluations::table)
+                .values(&models::NewEvaluation {
+                    actions_path: None,
+                    flake: true,
+                    jobset_name: "main",
+                    project_id: project.id,
+                    task_id: task.task.id,
+                    time_created: i as i64,
+                    url: "github:typhon-ci/typhon",
+                    uuid: &Uuid::now_v7().to_string(),
+                })
+                .get_result(conn)
+                .unwrap();
+            let job: models::Job = diesel::insert_into(schema::jobs::table)
+                .values(&models::NewJob {
+                    dist: false,
+                    drv: "/nix/store/00000000000000000000000000000000-hello.drv",
+                    eval_error: None,
+                    evaluation_id: evaluation.id,
+                    max_silent: None,
+                    name: "hello",
+                    out: "/nix/store/00000000000000000000000000000000-hello",
+                    outputs: "{}",
+                    system: "x86_64-linux",
+                    timeout: None,
+                    tries: 1,
+                })
+                .get_result(conn)
+                .unwrap();
+            let run: models::Run = diesel::insert_into(schema::runs::table)
+                .values(&models::NewRun {
+                    job_id: job.id,
+                    num: 1,
+                    time_created: i as i64,
+                })
+                .get_result(conn)
+                .unwrap();
+            let run = diesel::update(&run)
+                .set(schema::runs::status.eq(i32::from(*status)))
+                .get_result(conn)
+                .unwrap();
+            last = Some(Run {
+                begin: None,
+                end: None,
+                build: None,
+                run,
+                job,
+                evaluation,
+                project: project.clone(),
+            });
+        }
+        last.unwrap()
+    }
+
+    #[test]
+    fn broken_across_canceled_run() {
+        let conn = &mut crate::test_conn();
+        let run = history(
+            conn,
+            &[
+                TaskStatusKind::Success,
+                TaskStatusKind::Canceled,
+                TaskStatusKind::Failure,
+            ],
+        );
+        let previous = run.previous_status(conn).unwrap();
+        assert_eq!(previous, Some(TaskStatusKind::Success));
+        assert_eq!(
+            run.job_event(previous, TaskStatusKind::Failure),
+            Some(Event::JobBroken(run.handle().job))
+        );
+    }
+
+    #[test]
+    fn fixed_across_canceled_run() {
+        let conn = &mut crate::test_conn();
+        let run = history(
+            conn,
+            &[
+                TaskStatusKind::Failure,
+                TaskStatusKind::Canceled,
+                TaskStatusKind::Success,
+            ],
+        );
+        let previous = run.previous_status(conn).unwrap();
+        assert_eq!(previous, Some(TaskStatusKind::Failure));
+        assert_eq!(
+            run.job_event(previous, TaskStatusKind::Success),
+            Some(Event::JobFixed(run.handle().job))
+        );
+    }
+
+    #[test]
+    fn no_previous_status() {
+        let conn = &mut crate::test_conn();
+        let run = history(conn, &[TaskStatusKind::Canceled, TaskStatusKind::Success]);
+        let previous = run.previous_status(conn).unwrap();
+        assert_eq!(previous, None);
+        assert_eq!(run.job_event(previous, TaskStatusKind::Success), None);
+    }
+}
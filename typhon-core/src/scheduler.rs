@@ -0,0 +1,238 @@
+use crate::error::Error;
+use crate::jobsets;
+use crate::models;
+use crate::schema;
+use crate::Conn;
+use crate::POOL;
+use crate::RUNTIME;
+
+use typhon_types::data::Schedule;
+
+use diesel::prelude::*;
+use once_cell::sync::Lazy;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, watch};
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Computes the first time strictly after `after` at which a schedule fires
+pub fn next_time(schedule: &Schedule, after: OffsetDateTime) -> Result<OffsetDateTime, Error> {
+    match schedule {
+        Schedule::Interval(seconds) => i64::try_from(*seconds)
+            .ok()
+            .filter(|seconds| *seconds > 0)
+            .and_then(|seconds| after.checked_add(time::Duration::seconds(seconds)))
+            .ok_or(Error::BadJobsetDecl(format!(
+                "invalid interval {}",
+                seconds
+            ))),
+        Schedule::Cron(expr) => {
+            let bad = || Error::BadJobsetDecl(format!("invalid cron expression {}", expr));
+            let cron = croner::Cron::new(expr).parse().map_err(|_| bad())?;
+            let after =
+                chrono::DateTime::from_timestamp(after.unix_timestamp(), 0).ok_or_else(bad)?;
+            let next = cron
+                .find_next_occurrence(&after, false)
+                .map_err(|_| bad())?;
+            OffsetDateTime::from_unix_timestamp(next.timestamp()).map_err(|_| bad())
+        }
+    }
+}
+
+/// Jobsets whose scheduled evaluation or polling is running
+static RUNNING: Lazy<Mutex<HashSet<i32>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Evaluates or polls a jobset whose time has come
+fn run_due(conn: &mut Conn, jobset: &jobsets::Jobset, evaluate: bool, poll: bool) {
+    if evaluate {
+        if let Err(e) = jobset.evaluate(conn, false) {
+            tracing::warn!(
+                "scheduled evaluation of jobset {} failed: {}",
+                jobset.handle(),
+                e
+            );
+        }
+    }
+    if poll {
+        if let Err(e) = jobset.poll(conn) {
+            tracing::warn!("polling of jobset {} failed: {}", jobset.handle(), e);
+        }
+    }
+}
+
+/// Starts the evaluation of the jobsets whose scheduled time has come and the
+/// polling of the ones whose polling time has come, and returns the next time
+/// the scheduler has to wake up. Each jobset is handled in its own blocking
+/// task, so that a jobset whose URL takes long to lock does not delay the
+/// others; a jobset is skipped while its previous evaluation or polling is
+/// still running.
+fn evaluate_due(conn: &mut Conn) -> Result<Option<OffsetDateTime>, Error> {
+    let now = OffsetDateTime::now_utc();
+    let due = schema::jobsets::table
+        .inner_join(schema::projects::table)
+        .filter(
+            schema::jobsets::time_next_evaluation
+                .le(now.unix_timestamp())
+                .or(schema::jobsets::time_next_poll.le(now.unix_timestamp())),
+        )
+        .load::<(models::Jobset, models::Project)>(conn)?;
+    for (jobset, project) in due {
+        let jobset = jobsets::Jobset { jobset, project };
+        let decl = jobset.decl();
+        let evaluate = jobset
+            .jobset
+            .time_next_evaluation
+            .is_some_and(|t| t <= now.unix_timestamp());
+        if evaluate {
+            let next = decl
+                .schedule
+                .and_then(|schedule| next_time(&schedule, now).ok())
+                .map(OffsetDateTime::unix_timestamp);
+            diesel::update(schema::jobsets::table.find(jobset.jobset.id))
+                .set(schema::jobsets::time_next_evaluation.eq(next))
+                .execute(conn)?;
+        }
+        let poll = jobset
+            .jobset
+            .time_next_poll
+            .is_some_and(|t| t <= now.unix_timestamp());
+        if poll {
+            let next = decl
+                .poll
+                .and_then(|seconds| next_time(&Schedule::Interval(seconds), now).ok())
+                .map(OffsetDateTime::unix_timestamp);
+            diesel::update(schema::jobsets::table.find(jobset.jobset.id))
+                .set(schema::jobsets::time_next_poll.eq(next))
+                .execute(conn)?;
+        }
+        let id = jobset.jobset.id;
+        if !RUNNING.lock().unwrap().insert(id) {
+            tracing::warn!(
+                "skipping jobset {}, which is still being evaluated or polled",
+                jobset.handle()
+            );
+            continue;
+        }
+        RUNTIME.spawn_blocking(move || {
+            run_due(&mut POOL.get().unwrap(), &jobset, evaluate, poll);
+            RUNNING.lock().unwrap().remove(&id);
+        });
+    }
+    let (next_evaluation, next_poll) = schema::jobsets::table
+        .select((
+            diesel::dsl::min(schema::jobsets::time_next_evaluation),
+            diesel::dsl::min(schema::jobsets::time_next_poll),
+        ))
+        .first::<(Option<i64>, Option<i64>)>(conn)?;
+    let next = match (next_evaluation, next_poll) {
+        (Some(t1), Some(t2)) => Some(t1.min(t2)),
+        (t1, t2) => t1.or(t2),
+    };
+    Ok(next.map(OffsetDateTime::from_unix_timestamp).transpose()?)
+}
+
+enum Msg {
+    Wake,
+    Shutdown,
+}
+
+/// Triggers the scheduled evaluations and the polling of jobsets. The next
+/// evaluation and polling times of each jobset are stored in the database, so
+/// that schedules survive restarts.
+pub struct Scheduler {
+    sender: mpsc::UnboundedSender<Msg>,
+    watch: watch::Receiver<()>,
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (watch_send, watch) = watch::channel(());
+        RUNTIME.spawn(async move {
+            loop {
+                let next = RUNTIME
+                    .spawn_blocking(|| evaluate_due(&mut POOL.get().unwrap()))
+                    .await
+                    .unwrap()
+                    .unwrap_or_else(|e| {
+                        tracing::error!("scheduler raised error: {:?}", e);
+                        // try again later
+                        Some(OffsetDateTime::now_utc() + time::Duration::minutes(1))
+                    });
+                let sleep = async move {
+                    match next {
+                        Some(next) => {
+                            let duration = (next - OffsetDateTime::now_utc())
+                                .try_into()
+                                .unwrap_or_default();
+                            tokio::time::sleep(duration).await
+                        }
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    msg = receiver.recv() => match msg {
+                        Some(Msg::Wake) => (),
+                        Some(Msg::Shutdown) | None => break,
+                    },
+                    () = sleep => (),
+                }
+            }
+            let _watch_send = watch_send;
+        });
+        Self { sender, watch }
+    }
+
+    /// Takes into account changes to the schedules or polling intervals of
+    /// jobsets
+    pub fn wake(&self) {
+        let _ = self.sender.send(Msg::Wake);
+    }
+
+    pub async fn shutdown(&self) {
+        let _ = self.sender.send(Msg::Shutdown);
+        while self.watch.clone().changed().await.is_ok() {}
+    }
+}
+
+pub static SCHEDULER: Lazy<Scheduler> = Lazy::new(Scheduler::new);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(timestamp).unwrap()
+    }
+
+    #[test]
+    fn interval() {
+        assert_eq!(
+            next_time(&Schedule::Interval(60), at(1000)).unwrap(),
+            at(1060)
+        );
+        assert!(next_time(&Schedule::Interval(0), at(1000)).is_err());
+        assert!(next_time(&Schedule::Interval(u64::MAX), at(1000)).is_err());
+    }
+
+    #[test]
+    fn cron() {
+        // 2024-01-01 12:30:00 UTC
+        let now = at(1704112200);
+        assert_eq!(
+            next_time(&Schedule::Cron("0 * * * *".to_string()), now).unwrap(),
+            at(1704114000)
+        );
+        assert_eq!(
+            next_time(&Schedule::Cron("0 0 * * *".to_string()), now).unwrap(),
+            at(1704153600)
+        );
+        // strictly after
+        assert_eq!(
+            next_time(&Schedule::Cron("30 12 * * *".to_string()), now).unwrap(),
+            at(1704112200 + 86400)
+        );
+        assert!(next_time(&Schedule::Cron("not a schedule".to_string()), now).is_err());
+    }
+}
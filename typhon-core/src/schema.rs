@@ -13,16 +13,41 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    build_dependencies (id) {
+        build_id -> Integer,
+        dependency_id -> Integer,
+        id -> Integer,
+    }
+}
+
 diesel::table! {
     builds (id) {
+        attempt -> Integer,
         drv -> Text,
+        failed_dependency_id -> Nullable<Integer>,
         id -> Integer,
+        previous_id -> Nullable<Integer>,
         task_id -> Integer,
         time_created -> BigInt,
         uuid -> Text,
     }
 }
 
+diesel::table! {
+    deliveries (id) {
+        attempts -> Integer,
+        error -> Nullable<Text>,
+        event -> Text,
+        id -> Integer,
+        response_code -> Nullable<Integer>,
+        status -> Integer,
+        subscription_id -> Integer,
+        time_created -> BigInt,
+        time_finished -> Nullable<BigInt>,
+    }
+}
+
 diesel::table! {
     evaluations (id) {
         actions_path -> Nullable<Text>,
@@ -41,11 +66,15 @@ diesel::table! {
     jobs (id) {
         dist -> Bool,
         drv -> Text,
+        eval_error -> Nullable<Text>,
         evaluation_id -> Integer,
         id -> Integer,
+        max_silent -> Nullable<BigInt>,
         name -> Text,
         out -> Text,
+        outputs -> Text,
         system -> Text,
+        timeout -> Nullable<BigInt>,
         tries -> Integer,
     }
 }
@@ -55,7 +84,12 @@ diesel::table! {
         flake -> Bool,
         id -> Integer,
         name -> Text,
+        poll -> Nullable<Integer>,
+        priority -> Integer,
         project_id -> Integer,
+        schedule -> Nullable<Text>,
+        time_next_evaluation -> Nullable<BigInt>,
+        time_next_poll -> Nullable<BigInt>,
         url -> Text,
     }
 }
@@ -63,6 +97,7 @@ diesel::table! {
 diesel::table! {
     logs (id) {
         id -> Integer,
+        records -> Nullable<Text>,
         stderr -> Nullable<Text>,
     }
 }
@@ -70,6 +105,8 @@ diesel::table! {
 diesel::table! {
     projects (id) {
         actions_path -> Nullable<Text>,
+        build_max_silent -> Nullable<BigInt>,
+        build_timeout -> Nullable<BigInt>,
         description -> Text,
         flake -> Bool,
         homepage -> Text,
@@ -77,12 +114,23 @@ diesel::table! {
         key -> Text,
         last_refresh_task_id -> Nullable<Integer>,
         name -> Text,
+        notify_emails -> Text,
+        notify_maintainers -> Bool,
         title -> Text,
         url -> Text,
         url_locked -> Text,
     }
 }
 
+diesel::table! {
+    roles (id) {
+        id -> Integer,
+        project_id -> Integer,
+        role -> Integer,
+        user_id -> Integer,
+    }
+}
+
 diesel::table! {
     runs (id) {
         begin_id -> Nullable<Integer>,
@@ -91,7 +139,22 @@ diesel::table! {
         id -> Integer,
         job_id -> Integer,
         num -> Integer,
+        previous_status -> Nullable<Integer>,
+        status -> Integer,
         time_created -> BigInt,
+        time_finished -> Nullable<BigInt>,
+        time_started -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    subscriptions (id) {
+        id -> Integer,
+        name -> Text,
+        project_id -> Nullable<Integer>,
+        secret -> Text,
+        time_created -> BigInt,
+        url -> Text,
     }
 }
 
@@ -105,26 +168,61 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    tokens (id) {
+        hash -> Text,
+        id -> Integer,
+        kinds -> Nullable<Text>,
+        name -> Text,
+        project_id -> Nullable<Integer>,
+        time_created -> BigInt,
+        time_expires -> Nullable<BigInt>,
+        time_last_used -> Nullable<BigInt>,
+        user_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        admin -> Bool,
+        id -> Integer,
+        name -> Text,
+        password -> Text,
+    }
+}
+
 diesel::joinable!(actions -> projects (project_id));
 diesel::joinable!(actions -> tasks (task_id));
 diesel::joinable!(builds -> tasks (task_id));
+diesel::joinable!(deliveries -> subscriptions (subscription_id));
 diesel::joinable!(evaluations -> projects (project_id));
 diesel::joinable!(evaluations -> tasks (task_id));
 diesel::joinable!(jobs -> evaluations (evaluation_id));
 diesel::joinable!(jobsets -> projects (project_id));
 diesel::joinable!(projects -> tasks (last_refresh_task_id));
+diesel::joinable!(roles -> projects (project_id));
+diesel::joinable!(roles -> users (user_id));
 diesel::joinable!(runs -> builds (build_id));
 diesel::joinable!(runs -> jobs (job_id));
+diesel::joinable!(subscriptions -> projects (project_id));
 diesel::joinable!(tasks -> logs (log_id));
+diesel::joinable!(tokens -> projects (project_id));
+diesel::joinable!(tokens -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     actions,
+    build_dependencies,
     builds,
+    deliveries,
     evaluations,
     jobs,
     jobsets,
     logs,
     projects,
+    roles,
     runs,
+    subscriptions,
     tasks,
+    tokens,
+    users,
 );
@@ -0,0 +1,577 @@
+use crate::actions;
+use crate::error::Error;
+use crate::evaluations;
+use crate::handles;
+use crate::models;
+use crate::projects;
+use crate::responses;
+use crate::schema;
+use crate::Conn;
+use crate::DbPool;
+use crate::EVENT_LOGGER;
+use crate::POOL;
+use crate::RUNTIME;
+
+use typhon_types::data::TaskStatusKind;
+use typhon_types::requests::SubscriptionDecl;
+use typhon_types::Event;
+
+use diesel::prelude::*;
+use once_cell::sync::Lazy;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+
+/// Number of attempts after which a delivery is marked as failed
+const MAX_ATTEMPTS: i32 = 5;
+
+/// Number of deliveries kept in the log of each subscription
+const KEPT_DELIVERIES: i64 = 100;
+
+/// Timeout of a single delivery attempt
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct Subscription {
+    pub subscription: models::Subscription,
+    pub project: Option<models::Project>,
+}
+
+impl Subscription {
+    pub fn create(conn: &mut Conn, name: &String, decl: &SubscriptionDecl) -> Result<(), Error> {
+        if Self::get(conn, name).is_ok() {
+            return Err(Error::SubscriptionAlreadyExists(name.clone()));
+        }
+        match reqwest::Url::parse(&decl.url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (),
+            _ => return Err(Error::BadSubscriptionUrl(decl.url.clone())),
+        }
+        let project_id = match &decl.project {
+            Some(handle) => Some(projects::Project::get(conn, handle)?.project.id),
+            None => None,
+        };
+        let new_subscription = models::NewSubscription {
+            name,
+            project_id,
+            secret: &decl.secret,
+            time_created: OffsetDateTime::now_utc().unix_timestamp(),
+            url: &decl.url,
+        };
+        diesel::insert_into(schema::subscriptions::table)
+            .values(&new_subscription)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn delete(&self, conn: &mut Conn) -> Result<(), Error> {
+        conn.transaction::<(), Error, _>(|conn| {
+            diesel::delete(
+                schema::deliveries::table
+                    .filter(schema::deliveries::subscription_id.eq(self.subscription.id)),
+            )
+            .execute(conn)?;
+            diesel::delete(&self.subscription).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    pub fn get(conn: &mut Conn, name: &String) -> Result<Self, Error> {
+        schema::subscriptions::table
+            .left_join(schema::projects::table)
+            .filter(schema::subscriptions::name.eq(name))
+            .first::<(models::Subscription, Option<models::Project>)>(conn)
+            .optional()?
+            .map(|(subscription, project)| Self {
+                subscription,
+                project,
+            })
+            .ok_or(Error::SubscriptionNotFound(name.clone()))
+    }
+
+    /// Lists the subscriptions of a project, or the global subscriptions if
+    /// `project` is `None`
+    pub fn list(conn: &mut Conn, project: Option<&handles::Project>) -> Result<Vec<Self>, Error> {
+        let query = schema::subscriptions::table
+            .left_join(schema::projects::table)
+            .order(schema::subscriptions::name.asc())
+            .into_boxed();
+        let query = match project {
+            Some(handle) => query.filter(schema::projects::name.eq(&handle.name)),
+            None => query.filter(schema::subscriptions::project_id.is_null()),
+        };
+        Ok(query
+            .load::<(models::Subscription, Option<models::Project>)>(conn)?
+            .into_iter()
+            .map(|(subscription, project)| Self {
+                subscription,
+                project,
+            })
+            .collect())
+    }
+
+    pub fn info(&self) -> Result<responses::SubscriptionInfo, Error> {
+        Ok(responses::SubscriptionInfo {
+            name: self.subscription.name.clone(),
+            url: self.subscription.url.clone(),
+            project: self
+                .project
+                .as_ref()
+                .map(|project| handles::project(project.name.clone())),
+            time_created: OffsetDateTime::from_unix_timestamp(self.subscription.time_created)?,
+        })
+    }
+
+    /// Returns the latest deliveries of the subscription, most recent first
+    pub fn deliveries(&self, conn: &mut Conn) -> Result<Vec<responses::DeliveryInfo>, Error> {
+        let from_timestamp = |t| OffsetDateTime::from_unix_timestamp(t);
+        schema::deliveries::table
+            .filter(schema::deliveries::subscription_id.eq(self.subscription.id))
+            .order(schema::deliveries::id.desc())
+            .limit(KEPT_DELIVERIES)
+            .load::<models::Delivery>(conn)?
+            .into_iter()
+            .map(|delivery| {
+                Ok(responses::DeliveryInfo {
+                    event: serde_json::from_str(&delivery.event).unwrap(),
+                    status: delivery.status.try_into().unwrap(),
+                    attempts: delivery.attempts as u32,
+                    response_code: delivery.response_code.map(|code| code as u16),
+                    error: delivery.error,
+                    time_created: from_timestamp(delivery.time_created)?,
+                    time_finished: delivery.time_finished.map(from_timestamp).transpose()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Returns the name of the project an event relates to. Builds are shared
+/// between projects, so their events are only sent to global subscriptions.
+fn event_project(conn: &mut Conn, event: &Event) -> Result<Option<String>, Error> {
+    fn evaluation_project(
+        conn: &mut Conn,
+        handle: &handles::Evaluation,
+    ) -> Result<Option<String>, Error> {
+        match evaluations::Evaluation::get(conn, handle) {
+            Ok(evaluation) => Ok(Some(evaluation.project.name)),
+            Err(Error::EvaluationNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    match event {
+        Event::Ping | Event::BuildNew(_) | Event::BuildFinished(_) | Event::BuildProgress(_) => {
+            Ok(None)
+        }
+        Event::ProjectNew(handle)
+        | Event::ProjectDeleted(handle)
+        | Event::ProjectUpdated(handle) => Ok(Some(handle.name.clone())),
+        Event::EvaluationNew(handle) | Event::EvaluationFinished(handle) => {
+            evaluation_project(conn, handle)
+        }
+        Event::RunNew(handle) | Event::RunUpdated(handle) => {
+            evaluation_project(conn, &handle.job.evaluation)
+        }
+        Event::JobBroken(handle) | Event::JobFixed(handle) => {
+            evaluation_project(conn, &handle.evaluation)
+        }
+        Event::ActionNew(handle) | Event::ActionFinished(handle) => {
+            match actions::Action::get(conn, handle) {
+                Ok(action) => Ok(Some(action.project.name)),
+                Err(Error::ActionNotFound(_)) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+/// Records a pending delivery of the event for each matching subscription
+/// and returns their ids
+fn queue(conn: &mut Conn, event: &Event) -> Result<Vec<i32>, Error> {
+    let project = event_project(conn, event)?;
+    let subscriptions = schema::subscriptions::table
+        .left_join(schema::projects::table)
+        .filter(
+            schema::subscriptions::project_id
+                .is_null()
+                .or(schema::projects::name.nullable().eq(project)),
+        )
+        .select(schema::subscriptions::id)
+        .load::<i32>(conn)?;
+    let event = serde_json::to_string(event).unwrap();
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    conn.transaction::<_, Error, _>(|conn| {
+        let mut ids = Vec::new();
+        for subscription_id in subscriptions {
+            let new_delivery = models::NewDelivery {
+                event: &event,
+                subscription_id,
+                time_created: now,
+            };
+            let id = diesel::insert_into(schema::deliveries::table)
+                .values(&new_delivery)
+                .returning(schema::deliveries::id)
+                .get_result::<i32>(conn)?;
+            // prune the delivery log
+            let oldest_kept = schema::deliveries::table
+                .filter(schema::deliveries::subscription_id.eq(subscription_id))
+                .order(schema::deliveries::id.desc())
+                .offset(KEPT_DELIVERIES - 1)
+                .select(schema::deliveries::id)
+                .first::<i32>(conn)
+                .optional()?;
+            if let Some(oldest_kept) = oldest_kept {
+                diesel::delete(
+                    schema::deliveries::table
+                        .filter(schema::deliveries::subscription_id.eq(subscription_id))
+                        .filter(schema::deliveries::id.lt(oldest_kept))
+                        .filter(schema::deliveries::status.ne(i32::from(TaskStatusKind::Pending))),
+                )
+                .execute(conn)?;
+            }
+            ids.push(id);
+        }
+        Ok(ids)
+    })
+}
+
+/// Returns the ids of the deliveries that were interrupted by a restart
+fn pending(conn: &mut Conn) -> Result<Vec<i32>, Error> {
+    Ok(schema::deliveries::table
+        .filter(schema::deliveries::status.eq(i32::from(TaskStatusKind::Pending)))
+        .order(schema::deliveries::id.asc())
+        .select(schema::deliveries::id)
+        .load::<i32>(conn)?)
+}
+
+/// Signs a payload with the secret of a subscription
+fn signature(secret: &str, payload: &str) -> String {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Returns the delay before the next attempt of a delivery that failed
+/// `attempts` times: 10s, 20s, 40s, ...
+fn backoff(attempts: i32) -> std::time::Duration {
+    let factor = u32::try_from(attempts.saturating_sub(1))
+        .ok()
+        .and_then(|shift| 1u64.checked_shl(shift))
+        .unwrap_or(u64::MAX);
+    std::time::Duration::from_secs(10u64.saturating_mul(factor))
+}
+
+/// Posts an event to the URL of a subscription, retrying after the delay
+/// given by `backoff` until it succeeds or the maximum number of attempts is
+/// reached. Each attempt is recorded in the database.
+async fn deliver(
+    client: reqwest::Client,
+    pool: DbPool,
+    id: i32,
+    backoff: fn(i32) -> std::time::Duration,
+) {
+    let res: Result<(), Error> = async {
+        loop {
+            let pool_ = pool.clone();
+            let (delivery, subscription) = RUNTIME
+                .spawn_blocking(move || {
+                    schema::deliveries::table
+                        .inner_join(schema::subscriptions::table)
+                        .filter(schema::deliveries::id.eq(id))
+                        .first::<(models::Delivery, models::Subscription)>(
+                            &mut pool_.get().unwrap(),
+                        )
+                })
+                .await
+                .unwrap()?;
+            if delivery.status != i32::from(TaskStatusKind::Pending) {
+                return Ok(());
+            }
+            let res = client
+                .post(&subscription.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header("X-Typhon-Delivery", id.to_string())
+                .header(
+                    "X-Typhon-Signature",
+                    signature(&subscription.secret, &delivery.event),
+                )
+                .body(delivery.event.clone())
+                .send()
+                .await;
+            let (response_code, error) = match res {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16() as i32), None)
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16() as i32),
+                    Some(format!("unexpected status {}", response.status())),
+                ),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let attempts = delivery.attempts + 1;
+            let status = match error {
+                None => Some(TaskStatusKind::Success),
+                Some(_) if attempts >= MAX_ATTEMPTS => Some(TaskStatusKind::Failure),
+                Some(_) => None,
+            };
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            let pool_ = pool.clone();
+            RUNTIME
+                .spawn_blocking(move || {
+                    diesel::update(&delivery)
+                        .set((
+                            schema::deliveries::attempts.eq(attempts),
+                            schema::deliveries::error.eq(error),
+                            schema::deliveries::response_code.eq(response_code),
+                            schema::deliveries::status
+                                .eq(i32::from(status.unwrap_or(TaskStatusKind::Pending))),
+                            schema::deliveries::time_finished.eq(status.map(|_| now)),
+                        ))
+                        .execute(&mut pool_.get().unwrap())
+                })
+                .await
+                .unwrap()?;
+            if status.is_some() {
+                return Ok(());
+            }
+            tokio::time::sleep(backoff(attempts)).await;
+        }
+    }
+    .await;
+    if let Err(e) = res {
+        tracing::error!("delivery {} raised error: {:?}", id, e);
+    }
+}
+
+enum Msg {
+    Shutdown,
+}
+
+/// Sends the events emitted by Typhon to the matching subscriptions.
+/// Deliveries are stored in the database before being attempted, so that
+/// pending ones are resumed after a restart.
+pub struct Dispatcher {
+    sender: mpsc::UnboundedSender<Msg>,
+    watch: watch::Receiver<()>,
+}
+
+impl Dispatcher {
+    fn new() -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (watch_send, watch) = watch::channel(());
+        let mut events = EVENT_LOGGER.subscribe();
+        RUNTIME.spawn(async move {
+            let client = reqwest::Client::builder().timeout(TIMEOUT).build().unwrap();
+            let mut deliveries = JoinSet::new();
+            match RUNTIME
+                .spawn_blocking(|| pending(&mut POOL.get().unwrap()))
+                .await
+                .unwrap()
+            {
+                Ok(ids) => {
+                    for id in ids {
+                        deliveries.spawn(deliver(client.clone(), POOL.clone(), id, backoff));
+                    }
+                }
+                Err(e) => tracing::error!("failed to resume pending deliveries: {:?}", e),
+            }
+            loop {
+                tokio::select! {
+                    msg = receiver.recv() => match msg {
+                        Some(Msg::Shutdown) | None => break,
+                    },
+                    event = events.recv() => match event {
+                        // progress events are too frequent to be delivered
+                        Some(Event::Ping | Event::BuildProgress(_)) => (),
+                        Some(event) => {
+                            let res = RUNTIME
+                                .spawn_blocking(move || queue(&mut POOL.get().unwrap(), &event))
+                                .await
+                                .unwrap();
+                            match res {
+                                Ok(ids) => {
+                                    for id in ids {
+                                        deliveries.spawn(deliver(client.clone(), POOL.clone(), id, backoff));
+                                    }
+                                }
+                                Err(e) => tracing::error!("failed to queue deliveries: {:?}", e),
+                            }
+                        }
+                        None => break,
+                    },
+                    Some(_) = deliveries.join_next(), if !deliveries.is_empty() => (),
+                }
+            }
+            // pending deliveries are resumed at the next start
+            deliveries.abort_all();
+            let _watch_send = watch_send;
+        });
+        Self { sender, watch }
+    }
+
+    pub async fn shutdown(&self) {
+        let _ = self.sender.send(Msg::Shutdown);
+        while self.watch.clone().changed().await.is_ok() {}
+    }
+}
+
+pub static DISPATCHER: Lazy<Dispatcher> = Lazy::new(Dispatcher::new);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            signature("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let delays: Vec<u64> = (1..MAX_ATTEMPTS).map(|n| backoff(n).as_secs()).collect();
+        assert_eq!(delays, vec![10, 20, 40, 80]);
+    }
+
+    #[test]
+    fn backoff_saturates() {
+        assert_eq!(backoff(64).as_secs(), u64::MAX);
+        assert_eq!(backoff(i32::MAX).as_secs(), u64::MAX);
+    }
+
+    fn subscribe(conn: &mut Conn) -> i32 {
+        subscribe_url(conn, "http://localhost")
+    }
+
+    fn subscribe_url(conn: &mut Conn, url: &str) -> i32 {
+        diesel::insert_into(schema::subscriptions::table)
+            .values(&models::NewSubscription {
+                name: "test",
+                project_id: None,
+                secret: "secret",
+                time_created: 0,
+                url,
+            })
+            .returning(schema::subscriptions::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    fn count(conn: &mut Conn, status: Option<TaskStatusKind>) -> i64 {
+        let mut query = schema::deliveries::table.count().into_boxed();
+        if let Some(status) = status {
+            query = query.filter(schema::deliveries::status.eq(i32::from(status)));
+        }
+        query.get_result(conn).unwrap()
+    }
+
+    #[test]
+    fn queue_prunes_finished_deliveries() {
+        let conn = &mut crate::test_conn();
+        subscribe(conn);
+        for _ in 0..KEPT_DELIVERIES {
+            queue(conn, &Event::Ping).unwrap();
+        }
+        diesel::update(schema::deliveries::table)
+            .set(schema::deliveries::status.eq(i32::from(TaskStatusKind::Success)))
+            .execute(conn)
+            .unwrap();
+        for _ in 0..5 {
+            queue(conn, &Event::Ping).unwrap();
+        }
+        assert_eq!(count(conn, None), KEPT_DELIVERIES);
+        assert_eq!(count(conn, Some(TaskStatusKind::Pending)), 5);
+    }
+
+    #[test]
+    fn queue_keeps_pending_deliveries() {
+        let conn = &mut crate::test_conn();
+        subscribe(conn);
+        for _ in 0..KEPT_DELIVERIES + 5 {
+            queue(conn, &Event::Ping).unwrap();
+        }
+        assert_eq!(count(conn, None), KEPT_DELIVERIES + 5);
+        assert_eq!(pending(conn).unwrap().len() as i64, KEPT_DELIVERIES + 5);
+    }
+
+    /// Answers the first request with a server error and the second one with
+    /// a success, and sends back the requests it received
+    async fn server(listener: tokio::net::TcpListener, sender: mpsc::UnboundedSender<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        for status in ["500 Internal Server Error", "200 OK"] {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                let complete = text.find("\r\n\r\n").is_some_and(|end| {
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|line| {
+                            let line = line.to_lowercase();
+                            line.strip_prefix("content-length:")
+                                .map(|n| n.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    request.len() >= end + 4 + length
+                });
+                if complete || n == 0 {
+                    break;
+                }
+            }
+            let response =
+                format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+            stream.write_all(response.as_bytes()).await.unwrap();
+            sender.send(String::from_utf8(request).unwrap()).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_retries_until_success() {
+        let pool = crate::test_pool();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let id = {
+            let conn = &mut pool.get().unwrap();
+            subscribe_url(conn, &url);
+            queue(conn, &Event::Ping).unwrap()[0]
+        };
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(server(listener, sender));
+
+        deliver(reqwest::Client::new(), pool.clone(), id, |_| {
+            std::time::Duration::ZERO
+        })
+        .await;
+
+        let event = serde_json::to_string(&Event::Ping).unwrap();
+        for _ in 0..2 {
+            let request = receiver.recv().await.unwrap();
+            let (head, body) = request.split_once("\r\n\r\n").unwrap();
+            assert!(head.starts_with("POST /hook "));
+            let head = head.to_lowercase();
+            assert!(head.contains(&format!("x-typhon-delivery: {id}")));
+            assert!(head.contains(&format!(
+                "x-typhon-signature: {}",
+                signature("secret", &event)
+            )));
+            assert_eq!(body, event);
+        }
+        let delivery = schema::deliveries::table
+            .find(id)
+            .first::<models::Delivery>(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.status, i32::from(TaskStatusKind::Success));
+        assert_eq!(delivery.response_code, Some(200));
+        assert_eq!(delivery.error, None);
+        assert!(delivery.time_finished.is_some());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::Error;
 use crate::log_event;
+use crate::logs;
 use crate::models;
 use crate::schema;
 use crate::Conn;
@@ -7,7 +8,7 @@ use crate::POOL;
 use crate::{LOGS, TASKS};
 
 use typhon_types::data::TaskStatusKind;
-use typhon_types::responses::TaskStatus;
+use typhon_types::responses::{LogRecord, TaskStatus};
 use typhon_types::Event;
 
 use diesel::prelude::*;
@@ -39,20 +40,20 @@ impl Task {
         TASKS.cancel(self.task.id);
     }
 
-    pub fn log(&self, conn: &mut Conn) -> Result<Option<impl Stream<Item = String>>, Error> {
+    pub fn log(&self, conn: &mut Conn) -> Result<Option<impl Stream<Item = LogRecord>>, Error> {
         let stream = LOGS.listen(&self.task.id);
-        let stderr = schema::logs::dsl::logs
-            .find(self.task.log_id)
-            .select(schema::logs::stderr)
-            .first::<Option<String>>(conn)?;
+        let stored = match stream {
+            Some(_) => None,
+            None => Some(logs::storage::storage().read(conn, self.task.log_id)?),
+        };
         Ok(Some(async_stream::stream! {
             if let Some(stream) = stream {
-                for await line in stream {
-                    yield line;
+                for await record in stream {
+                    yield record;
                 }
-            } else if let Some(stderr) = stderr {
-                for line in stderr.split('\n') {
-                    yield line.to_string();
+            } else if let Some(stored) = stored {
+                for await record in logs::stream(stored) {
+                    yield record;
                 }
             }
         }))
@@ -60,7 +61,7 @@ impl Task {
 
     pub fn new(conn: &mut Conn) -> Result<Self, Error> {
         let log = diesel::insert_into(schema::logs::dsl::logs)
-            .values(models::NewLog { stderr: None })
+            .values(models::NewLog { records: None })
             .get_result::<models::Log>(conn)?;
         let new_task = models::NewTask {
             log_id: log.id,
@@ -75,7 +76,7 @@ impl Task {
     pub fn run<
         T: Send + 'static,
         O: Future<Output = T> + Send + 'static,
-        F: (FnOnce(mpsc::UnboundedSender<String>) -> O) + Send + 'static,
+        F: (FnOnce(mpsc::UnboundedSender<LogRecord>) -> O) + Send + 'static,
         G: (FnOnce(Option<T>) -> (TaskStatusKind, Event)) + Send + Sync + 'static,
     >(
         &self,
@@ -92,8 +93,8 @@ impl Task {
         let run = async move {
             LOGS.init(&id);
             let (res, ()) = tokio::join!(run(sender), async move {
-                while let Some(line) = receiver.recv().await {
-                    LOGS.send_line(&id, line);
+                while let Some(record) = receiver.recv().await {
+                    LOGS.send_line(&id, record);
                 }
             },);
             res
@@ -104,13 +105,14 @@ impl Task {
                 let mut conn = POOL.get().unwrap();
                 let (status_kind, event) = finish(res);
                 let time_finished = OffsetDateTime::now_utc();
-                let stderr = LOGS.remove(&id).unwrap_or(String::new()); // FIXME
+                let records = LOGS.remove(&id).unwrap_or_default();
                 let status = status_kind.into_task_status(start, Some(time_finished));
                 task.set_status(&mut conn, status).unwrap();
-                diesel::update(schema::logs::table.filter(schema::logs::id.eq(task.task.log_id)))
-                    .set(schema::logs::stderr.eq(stderr))
-                    .execute(&mut conn)
-                    .unwrap(); // TODO: handle error properly
+                if let Err(e) =
+                    logs::storage::storage().write(&mut conn, task.task.log_id, &records)
+                {
+                    tracing::error!("failed to store the log of task {}: {}", id, e);
+                }
                 log_event(event);
                 None::<()>
             }
@@ -0,0 +1,254 @@
+use crate::error::Error;
+use crate::handles;
+use crate::models;
+use crate::projects;
+use crate::responses;
+use crate::schema;
+use crate::Conn;
+
+use typhon_types::requests::{RequestKind, TokenDecl};
+
+use diesel::prelude::*;
+use time::OffsetDateTime;
+
+#[derive(Clone)]
+pub struct Token {
+    pub token: models::Token,
+    pub project: Option<models::Project>,
+}
+
+/// Tokens are random, so a fast hash is enough to store them at rest
+fn hash(secret: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+impl Token {
+    /// Authenticates a secret sent by a client, recording the time of use.
+    /// Returns `None` if the token does not exist or has expired.
+    pub fn authenticate(conn: &mut Conn, secret: &str) -> Result<Option<Self>, Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let token = schema::tokens::table
+            .left_join(schema::projects::table)
+            .filter(schema::tokens::hash.eq(hash(secret)))
+            .first::<(models::Token, Option<models::Project>)>(conn)
+            .optional()?
+            .map(|(token, project)| Self { token, project })
+            .filter(|token| token.token.time_expires.is_none_or(|t| now < t));
+        if let Some(token) = &token {
+            diesel::update(&token.token)
+                .set(schema::tokens::time_last_used.eq(now))
+                .execute(conn)?;
+        }
+        Ok(token)
+    }
+
+    /// Creates a token for a user (or for the administrator if `user_id` is
+    /// `None`) and returns its secret. The secret is not stored and cannot
+    /// be retrieved afterwards.
+    pub fn create(
+        conn: &mut Conn,
+        user_id: Option<i32>,
+        name: &String,
+        decl: &TokenDecl,
+    ) -> Result<String, Error> {
+        use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+        if Self::get(conn, user_id, name).is_ok() {
+            return Err(Error::TokenAlreadyExists(name.clone()));
+        }
+        let project_id = match &decl.project {
+            Some(handle) => Some(projects::Project::get(conn, handle)?.project.id),
+            None => None,
+        };
+        let kinds = decl
+            .kinds
+            .as_ref()
+            .map(|kinds| serde_json::to_string(kinds).unwrap());
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let secret = hex::encode(bytes);
+        let new_token = models::NewToken {
+            hash: &hash(&secret),
+            kinds: kinds.as_deref(),
+            name,
+            project_id,
+            time_created: OffsetDateTime::now_utc().unix_timestamp(),
+            time_expires: decl.expires.map(OffsetDateTime::unix_timestamp),
+            user_id,
+        };
+        diesel::insert_into(schema::tokens::table)
+            .values(&new_token)
+            .execute(conn)?;
+        Ok(secret)
+    }
+
+    pub fn delete(&self, conn: &mut Conn) -> Result<(), Error> {
+        diesel::delete(&self.token).execute(conn)?;
+        Ok(())
+    }
+
+    pub fn get(conn: &mut Conn, user_id: Option<i32>, name: &String) -> Result<Self, Error> {
+        Self::list(conn, user_id)?
+            .into_iter()
+            .find(|token| token.token.name == *name)
+            .ok_or(Error::TokenNotFound(name.clone()))
+    }
+
+    pub fn get_by_id(conn: &mut Conn, id: i32) -> Result<Option<Self>, Error> {
+        Ok(schema::tokens::table
+            .left_join(schema::projects::table)
+            .filter(schema::tokens::id.eq(id))
+            .first::<(models::Token, Option<models::Project>)>(conn)
+            .optional()?
+            .map(|(token, project)| Self { token, project }))
+    }
+
+    /// Lists the tokens of a user (or of the administrator if `user_id` is
+    /// `None`)
+    pub fn list(conn: &mut Conn, user_id: Option<i32>) -> Result<Vec<Self>, Error> {
+        let query = schema::tokens::table
+            .left_join(schema::projects::table)
+            .order(schema::tokens::time_created.desc())
+            .into_boxed();
+        let query = match user_id {
+            Some(user_id) => query.filter(schema::tokens::user_id.eq(user_id)),
+            None => query.filter(schema::tokens::user_id.is_null()),
+        };
+        Ok(query
+            .load::<(models::Token, Option<models::Project>)>(conn)?
+            .into_iter()
+            .map(|(token, project)| Self { token, project })
+            .collect())
+    }
+
+    pub fn info(&self) -> Result<responses::TokenInfo, Error> {
+        let from_timestamp = |t| OffsetDateTime::from_unix_timestamp(t);
+        Ok(responses::TokenInfo {
+            name: self.token.name.clone(),
+            project: self
+                .project
+                .as_ref()
+                .map(|project| handles::project(project.name.clone())),
+            kinds: self.kinds()?,
+            time_created: from_timestamp(self.token.time_created)?,
+            expires: self.token.time_expires.map(from_timestamp).transpose()?,
+            last_used: self.token.time_last_used.map(from_timestamp).transpose()?,
+        })
+    }
+
+    /// Checks the restrictions of the token. `project` is the project the
+    /// request acts upon, if any.
+    pub fn allows(
+        &self,
+        kind: RequestKind,
+        project: Option<&handles::Project>,
+    ) -> Result<bool, Error> {
+        let kind_allowed = self.kinds()?.is_none_or(|kinds| kinds.contains(&kind));
+        let project_allowed = match &self.project {
+            Some(restriction) => project.is_some_and(|project| project.name == restriction.name),
+            None => true,
+        };
+        Ok(kind_allowed && project_allowed)
+    }
+
+    fn kinds(&self) -> Result<Option<Vec<RequestKind>>, Error> {
+        self.token
+            .kinds
+            .as_ref()
+            .map(|kinds| {
+                serde_json::from_str(kinds).map_err(|_| Error::InvalidTokenKinds(kinds.clone()))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create(conn: &mut Conn, decl: &TokenDecl) -> Token {
+        let secret = Token::create(conn, None, &"token".to_string(), decl).unwrap();
+        Token::authenticate(conn, &secret).unwrap().unwrap()
+    }
+
+    fn project(conn: &mut Conn, name: &str) -> handles::Project {
+        diesel::insert_into(schema::projects::table)
+            .values(&models::NewProject {
+                flake: true,
+                key: "",
+                name,
+                url: "github:typhon-ci/typhon",
+            })
+            .execute(conn)
+            .unwrap();
+        handles::project(name.to_string())
+    }
+
+    #[test]
+    fn allows_kinds() {
+        let conn = &mut crate::test_conn();
+        let p = project(conn, "p");
+        let token = create(
+            conn,
+            &TokenDecl {
+                project: None,
+                kinds: Some(vec![RequestKind::JobsetEvaluate, RequestKind::AgentPoll]),
+                expires: None,
+            },
+        );
+        assert!(token.allows(RequestKind::JobsetEvaluate, Some(&p)).unwrap());
+        assert!(token.allows(RequestKind::AgentPoll, None).unwrap());
+        assert!(!token.allows(RequestKind::ProjectDelete, Some(&p)).unwrap());
+    }
+
+    #[test]
+    fn allows_project() {
+        let conn = &mut crate::test_conn();
+        let p = project(conn, "p");
+        let q = project(conn, "q");
+        let token = create(
+            conn,
+            &TokenDecl {
+                project: Some(p.clone()),
+                kinds: None,
+                expires: None,
+            },
+        );
+        assert!(token.allows(RequestKind::ProjectDelete, Some(&p)).unwrap());
+        assert!(!token.allows(RequestKind::ProjectDelete, Some(&q)).unwrap());
+        // requests that do not act on a project are out of its reach
+        assert!(!token.allows(RequestKind::CreateProject, None).unwrap());
+    }
+
+    #[test]
+    fn expired_token() {
+        let conn = &mut crate::test_conn();
+        let decl = TokenDecl {
+            project: None,
+            kinds: None,
+            expires: Some(OffsetDateTime::now_utc() - time::Duration::hours(1)),
+        };
+        let secret = Token::create(conn, None, &"token".to_string(), &decl).unwrap();
+        assert!(Token::authenticate(conn, &secret).unwrap().is_none());
+        assert!(Token::authenticate(conn, "not a secret").unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_kinds_are_an_error() {
+        let conn = &mut crate::test_conn();
+        let mut token = create(
+            conn,
+            &TokenDecl {
+                project: None,
+                kinds: None,
+                expires: None,
+            },
+        );
+        token.token.kinds = Some("[\"unknown\"]".to_string());
+        assert!(matches!(
+            token.allows(RequestKind::Search, None),
+            Err(Error::InvalidTokenKinds(_)),
+        ));
+    }
+}
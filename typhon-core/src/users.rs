@@ -0,0 +1,148 @@
+use crate::error::Error;
+use crate::handles;
+use crate::models;
+use crate::schema;
+use crate::Conn;
+
+use typhon_types::data;
+
+use argon2::PasswordHash;
+use diesel::prelude::*;
+
+use std::collections::HashMap;
+
+#[derive(Clone)]
+pub struct User {
+    pub user: models::User,
+}
+
+impl User {
+    pub fn create(
+        conn: &mut Conn,
+        name: &String,
+        decl: &typhon_types::requests::UserDecl,
+    ) -> Result<(), Error> {
+        use argon2::password_hash::{rand_core::OsRng, SaltString};
+        use argon2::{Argon2, PasswordHasher};
+
+        // user names follow the same rules as project names
+        if !handles::project(name.clone()).legal() {
+            return Err(Error::IllegalUserName(name.clone()));
+        }
+        match Self::get(conn, name) {
+            Ok(_) => Err(Error::UserAlreadyExists(name.clone())),
+            Err(_) => {
+                let salt = SaltString::generate(&mut OsRng);
+                let hash = Argon2::default()
+                    .hash_password(decl.password.as_bytes(), &salt)
+                    .map_err(|e| Error::InvalidPassword(e.to_string()))?
+                    .to_string();
+                let new_user = models::NewUser {
+                    admin: decl.admin,
+                    name,
+                    password: &hash,
+                };
+                diesel::insert_into(schema::users::table)
+                    .values(&new_user)
+                    .execute(conn)?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn delete(&self, conn: &mut Conn) -> Result<(), Error> {
+        conn.transaction::<(), Error, _>(|conn| {
+            diesel::delete(schema::roles::table.filter(schema::roles::user_id.eq(self.user.id)))
+                .execute(conn)?;
+            diesel::delete(schema::tokens::table.filter(schema::tokens::user_id.eq(self.user.id)))
+                .execute(conn)?;
+            diesel::delete(&self.user).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    pub fn get(conn: &mut Conn, name: &String) -> Result<Self, Error> {
+        let user = schema::users::table
+            .filter(schema::users::name.eq(name))
+            .first(conn)
+            .optional()?
+            .ok_or(Error::UserNotFound(name.clone()))?;
+        Ok(Self { user })
+    }
+
+    pub fn get_by_id(conn: &mut Conn, id: i32) -> Result<Self, Error> {
+        let user = schema::users::table.find(id).first(conn)?;
+        Ok(Self { user })
+    }
+
+    pub fn info(&self, conn: &mut Conn) -> Result<data::User, Error> {
+        let roles = schema::roles::table
+            .inner_join(schema::projects::table)
+            .filter(schema::roles::user_id.eq(self.user.id))
+            .select((schema::projects::name, schema::roles::role))
+            .load::<(String, i32)>(conn)?
+            .into_iter()
+            .map(|(project, role)| Ok((handles::project(project), Self::parse_role(role)?)))
+            .collect::<Result<HashMap<_, _>, Error>>()?;
+        Ok(data::User::Account {
+            name: self.user.name.clone(),
+            admin: self.user.admin,
+            roles,
+        })
+    }
+
+    /// Returns the role of the user in a given project, if any
+    pub fn role(
+        &self,
+        conn: &mut Conn,
+        project: &handles::Project,
+    ) -> Result<Option<data::Role>, Error> {
+        schema::roles::table
+            .inner_join(schema::projects::table)
+            .filter(schema::roles::user_id.eq(self.user.id))
+            .filter(schema::projects::name.eq(&project.name))
+            .select(schema::roles::role)
+            .first::<i32>(conn)
+            .optional()?
+            .map(Self::parse_role)
+            .transpose()
+    }
+
+    pub fn set_role(
+        &self,
+        conn: &mut Conn,
+        project: &models::Project,
+        role: Option<data::Role>,
+    ) -> Result<(), Error> {
+        conn.transaction::<(), Error, _>(|conn| {
+            diesel::delete(
+                schema::roles::table
+                    .filter(schema::roles::user_id.eq(self.user.id))
+                    .filter(schema::roles::project_id.eq(project.id)),
+            )
+            .execute(conn)?;
+            if let Some(role) = role {
+                let new_role = models::NewRole {
+                    project_id: project.id,
+                    role: role.into(),
+                    user_id: self.user.id,
+                };
+                diesel::insert_into(schema::roles::table)
+                    .values(&new_role)
+                    .execute(conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn verify_password(&self, password: &[u8]) -> bool {
+        use argon2::{Argon2, PasswordVerifier};
+        PasswordHash::new(&self.user.password)
+            .map(|hash| Argon2::default().verify_password(password, &hash).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn parse_role(role: i32) -> Result<data::Role, Error> {
+        role.try_into().map_err(|_| Error::InvalidRole(role))
+    }
+}
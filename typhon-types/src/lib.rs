@@ -213,12 +213,69 @@ pub mod handles {
     }
 }
 pub mod data {
+    use crate::handles;
     pub use crate::task_status::TaskStatusKind;
     use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    /// The role of a user account within a project. Roles are ordered:
+    /// a role grants every permission of the roles below it. Projects are
+    /// readable by everyone, so no role is needed to see them.
+    #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    #[repr(u8)]
+    pub enum Role {
+        /// Can evaluate jobsets, cancel evaluations and rerun jobs
+        Operator = 1,
+        /// Can change the declaration of the project and refresh it
+        Maintainer = 2,
+    }
+
+    impl TryFrom<i32> for Role {
+        type Error = ();
+        fn try_from(n: i32) -> Result<Role, ()> {
+            match n {
+                1 => Ok(Self::Operator),
+                2 => Ok(Self::Maintainer),
+                _ => Err(()),
+            }
+        }
+    }
+    impl From<Role> for i32 {
+        fn from(x: Role) -> i32 {
+            (x as u8) as i32
+        }
+    }
+
+    impl std::fmt::Display for Role {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                Self::Operator => write!(f, "operator"),
+                Self::Maintainer => write!(f, "maintainer"),
+            }
+        }
+    }
+
+    /// When a jobset is evaluated automatically
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum Schedule {
+        /// Every given number of seconds
+        Interval(u64),
+        /// Following a cron expression (in UTC)
+        Cron(String),
+    }
 
-    #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum User {
+        /// The administrator authenticated with the global password
         Admin,
+        /// A user account stored in the database
+        Account {
+            name: String,
+            admin: bool,
+            roles: HashMap<handles::Project, Role>,
+        },
     }
 }
 
@@ -226,6 +283,7 @@ pub mod requests {
     use crate::handles;
 
     use serde::{Deserialize, Serialize};
+    use time::OffsetDateTime;
 
     pub mod search {
         use crate::data::TaskStatusKind;
@@ -309,12 +367,71 @@ pub mod requests {
         pub url: String,
     }
 
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct UserDecl {
+        pub password: String,
+        #[serde(default)]
+        pub admin: bool,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct TokenDecl {
+        /// Restrict the token to requests on a single project
+        #[serde(default)]
+        pub project: Option<handles::Project>,
+        /// Restrict the token to a subset of request kinds
+        #[serde(default)]
+        pub kinds: Option<Vec<RequestKind>>,
+        #[serde(default, with = "time::serde::timestamp::option")]
+        pub expires: Option<OffsetDateTime>,
+    }
+
+    /// Who is emailed when a job of a project breaks
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+    pub struct NotificationsDecl {
+        /// Addresses that receive the notifications of every job
+        #[serde(default)]
+        pub emails: Vec<String>,
+        /// Also notify the maintainers listed in `meta.maintainers` of the job
+        #[serde(default)]
+        pub maintainers: bool,
+    }
+
+    /// Limits applied to the builds of a project, in seconds. Jobs can
+    /// override them with `meta.timeout` and `meta.maxSilent`.
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+    pub struct BuildLimitsDecl {
+        /// Maximum duration of a build
+        #[serde(default)]
+        pub timeout: Option<u64>,
+        /// Maximum duration a build can go without producing any output
+        #[serde(default)]
+        pub max_silent: Option<u64>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct SubscriptionDecl {
+        /// The URL events are posted to
+        pub url: String,
+        /// The secret the payloads are signed with
+        pub secret: String,
+        /// Only send the events of a single project
+        #[serde(default)]
+        pub project: Option<handles::Project>,
+    }
+
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Project {
-        //Delete,
+        Delete,
         Info,
         Refresh,
+        SetBuildLimits(BuildLimitsDecl),
         SetDecl(ProjectDecl),
+        SetNotifications(NotificationsDecl),
+        SetRole {
+            user: String,
+            role: Option<crate::data::Role>,
+        },
         UpdateJobsets,
     }
 
@@ -327,6 +444,9 @@ pub mod requests {
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Evaluation {
         Cancel,
+        /// Compare the jobs of the evaluation with the ones of another
+        /// evaluation
+        Diff(handles::Evaluation),
         Info,
     }
 
@@ -338,9 +458,54 @@ pub mod requests {
 
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Build {
+        /// The build and the builds of its dependencies, recursively
+        Graph,
         Info,
     }
 
+    /// How a build ran by an agent ended
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum AgentBuildResult {
+        Success,
+        Failure {
+            /// Whether the failure is likely to go away by building again
+            transient: bool,
+        },
+        TimedOut,
+    }
+
+    /// The requests of the build agents
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum Agent {
+        /// Advertise the agent and ask for builds. Agents poll regularly,
+        /// which also tells the server they are alive.
+        Poll {
+            /// Systems the agent builds for
+            systems: Vec<String>,
+            /// System features the agent supports
+            features: Vec<String>,
+            /// Maximum number of new builds the agent accepts
+            capacity: u32,
+            /// Builds the agent is running
+            running: Vec<handles::Build>,
+        },
+        /// Append lines to the log of a build
+        Log {
+            build: handles::Build,
+            records: Vec<crate::responses::LogRecord>,
+        },
+        /// Report the progress of a build
+        Progress {
+            build: handles::Build,
+            progress: crate::responses::BuildProgress,
+        },
+        /// Report the result of a build
+        Finish {
+            build: handles::Build,
+            result: AgentBuildResult,
+        },
+    }
+
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Action {
         Info,
@@ -348,14 +513,17 @@ pub mod requests {
 
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Run {
-        //Cancel,
+        Cancel,
         Info,
     }
 
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub enum Request {
         Search(search::Request),
-        CreateProject { name: String, decl: ProjectDecl },
+        CreateProject {
+            name: String,
+            decl: ProjectDecl,
+        },
         Project(handles::Project, Project),
         Jobset(handles::Jobset, Jobset),
         Evaluation(handles::Evaluation, Evaluation),
@@ -363,8 +531,149 @@ pub mod requests {
         Build(handles::Build, Build),
         Action(handles::Action, Action),
         Run(handles::Run, Run),
-        Login { password: String },
+        CreateUser {
+            name: String,
+            decl: UserDecl,
+        },
+        DeleteUser {
+            name: String,
+        },
+        Login {
+            username: Option<String>,
+            password: String,
+        },
+        User,
+        CreateToken {
+            name: String,
+            decl: TokenDecl,
+        },
+        RevokeToken {
+            name: String,
+        },
+        Tokens,
+        CreateSubscription {
+            name: String,
+            decl: SubscriptionDecl,
+        },
+        DeleteSubscription {
+            name: String,
+        },
+        /// List the subscriptions of a project, or the global ones
+        Subscriptions {
+            project: Option<handles::Project>,
+        },
+        /// List the latest deliveries of a subscription
+        Deliveries {
+            name: String,
+        },
+        /// A request of the build agent `name`
+        Agent {
+            name: String,
+            req: Agent,
+        },
+    }
+
+    /// The kind of a request, without its arguments. API tokens can be
+    /// restricted to a subset of request kinds.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum RequestKind {
+        Search,
+        CreateProject,
+        ProjectDelete,
+        ProjectInfo,
+        ProjectRefresh,
+        ProjectSetBuildLimits,
+        ProjectSetDecl,
+        ProjectSetNotifications,
+        ProjectSetRole,
+        ProjectUpdateJobsets,
+        JobsetEvaluate,
+        JobsetInfo,
+        EvaluationCancel,
+        EvaluationDiff,
+        EvaluationInfo,
+        JobInfo,
+        JobRerun,
+        BuildGraph,
+        BuildInfo,
+        ActionInfo,
+        RunCancel,
+        RunInfo,
+        CreateUser,
+        DeleteUser,
+        Login,
         User,
+        CreateToken,
+        RevokeToken,
+        Tokens,
+        CreateSubscription,
+        DeleteSubscription,
+        Subscriptions,
+        Deliveries,
+        AgentPoll,
+        AgentLog,
+        AgentProgress,
+        AgentFinish,
+    }
+
+    impl Request {
+        pub fn kind(&self) -> RequestKind {
+            use RequestKind as K;
+            match self {
+                Request::Search(_) => K::Search,
+                Request::CreateProject { .. } => K::CreateProject,
+                Request::Project(_, req) => match req {
+                    Project::Delete => K::ProjectDelete,
+                    Project::Info => K::ProjectInfo,
+                    Project::Refresh => K::ProjectRefresh,
+                    Project::SetDecl(_) => K::ProjectSetDecl,
+                    Project::SetBuildLimits(_) => K::ProjectSetBuildLimits,
+                    Project::SetNotifications(_) => K::ProjectSetNotifications,
+                    Project::SetRole { .. } => K::ProjectSetRole,
+                    Project::UpdateJobsets => K::ProjectUpdateJobsets,
+                },
+                Request::Jobset(_, req) => match req {
+                    Jobset::Evaluate(_) => K::JobsetEvaluate,
+                    Jobset::Info => K::JobsetInfo,
+                },
+                Request::Evaluation(_, req) => match req {
+                    Evaluation::Cancel => K::EvaluationCancel,
+                    Evaluation::Diff(_) => K::EvaluationDiff,
+                    Evaluation::Info => K::EvaluationInfo,
+                },
+                Request::Job(_, req) => match req {
+                    Job::Info => K::JobInfo,
+                    Job::Rerun => K::JobRerun,
+                },
+                Request::Build(_, req) => match req {
+                    Build::Graph => K::BuildGraph,
+                    Build::Info => K::BuildInfo,
+                },
+                Request::Action(_, Action::Info) => K::ActionInfo,
+                Request::Run(_, req) => match req {
+                    Run::Cancel => K::RunCancel,
+                    Run::Info => K::RunInfo,
+                },
+                Request::CreateUser { .. } => K::CreateUser,
+                Request::DeleteUser { .. } => K::DeleteUser,
+                Request::Login { .. } => K::Login,
+                Request::User => K::User,
+                Request::CreateToken { .. } => K::CreateToken,
+                Request::RevokeToken { .. } => K::RevokeToken,
+                Request::Tokens => K::Tokens,
+                Request::CreateSubscription { .. } => K::CreateSubscription,
+                Request::DeleteSubscription { .. } => K::DeleteSubscription,
+                Request::Subscriptions { .. } => K::Subscriptions,
+                Request::Deliveries { .. } => K::Deliveries,
+                Request::Agent { req, .. } => match req {
+                    Agent::Poll { .. } => K::AgentPoll,
+                    Agent::Log { .. } => K::AgentLog,
+                    Agent::Progress { .. } => K::AgentProgress,
+                    Agent::Finish { .. } => K::AgentFinish,
+                },
+            }
+        }
     }
 
     impl std::fmt::Display for Request {
@@ -389,8 +698,55 @@ pub mod requests {
                 Request::Build(h, req) => write!(f, "{:?} for build {}", req, h),
                 Request::Action(h, req) => write!(f, "{:?} for action {}", req, h),
                 Request::Run(h, req) => write!(f, "{:?} for run {}", req, h),
-                Request::Login { .. } => write!(f, "Log in"),
+                Request::CreateUser { name, decl } => {
+                    write!(
+                        f,
+                        "Create{} user {}",
+                        if decl.admin { " admin" } else { "" },
+                        name
+                    )
+                }
+                Request::DeleteUser { name } => write!(f, "Delete user {}", name),
+                Request::Login { username: None, .. } => write!(f, "Log in"),
+                Request::Login {
+                    username: Some(name),
+                    ..
+                } => write!(f, "Log in as {}", name),
                 Request::User => write!(f, "Get current user"),
+                Request::CreateToken { name, .. } => write!(f, "Create token {}", name),
+                Request::RevokeToken { name } => write!(f, "Revoke token {}", name),
+                Request::Tokens => write!(f, "List tokens"),
+                Request::CreateSubscription { name, decl } => {
+                    write!(f, "Create subscription {} to {}", name, decl.url)
+                }
+                Request::DeleteSubscription { name } => write!(f, "Delete subscription {}", name),
+                Request::Subscriptions { project: None } => write!(f, "List global subscriptions"),
+                Request::Subscriptions {
+                    project: Some(project),
+                } => write!(f, "List subscriptions of project {}", project),
+                Request::Deliveries { name } => {
+                    write!(f, "List deliveries of subscription {}", name)
+                }
+                Request::Agent {
+                    name,
+                    req: Agent::Poll { .. },
+                } => write!(f, "Poll for agent {}", name),
+                Request::Agent {
+                    name,
+                    req: Agent::Log { build, .. },
+                } => write!(f, "Log of build {} for agent {}", build, name),
+                Request::Agent {
+                    name,
+                    req: Agent::Progress { build, .. },
+                } => write!(f, "Progress of build {} for agent {}", build, name),
+                Request::Agent {
+                    name,
+                    req: Agent::Finish { build, result },
+                } => write!(
+                    f,
+                    "Finish build {} with {:?} for agent {}",
+                    build, result, name
+                ),
             }
         }
     }
@@ -420,10 +776,16 @@ pub mod responses {
     pub struct ProjectInfo {
         pub handle: handles::Project,
         pub actions_path: Option<String>,
+        pub build_limits: crate::requests::BuildLimitsDecl,
+        /// Whether the project is being deleted
+        pub deleting: bool,
+        /// Why the last deletion of the project failed
+        pub deletion_error: Option<String>,
         pub flake: bool,
         pub jobsets: Vec<String>,
         pub last_refresh: Option<TaskStatus>,
         pub metadata: ProjectMetadata,
+        pub notifications: crate::requests::NotificationsDecl,
         pub public_key: String,
         pub url: String,
         pub url_locked: String,
@@ -434,6 +796,13 @@ pub mod responses {
         pub handle: handles::Jobset,
         pub flake: bool,
         pub url: String,
+        pub schedule: Option<data::Schedule>,
+        #[serde(with = "time::serde::timestamp::option")]
+        pub next_evaluation: Option<OffsetDateTime>,
+        /// Polling interval in seconds
+        pub poll: Option<u64>,
+        /// Priority of the builds of the jobset
+        pub priority: i32,
     }
 
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
@@ -450,6 +819,8 @@ pub mod responses {
         #[serde(with = "crate::helpers::serialize_jobs")]
         pub jobs: HashMap<JobSystemName, JobInfo>,
         pub jobset_name: String,
+        /// The previous evaluation of the same jobset
+        pub previous: Option<handles::Evaluation>,
         pub project: handles::Project,
         pub status: TaskStatus,
         #[serde(with = "time::serde::timestamp")]
@@ -457,22 +828,155 @@ pub mod responses {
         pub url: String,
     }
 
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct StatusTransition {
+        pub job: JobSystemName,
+        pub before: TaskStatusKind,
+        pub after: TaskStatusKind,
+    }
+
+    /// The changes between the jobs of an evaluation and the jobs of a base
+    /// evaluation. Jobs are rebuilt when their derivation changed, and status
+    /// transitions compare the last runs of jobs present in both evaluations.
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct EvaluationDiff {
+        pub base: handles::Evaluation,
+        pub added: Vec<JobSystemName>,
+        pub removed: Vec<JobSystemName>,
+        pub rebuilt: Vec<JobSystemName>,
+        pub unchanged: Vec<JobSystemName>,
+        pub transitions: Vec<StatusTransition>,
+    }
+
+    /// The error of a job that failed to evaluate
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct JobEvalError {
+        pub message: String,
+        /// The time range of the evaluation
+        pub time: TimeRange,
+    }
+
+    /// A job of an evaluation. Jobs that failed to evaluate have an
+    /// `eval_error`, no derivation and no run.
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub struct JobInfo {
         pub handle: handles::Job,
         pub dist: bool,
         pub drv: String,
+        pub eval_error: Option<JobEvalError>,
+        /// The main output of the job: `out` if it exists
         pub out: String,
+        /// All the outputs of the job, by name
+        pub outputs: HashMap<String, String>,
         pub system: String,
-        pub last_run: RunInfo,
+        pub last_run: Option<RunInfo>,
         pub run_count: u32,
     }
 
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub struct BuildInfo {
         pub handle: handles::Build,
+        /// Builds that failed because of a transient error are retried, each
+        /// attempt being a new build
+        pub attempt: u32,
         pub drv: String,
+        /// Number of builds that will start before this one, if it is
+        /// waiting in the build queue
+        pub queue_position: Option<u32>,
         pub status: TaskStatus,
+        /// Progress of the build, while it runs
+        pub progress: Option<BuildProgress>,
+        /// The build that made this one fail, if it was not started because
+        /// one of its dependencies failed
+        pub failed_dependency: Option<handles::Build>,
+    }
+
+    /// Progress of a running build, as reported by Nix
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct BuildProgress {
+        /// Number of derivations built so far
+        pub built: u64,
+        /// Number of derivations to build, including the ones already built
+        pub to_build: u64,
+        /// Number of bytes downloaded from the substituters so far
+        pub downloaded: u64,
+        /// Number of bytes to download, including the ones already downloaded
+        pub to_download: u64,
+        /// Current phase of the build of the derivation itself
+        pub phase: Option<String>,
+    }
+
+    /// Where a line of a log comes from
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum LogStream {
+        /// Output of the build, evaluation or action
+        Output,
+        /// Message from Typhon itself
+        Typhon,
+        /// Start of a phase of a build
+        Phase,
+    }
+
+    /// A line of the log of a task
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct LogRecord {
+        #[serde(with = "time::serde::rfc3339")]
+        pub time: OffsetDateTime,
+        pub stream: LogStream,
+        /// The phase of the build the line belongs to, or that starts for
+        /// [`LogStream::Phase`] records
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub phase: Option<String>,
+        /// Id of the Nix activity that printed the line
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub activity: Option<u64>,
+        pub text: String,
+    }
+
+    impl LogRecord {
+        pub fn new(stream: LogStream, text: impl Into<String>) -> Self {
+            Self {
+                time: OffsetDateTime::now_utc(),
+                stream,
+                phase: None,
+                activity: None,
+                text: text.into(),
+            }
+        }
+
+        /// A line of output, printed now
+        pub fn output(text: impl Into<String>) -> Self {
+            Self::new(LogStream::Output, text)
+        }
+
+        /// A message from Typhon, printed now
+        pub fn typhon(text: impl Into<String>) -> Self {
+            Self::new(LogStream::Typhon, text)
+        }
+
+        /// The start of a phase, now
+        pub fn phase(phase: impl Into<String>) -> Self {
+            let phase = phase.into();
+            Self {
+                phase: Some(phase.clone()),
+                ..Self::new(LogStream::Phase, format!("Running phase: {}", phase))
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct BuildGraphNode {
+        pub build: BuildInfo,
+        pub dependencies: Vec<handles::Build>,
+    }
+
+    /// The builds a build depended on, each build appearing once even if
+    /// several builds depend on it
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct BuildGraph {
+        pub root: handles::Build,
+        pub nodes: Vec<BuildGraphNode>,
     }
 
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -488,11 +992,72 @@ pub mod responses {
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
     pub struct RunInfo {
         pub handle: handles::Run,
+        pub status: TaskStatus,
+        pub previous_status: Option<TaskStatusKind>,
         pub begin: Option<ActionInfo>,
         pub build: Option<BuildInfo>,
         pub end: Option<ActionInfo>,
     }
 
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct TokenInfo {
+        pub name: String,
+        pub project: Option<handles::Project>,
+        pub kinds: Option<Vec<crate::requests::RequestKind>>,
+        #[serde(with = "time::serde::timestamp")]
+        pub time_created: OffsetDateTime,
+        #[serde(with = "time::serde::timestamp::option")]
+        pub expires: Option<OffsetDateTime>,
+        #[serde(with = "time::serde::timestamp::option")]
+        pub last_used: Option<OffsetDateTime>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct SubscriptionInfo {
+        pub name: String,
+        pub url: String,
+        pub project: Option<handles::Project>,
+        #[serde(with = "time::serde::timestamp")]
+        pub time_created: OffsetDateTime,
+    }
+
+    /// A delivery of an event to a subscription. `status` is pending until
+    /// the event is delivered or all attempts have failed.
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct DeliveryInfo {
+        pub event: crate::Event,
+        pub status: TaskStatusKind,
+        pub attempts: u32,
+        /// The HTTP status code of the last attempt
+        pub response_code: Option<u16>,
+        /// The error raised by the last attempt
+        pub error: Option<String>,
+        #[serde(with = "time::serde::timestamp")]
+        pub time_created: OffsetDateTime,
+        #[serde(with = "time::serde::timestamp::option")]
+        pub time_finished: Option<OffsetDateTime>,
+    }
+
+    /// A build assigned to an agent
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AgentBuild {
+        pub build: handles::Build,
+        pub drv: String,
+        /// Maximum duration of the build, in seconds
+        pub timeout: Option<u64>,
+        /// Maximum duration without output, in seconds
+        pub max_silent: Option<u64>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct AgentPoll {
+        /// New builds for the agent
+        pub builds: Vec<AgentBuild>,
+        /// Builds the agent runs that were canceled, or that were given to
+        /// another agent
+        pub cancel: Vec<handles::Build>,
+    }
+
     pub mod search {
         use crate::handles;
         use serde::{Deserialize, Serialize};
@@ -520,11 +1085,18 @@ pub mod responses {
         JobsetEvaluate(crate::handles::Evaluation),
         JobsetInfo(JobsetInfo),
         EvaluationInfo(EvaluationInfo),
+        EvaluationDiff(EvaluationDiff),
         JobInfo(JobInfo),
+        BuildGraph(BuildGraph),
         BuildInfo(BuildInfo),
         ActionInfo(ActionInfo),
         RunInfo(RunInfo),
         User(Option<data::User>),
+        Token(String),
+        Tokens(Vec<TokenInfo>),
+        Subscriptions(Vec<SubscriptionInfo>),
+        Deliveries(Vec<DeliveryInfo>),
+        AgentPoll(AgentPoll),
     }
 
     #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -545,18 +1117,25 @@ pub mod responses {
     }
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Event {
     Ping,
     ProjectNew(handles::Project),
-    //ProjectDeleted(handles::Project),
+    ProjectDeleted(handles::Project),
     ProjectUpdated(handles::Project),
     EvaluationNew(handles::Evaluation),
     EvaluationFinished(handles::Evaluation),
     BuildNew(handles::Build),
     BuildFinished(handles::Build),
+    /// The progress of a running build changed. This event is sent at most
+    /// once per second for each build.
+    BuildProgress(handles::Build),
     RunNew(handles::Run),
     RunUpdated(handles::Run),
+    /// The last run of a job failed while the previous one succeeded
+    JobBroken(handles::Job),
+    /// The last run of a job succeeded while the previous one failed
+    JobFixed(handles::Job),
     ActionNew(handles::Action),
     ActionFinished(handles::Action),
 }
@@ -569,7 +1148,17 @@ impl Event {
             (_, Req::Search(requests::search::Request { kind, .. })) => {
                 use search::Kind as Search;
                 match (kind, self) {
-                    (Search::Projects, Ev::ProjectNew(_) | Ev::ProjectUpdated(_))
+                    (
+                        Search::Projects,
+                        Ev::ProjectNew(_) | Ev::ProjectDeleted(_) | Ev::ProjectUpdated(_),
+                    )
+                    | (
+                        Search::Evaluations(_)
+                        | Search::Runs(_)
+                        | Search::Builds(_)
+                        | Search::Actions(_),
+                        Ev::ProjectDeleted(_),
+                    )
                     | (Search::Evaluations(_), Ev::EvaluationNew(_) | Ev::EvaluationFinished(_))
                     | (Search::Runs(_), Ev::RunUpdated(_) | Ev::RunNew(_))
                     | (Search::Builds(_), Ev::BuildNew(_) | Ev::BuildFinished(_))
@@ -577,10 +1166,22 @@ impl Event {
                     _ => false,
                 }
             }
-            (Ev::ProjectUpdated(h1), Req::Project(h2, Project::Info)) => h1 == h2,
+            (Ev::ProjectDeleted(h1) | Ev::ProjectUpdated(h1), Req::Project(h2, Project::Info)) => {
+                h1 == h2
+            }
+            (Ev::ProjectDeleted(h1), Req::Jobset(h2, _)) => *h1 == h2.project,
             (Ev::ProjectUpdated(h1), Req::Jobset(h2, Jobset::Info)) => *h1 == h2.project,
             (Ev::EvaluationFinished(h1), Req::Evaluation(h2, Evaluation::Info)) => h1 == h2,
-            (Ev::BuildFinished(h1), Req::Build(h2, Build::Info)) => h1 == h2,
+            (Ev::EvaluationFinished(h1), Req::Evaluation(h2, Evaluation::Diff(h3))) => {
+                h1 == h2 || h1 == h3
+            }
+            (Ev::RunUpdated(h1), Req::Evaluation(h2, Evaluation::Diff(h3))) => {
+                h1.job.evaluation == *h2 || h1.job.evaluation == *h3
+            }
+            (Ev::BuildFinished(h1) | Ev::BuildProgress(h1), Req::Build(h2, Build::Info)) => {
+                h1 == h2
+            }
+            (Ev::BuildNew(_) | Ev::BuildFinished(_), Req::Build(_, Build::Graph)) => true,
             (Ev::RunUpdated(h1), Req::Run(h2, Run::Info)) => h1 == h2,
             (Ev::ActionFinished(h1), Req::Action(h2, Action::Info)) => h1 == h2,
             (_, _) => false,
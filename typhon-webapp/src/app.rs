@@ -109,6 +109,9 @@ pub fn App() -> impl IntoView {
         :deep(*[data-status=Error]) {
             --color-task-status: var(--color-task-status-error);
         }
+        :deep(*[data-status=TimedOut]) {
+            --color-task-status: var(--color-task-status-error);
+        }
         :deep(*[data-status=Canceled]) {
             --color-task-status: var(--color-task-status-canceled);
         }
@@ -0,0 +1,90 @@
+use crate::prelude::*;
+
+/// Formats a number of bytes with a binary prefix
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// The progress of a running build, updated as Nix reports it
+#[component]
+pub fn BuildProgress(#[prop(into)] handle: handles::Build) -> impl IntoView {
+    let (error, info) = resource!(
+        Signal::derive(move || requests::Request::Build(handle.clone(), requests::Build::Info)),
+        |responses::Response::BuildInfo(info)| info
+    );
+    let progress = Signal::derive(move || info().and_then(|info| info.progress));
+    let style = style! {
+        div {
+            display: flex;
+            align-items: center;
+            gap: 8px;
+            font-size: 75%;
+            color: #8C959F;
+        }
+        progress {
+            width: 200px;
+        }
+    };
+    view! { class=style,
+        <Trans error>
+            {move || {
+                progress()
+                    .map(|progress| {
+                        let mut details = Vec::new();
+                        if progress.to_build > 0 {
+                            details
+                                .push(
+                                    format!(
+                                        "{}/{} derivations built",
+                                        progress.built,
+                                        progress.to_build,
+                                    ),
+                                );
+                        }
+                        if progress.to_download > 0 {
+                            details
+                                .push(
+                                    format!(
+                                        "{} of {} downloaded",
+                                        format_bytes(progress.downloaded),
+                                        format_bytes(progress.to_download),
+                                    ),
+                                );
+                        }
+                        if let Some(phase) = progress.phase {
+                            details.push(phase);
+                        }
+                        // an indeterminate bar until Nix knows what it has to do
+                        let (value, max) = if progress.to_build > 0 {
+                            (Some(progress.built), Some(progress.to_build))
+                        } else if progress.to_download > 0 {
+                            (Some(progress.downloaded), Some(progress.to_download))
+                        } else {
+                            (None, None)
+                        };
+                        view! {
+                            <div>
+                                <progress
+                                    value=value.map(|value| value.to_string())
+                                    max=max.map(|max| max.to_string())
+                                ></progress>
+                                <span>{details.join(", ")}</span>
+                            </div>
+                        }
+                    })
+            }}
+
+        </Trans>
+    }
+}
@@ -1,6 +1,6 @@
 use crate::prelude::*;
 
-use im::Vector;
+use im::{HashMap, Vector};
 use std::collections::HashSet;
 
 impl PartialEq for State {
@@ -15,28 +15,18 @@ impl Default for State {
             lines: Vector::new(),
             group_stack: Vector::new(),
             group_id: 0,
+            phase: None,
+            durations: HashMap::new(),
         }
     }
 }
 impl State {
-    fn parse_line(self, line: String) -> State {
+    fn parse_record(self, record: responses::LogRecord) -> State {
         let mut state = self;
         struct Command {
             name: String,
             rest: String,
         }
-        fn parse_nix_phase(line: &str) -> Option<Command> {
-            match &line
-                .split(r#"@nix { "action": "setPhase", "phase": ""#)
-                .collect::<Vec<_>>()[..]
-            {
-                ["", phase] if let Some(phase) = phase.split(r#"""#).next() => Some(Command {
-                    name: "nix-phase".into(),
-                    rest: phase.into(),
-                }),
-                _ => None,
-            }
-        }
         fn parse_command_line(line: &str) -> Option<Command> {
             match &line.split("::").collect::<Vec<_>>()[..] {
                 ["", name, rest @ ..] => Some(Command {
@@ -46,32 +36,42 @@ impl State {
                 _ => None,
             }
         }
-        match parse_command_line(line.as_str()).or_else(|| parse_nix_phase(line.as_str())) {
+        if record.stream == responses::LogStream::Phase {
+            state.group_id += 1;
+            state.group_stack = Vector::new();
+            state.group_stack.push_front(state.group_id);
+            state.phase = Some((state.group_id, record.time));
+            state.durations.insert(state.group_id, time::Duration::ZERO);
+            state.lines.push_back(Line {
+                time: record.time,
+                contents: record.phase.unwrap_or(record.text),
+                starts_group: Some(state.group_id),
+                group_stack: Vector::new(),
+            });
+            return state;
+        }
+        // a phase lasts until its last line
+        if let Some((id, start)) = state.phase {
+            state.durations.insert(id, record.time - start);
+        }
+        let line = strip_ansi_escapes::strip_str(record.text);
+        match parse_command_line(line.as_str()) {
             Some(cmd) if cmd.name == "group" => {
                 state.group_id += 1;
                 let group_stack = state.group_stack.clone();
                 state.group_stack.push_front(state.group_id);
                 state.lines.push_back(Line {
+                    time: record.time,
                     contents: cmd.rest,
                     starts_group: Some(state.group_id),
                     group_stack,
                 })
             }
-            Some(cmd) if cmd.name == "nix-phase" => {
-                state.group_id += 1;
-                state.group_stack = Vector::new();
-                state.group_stack.push_front(state.group_id);
-                state.lines.push_back(Line {
-                    contents: cmd.rest,
-                    starts_group: Some(state.group_id),
-                    group_stack: Vector::new(),
-                });
-                return state;
-            }
             Some(cmd) if cmd.name == "endgroup" => {
                 state.group_stack.pop_front();
             }
             _ => state.lines.push_back(Line {
+                time: record.time,
                 contents: line,
                 starts_group: None,
                 group_stack: state.group_stack.clone(),
@@ -84,6 +84,7 @@ impl State {
 type GroupId = usize;
 #[derive(Debug, Clone)]
 struct Line {
+    time: time::OffsetDateTime,
     contents: String,
     starts_group: Option<GroupId>,
     group_stack: Vector<GroupId>,
@@ -93,25 +94,22 @@ struct State {
     lines: Vector<Line>,
     group_stack: Vector<GroupId>,
     group_id: GroupId,
+    /// The group of the current phase and its start
+    phase: Option<(GroupId, time::OffsetDateTime)>,
+    /// Durations of the phases
+    durations: HashMap<GroupId, time::Duration>,
 }
 
 #[component]
-pub fn LiveLog(#[prop(into)] lines: leptos::ReadSignal<Option<String>>) -> impl IntoView {
-    let contents = Signal::derive(move || {
-        lines()
-            .map(|contents| {
-                strip_ansi_escapes::strip_str(contents)
-                    .split("\n")
-                    .map(|s| s.to_owned())
-                    .collect::<Vec<String>>()
-            })
-            .unwrap_or_else(|| vec![])
-    });
+pub fn LiveLog(
+    #[prop(into)] records: leptos::ReadSignal<Option<Vec<responses::LogRecord>>>,
+) -> impl IntoView {
+    let contents = Signal::derive(move || records().unwrap_or_else(|| vec![]));
     view! { <Log contents/> }
 }
 
 #[component]
-pub fn Log(#[prop(into)] contents: Signal<Vec<String>>) -> impl IntoView {
+pub fn Log(#[prop(into)] contents: Signal<Vec<responses::LogRecord>>) -> impl IntoView {
     let styler_class = style! {
         .log :deep(.line.hidden) {
             display: none;
@@ -125,10 +123,19 @@ pub fn Log(#[prop(into)] contents: Signal<Vec<String>>) -> impl IntoView {
             display: inline-block;
             opacity: 0.5;
         }
+        .log :deep(.line span.t) {
+            white-space: nowrap;
+            opacity: 0.5;
+            padding-left: 8px;
+        }
+        .log :deep(.line .duration) {
+            opacity: 0.5;
+            padding-left: 8px;
+        }
         .log :deep(.line) {
             display: grid;
             align-content: baseline;
-            grid-template-columns: "35px" 1fr;
+            grid-template-columns: "35px" "70px" 1fr;
             white-space: normal;
         }
         .log :deep(.line pre) {
@@ -147,8 +154,8 @@ pub fn Log(#[prop(into)] contents: Signal<Vec<String>>) -> impl IntoView {
     let (close, set_close) = create_signal(HashSet::<GroupId>::new());
     let state: Memo<State> = create_memo(move |state: Option<&State>| {
         let mut state = state.cloned().unwrap_or_default();
-        for line in contents.get() {
-            state = state.parse_line(line)
+        for record in contents.get() {
+            state = state.parse_record(record)
         }
         state
     });
@@ -183,6 +190,15 @@ pub fn Log(#[prop(into)] contents: Signal<Vec<String>>) -> impl IntoView {
                         >
 
                             <span class="n">{i + 1}</span>
+                            <span class="t">
+                                {format!(
+                                    "{:02}:{:02}:{:02}",
+                                    line.time.hour(),
+                                    line.time.minute(),
+                                    line.time.second(),
+                                )}
+
+                            </span>
                             <pre style=format!(
                                 "margin-left: {}px;",
                                 (line.group_stack.len() + 1) * 16,
@@ -203,6 +219,21 @@ pub fn Log(#[prop(into)] contents: Signal<Vec<String>>) -> impl IntoView {
                                         })
                                 }}
                                 {line.contents}
+                                {line
+                                    .starts_group
+                                    .filter(|id| {
+                                        state.with_untracked(|state| state.durations.contains_key(id))
+                                    })
+                                    .map(|id| {
+                                        view! {
+                                            <span class="duration">
+                                                <Duration duration=Signal::derive(move || {
+                                                    state.with(|state| state.durations.get(&id).copied())
+                                                })/>
+                                            </span>
+                                        }
+                                    })}
+
                             </pre>
                         </div>
                     }
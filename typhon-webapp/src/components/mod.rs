@@ -1,3 +1,4 @@
+pub mod build_progress;
 pub mod evaluations;
 pub mod header;
 pub mod log;
@@ -8,6 +9,7 @@ pub mod trans;
 pub mod uuid_label;
 
 //pub use header::Header;
+pub use build_progress::BuildProgress;
 pub use evaluations::Evaluations;
 pub use log::LiveLog;
 pub use pagination::Pagination;
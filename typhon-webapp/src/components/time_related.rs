@@ -80,6 +80,7 @@ pub fn TaskStatusDuration(#[prop(into)] status: Signal<TaskStatus>) -> impl Into
         <Duration duration=Signal::derive(move || match status() {
             TaskStatus::Success(range)
             | TaskStatus::Failure(range)
+            | TaskStatus::TimedOut(range)
             | TaskStatus::Canceled(Some(range)) => Some(range.into()),
             TaskStatus::Pending { start: Some(start) } => {
                 let now = use_context::<crate::utils::CurrentTime>().unwrap().0;
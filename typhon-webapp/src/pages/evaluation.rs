@@ -6,7 +6,7 @@ use typhon_types::responses::TaskStatus;
 
 use std::collections::HashMap;
 
-fn fetch_log(log: handles::Log) -> ReadSignal<Option<String>> {
+fn fetch_log(log: handles::Log) -> ReadSignal<Option<Vec<responses::LogRecord>>> {
     #[cfg(feature = "ssr")]
     {
         let _ = log;
@@ -15,7 +15,9 @@ fn fetch_log(log: handles::Log) -> ReadSignal<Option<String>> {
     #[cfg(feature = "hydrate")]
     {
         use gloo_net::http::Request;
-        crate::streams::fetch_as_signal(Request::post("/api/log").json(&log).unwrap())
+        crate::streams::fetch_records_as_signal(
+            Request::post("/api/log?format=ndjson").json(&log).unwrap(),
+        )
     }
 }
 
@@ -151,6 +153,10 @@ pub fn JobSubpage(
         .active {
             padding-top: 10px;
         }
+        .eval-error {
+            margin: 0;
+            white-space: pre-wrap;
+        }
     };
     let href = {
         let eval_handle = job.handle.evaluation.clone();
@@ -166,28 +172,54 @@ pub fn JobSubpage(
         }
     };
 
-    let logs: Vec<_> = {
-        let run = job.last_run.clone();
-        use handles::Log::*;
-        vec![
-            run.begin
-                .map(|x| (Action(x.handle), x.status, "Begin", LogTab::Begin)),
-            run.build
-                .map(|x| (Build(x.handle), x.status, "Build", LogTab::Build)),
-            run.end
-                .map(|x| (Action(x.handle), x.status, "End", LogTab::End)),
-        ]
-        .into_iter()
-        .flatten()
-        .collect()
-    };
+    let logs: Vec<_> = job
+        .last_run
+        .clone()
+        .map(|run| {
+            use handles::Log::*;
+            vec![
+                run.begin
+                    .map(|x| (Action(x.handle), x.status, "Begin", LogTab::Begin)),
+                run.build
+                    .map(|x| (Build(x.handle), x.status, "Build", LogTab::Build)),
+                run.end
+                    .map(|x| (Action(x.handle), x.status, "End", LogTab::End)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        })
+        .unwrap_or_default();
 
     let active_log = logs
         .iter()
         .find(|(.., tab)| tab == &log_tab)
         .map(|(handle, ..)| handle);
 
-    let run = job.last_run.clone();
+    let status = TaskStatus::from(&job);
+    let queue_position = job
+        .last_run
+        .as_ref()
+        .and_then(|run| run.build.as_ref())
+        .and_then(|build| build.queue_position);
+    let attempt = job
+        .last_run
+        .as_ref()
+        .and_then(|run| run.build.as_ref())
+        .map(|build| build.attempt)
+        .filter(|attempt| *attempt > 1);
+    let running_build = job
+        .last_run
+        .as_ref()
+        .and_then(|run| run.build.as_ref())
+        .filter(|build| matches!(build.status, TaskStatus::Pending { start: Some(_) }))
+        .map(|build| build.handle.clone());
+    let failed_dependency = job
+        .last_run
+        .as_ref()
+        .and_then(|run| run.build.as_ref())
+        .and_then(|build| build.failed_dependency.clone());
+    let eval_error = job.eval_error.clone().map(|error| error.message);
     view! { class=style,
         <div class="header">
             <div class="name">
@@ -198,7 +230,6 @@ pub fn JobSubpage(
                 <h2>
 
                     {
-                        let status = TaskStatus::from(run.clone());
                         let status_signal = create_signal(status.clone()).0;
                         let (_, end) = status.times();
                         let make = move |label: &'static str| {
@@ -216,6 +247,10 @@ pub fn JobSubpage(
                             }
                         };
                         match &status {
+                            TaskStatus::Pending { .. } if queue_position.is_some() => {
+                                let position = queue_position.unwrap();
+                                view! { <>{format!("queued, {} builds ahead", position)}</> }
+                            }
                             TaskStatus::Pending { start: None } => view! { <>pending</> },
                             TaskStatus::Pending { start: Some(_) } => {
                                 view! {
@@ -223,13 +258,22 @@ pub fn JobSubpage(
                                 }
                             }
                             TaskStatus::Success(..) => make("succeeded"),
+                            TaskStatus::Failure(..) if eval_error.is_some() => {
+                                view! { <>failed to evaluate</> }
+                            }
+                            TaskStatus::Failure(..) if failed_dependency.is_some() => {
+                                make("dependency failed")
+                            }
                             TaskStatus::Failure(..) => make("failed"),
+                            TaskStatus::TimedOut(..) => make("timed out"),
                             TaskStatus::Canceled(Some(..)) => make("canceled"),
                             TaskStatus::Canceled(None) => view! { <>canceled</> },
                         }
                     }
+                    {attempt.map(|attempt| format!(" (attempt {})", attempt))}
 
                 </h2>
+                {running_build.map(|handle| view! { <BuildProgress handle/> })}
             </div>
             <Icon icon=icondata::BiRefreshRegular/>
             <Icon icon=icondata::BiCogRegular/>
@@ -253,7 +297,8 @@ pub fn JobSubpage(
                     .collect::<Vec<_>>()}
             </div>
             <div class="active">
-                {active_log.map(|handle| view! { <LiveLog lines=fetch_log(handle.clone())/> })}
+                {active_log.map(|handle| view! { <LiveLog records=fetch_log(handle.clone())/> })}
+                {eval_error.clone().map(|message| view! { <pre class="eval-error">{message}</pre> })}
             </div>
         </div>
     }
@@ -277,6 +322,86 @@ fn collect_jobs(
     res
 }
 
+#[component]
+fn Changes(handle: handles::Evaluation, base: handles::Evaluation) -> impl IntoView {
+    let (error, diff) = resource!(
+        Signal::derive(move || Request::Evaluation(
+            handle.clone(),
+            requests::Evaluation::Diff(base.clone())
+        )),
+        |Response::EvaluationDiff(diff)| diff
+    );
+    let job_list = |label: &'static str, jobs: Vec<responses::JobSystemName>| {
+        (!jobs.is_empty()).then(|| {
+            view! {
+                <div class="field">
+                    <span class="label">{label}</span>
+                    <ul class="value">
+                        {jobs
+                            .into_iter()
+                            .map(|job| view! { <li>{format!("{} ({})", job.name, job.system)}</li> })
+                            .collect::<Vec<_>>()}
+                    </ul>
+                </div>
+            }
+        })
+    };
+    view! {
+        <Trans error>
+            {move || {
+                diff()
+                    .map(|diff| {
+                        let base = crate::routes::to_url(crate::routes::EvaluationPage {
+                            handle: diff.base.clone(),
+                            tab: EvaluationTab::Info,
+                        });
+                        let transitions = diff
+                            .transitions
+                            .into_iter()
+                            .map(|transition| {
+                                view! {
+                                    <li>
+                                        {format!(
+                                            "{} ({}): {} → {}",
+                                            transition.job.name,
+                                            transition.job.system,
+                                            transition.before,
+                                            transition.after,
+                                        )}
+                                    </li>
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        view! {
+                            <div class="field">
+                                <span class="label">Compared with</span>
+                                <div class="value">
+                                    <A href=base>
+                                        <UuidLabel uuid=diff.base.uuid/>
+                                    </A>
+                                    {format!(" ({} unchanged jobs)", diff.unchanged.len())}
+                                </div>
+                            </div>
+                            {job_list("Added jobs", diff.added)}
+                            {job_list("Removed jobs", diff.removed)}
+                            {job_list("Rebuilt jobs", diff.rebuilt)}
+                            {(!transitions.is_empty())
+                                .then(|| {
+                                    view! {
+                                        <div class="field">
+                                            <span class="label">Status changes</span>
+                                            <ul class="value">{transitions}</ul>
+                                        </div>
+                                    }
+                                })}
+                        }
+                    })
+            }}
+
+        </Trans>
+    }
+}
+
 #[component]
 fn Info(info: responses::EvaluationInfo) -> impl IntoView {
     let style = style! {
@@ -333,6 +458,12 @@ fn Info(info: responses::EvaluationInfo) -> impl IntoView {
     };
     let map = crate::components::evaluations::EvalStatus::new(&info).map;
     let status_kind = TaskStatusKind::from(info.status);
+    let mut eval_errors: Vec<_> = info
+        .jobs
+        .iter()
+        .filter_map(|(job, info)| Some((job.clone(), info.eval_error.clone()?.message)))
+        .collect();
+    eval_errors.sort_by_key(|(job, _)| (job.system.clone(), job.name.clone()));
     view! { class=style,
         <div class="blocks">
             <div class="block">
@@ -388,7 +519,37 @@ fn Info(info: responses::EvaluationInfo) -> impl IntoView {
 
                     </div>
                 </div>
+                {(!eval_errors.is_empty())
+                    .then(|| {
+                        view! {
+                            <div class="field">
+                                <span class="label">Jobs that failed to evaluate</span>
+                                <ul class="value">
+                                    {eval_errors
+                                        .into_iter()
+                                        .map(|(job, message)| {
+                                            view! {
+                                                <li>
+                                                    {format!("{} ({})", job.name, job.system)}
+                                                    <pre>{message}</pre>
+                                                </li>
+                                            }
+                                        })
+                                        .collect::<Vec<_>>()}
+                                </ul>
+                            </div>
+                        }
+                    })}
             </div>
+            {info
+                .previous
+                .map(|base| {
+                    view! {
+                        <div class="block">
+                            <Changes handle=info.handle.clone() base/>
+                        </div>
+                    }
+                })}
         </div>
     }
 }
@@ -469,16 +630,14 @@ fn Main(
                         {jobs
                             .into_iter()
                             .map(|(name, info)| {
-                                let last_run = info.last_run.clone();
+                                let status = TaskStatus::from(info);
                                 mk_item(
                                     EvaluationTab::Job {
                                         handle: info.handle.clone(),
                                         log_tab: LogTab::default(),
                                     },
                                     view! {
-                                        <Status status=move || {
-                                            TaskStatus::from(last_run.clone()).into()
-                                        }/>
+                                        <Status status=move || status.into()/>
                                     },
                                     view! { <span>{name}</span> }.into_view(),
                                 )
@@ -565,7 +724,7 @@ fn Main(
     let global_status: Signal<TaskStatus> = Signal::derive(move || {
         info.jobs
             .iter()
-            .map(|(_, info)| TaskStatus::from(info.last_run.clone()))
+            .map(|(_, info)| TaskStatus::from(info))
             .reduce(|a, b| a.union(&b))
             .unwrap_or_default()
     });
@@ -85,6 +85,57 @@ pub fn Jobset(
                                         <td>"Flake"</td>
                                         <td>{info.flake}</td>
                                     </tr>
+                                    {info
+                                        .schedule
+                                        .map(|schedule| {
+                                            let schedule = match schedule {
+                                                data::Schedule::Interval(seconds) => {
+                                                    format!("every {seconds} seconds")
+                                                }
+                                                data::Schedule::Cron(expr) => expr,
+                                            };
+                                            view! {
+                                                <tr>
+                                                    <td>"Schedule"</td>
+                                                    <td>{schedule}</td>
+                                                </tr>
+                                            }
+                                        })}
+                                    {info
+                                        .poll
+                                        .map(|seconds| {
+                                            view! {
+                                                <tr>
+                                                    <td>"Polling"</td>
+                                                    <td>{format!("every {seconds} seconds")}</td>
+                                                </tr>
+                                            }
+                                        })}
+                                    {(info.priority != 0)
+                                        .then(|| {
+                                            view! {
+                                                <tr>
+                                                    <td>"Priority"</td>
+                                                    <td>{info.priority}</td>
+                                                </tr>
+                                            }
+                                        })}
+                                    {info
+                                        .next_evaluation
+                                        .map(|datetime| {
+                                            let datetime = format!(
+                                                "{} {:02}:{:02} UTC",
+                                                datetime.date(),
+                                                datetime.hour(),
+                                                datetime.minute(),
+                                            );
+                                            view! {
+                                                <tr>
+                                                    <td>"Next evaluation"</td>
+                                                    <td>{datetime}</td>
+                                                </tr>
+                                            }
+                                        })}
                                 </table>
                             }
                         })
@@ -7,19 +7,20 @@ mod server_fn {
     use leptos::*;
 
     #[server(Login, "/leptos", "Url", "login")]
-    pub async fn login(password: String) -> Result<(), ServerFnError> {
+    pub async fn login(username: String, password: String) -> Result<(), ServerFnError> {
         use crate::prelude::*;
         use actix_session::Session;
         use leptos_actix::extract;
-        use typhon_types::data::User;
+        use typhon_core::User;
+        let username = Some(username).filter(|username| !username.is_empty());
         let res = handle_request!(
-            requests::Request::Login { password },
-            |responses::Response::Ok| ()
+            requests::Request::Login { username, password },
+            |responses::Response::User(user)| user
         );
         match res {
-            Ok(Ok(())) => {
+            Ok(Ok(Some(user))) => {
                 let session: Session = extract().await?;
-                session.insert("user", User::Admin).map_err(|_| {
+                session.insert("user", User::from(user)).map_err(|_| {
                     ServerFnError::<server_fn::error::NoCustomError>::ServerError(
                         "TODO".to_string(),
                     )
@@ -28,7 +29,7 @@ mod server_fn {
                 //redirect("/");
                 Ok(())
             }
-            Ok(Err(_)) => Err(ServerFnError::ServerError("TODO".to_string())),
+            Ok(_) => Err(ServerFnError::ServerError("TODO".to_string())),
             Err(e) => Err(e),
         }
     }
@@ -75,6 +76,14 @@ pub fn Login() -> impl IntoView {
             <Show when=move || user().is_none() fallback=|| view! { "You are logged in!" }>
                 <ActionForm action>
                     <h2>"Log In"</h2>
+                    <div>
+                        <label for="username">"Username"</label>
+                        <input
+                            type="text"
+                            placeholder="Leave empty to log in as administrator"
+                            name="username"
+                        />
+                    </div>
                     <div>
                         <label for="password">"Password"</label>
                         <input type="password" placeholder="Password" name="password"/>
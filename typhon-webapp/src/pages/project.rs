@@ -62,6 +62,15 @@ pub(crate) fn Project(handle: handles::Project) -> impl IntoView {
         handles::Project { name },
         requests::Project::Refresh,
     ));
+    let delete = request_action!(DeleteProject, |name: String| requests::Request::Project(
+        handles::Project { name },
+        requests::Project::Delete,
+    ));
+    create_effect(move |_| {
+        if let Some(Ok(Ok(()))) = delete.value()() {
+            leptos_router::use_navigate()(&String::from(Root::Projects), Default::default());
+        }
+    });
     let handle_name = {
         let handle_name = handle.name.clone();
         Signal::derive(move || handle_name.clone())
@@ -91,6 +100,63 @@ pub(crate) fn Project(handle: handles::Project) -> impl IntoView {
                                         <td>"Public key"</td>
                                         <td>{info.public_key}</td>
                                     </tr>
+                                    <tr>
+                                        <td>"Notifications"</td>
+                                        <td>
+                                            {
+                                                let mut recipients = info.notifications.emails.clone();
+                                                if info.notifications.maintainers {
+                                                    recipients.push("job maintainers".to_string());
+                                                }
+                                                if recipients.is_empty() {
+                                                    "none".to_string()
+                                                } else {
+                                                    recipients.join(", ")
+                                                }
+                                            }
+
+                                        </td>
+                                    </tr>
+                                    <tr>
+                                        <td>"Build limits"</td>
+                                        <td>
+                                            {
+                                                let mut limits = Vec::new();
+                                                if let Some(timeout) = info.build_limits.timeout {
+                                                    limits.push(format!("timeout of {timeout} seconds"));
+                                                }
+                                                if let Some(max_silent) = info.build_limits.max_silent {
+                                                    limits
+                                                        .push(
+                                                            format!("{max_silent} seconds without output"),
+                                                        );
+                                                }
+                                                if limits.is_empty() {
+                                                    "none".to_string()
+                                                } else {
+                                                    limits.join(", ")
+                                                }
+                                            }
+
+                                        </td>
+                                    </tr>
+                                    {
+                                        let deletion = if info.deleting {
+                                            Some("in progress".to_string())
+                                        } else {
+                                            info.deletion_error.map(|e| format!("failed: {e}"))
+                                        };
+                                        deletion
+                                            .map(|deletion| {
+                                                view! {
+                                                    <tr>
+                                                        <td>"Deletion"</td>
+                                                        <td>{deletion}</td>
+                                                    </tr>
+                                                }
+                                            })
+                                    }
+
                                 </table>
                             }
                         })
@@ -109,6 +175,10 @@ pub(crate) fn Project(handle: handles::Project) -> impl IntoView {
                             <input type="submit" value="Refresh"/>
 
                         </ActionForm>
+                        <ActionForm action=delete>
+                            <input type="hidden" name="name" value=handle_name/>
+                            <input type="submit" value="Delete"/>
+                        </ActionForm>
                     </Show>
                     {move || {
                         info()
@@ -14,7 +14,8 @@ use gloo_net::http;
 use wasm_bindgen::JsCast;
 use wasm_streams::readable::*;
 
-pub fn fetch_as_stream(req: http::Request) -> impl Stream<Item = String> {
+/// Fetches the body of a response as decoded chunks of text
+fn fetch_chunks(req: http::Request) -> impl Stream<Item = String> {
     async move {
         let res = req
             .send()
@@ -30,23 +31,51 @@ pub fn fetch_as_stream(req: http::Request) -> impl Stream<Item = String> {
             .filter_map(|item| core::future::ready(item.ok()))
             .map(|item| {
                 let text_decoder = web_sys::TextDecoder::new().unwrap();
-                let buffer = text_decoder
+                text_decoder
                     .decode_with_buffer_source(&item.into())
-                    .unwrap();
-                buffer
-                    .strip_suffix("\n")
-                    .map(|s| s.to_owned())
-                    .unwrap_or(buffer)
+                    .unwrap()
             })
     }
     .into_stream()
     .flatten()
 }
 
+pub fn fetch_as_stream(req: http::Request) -> impl Stream<Item = String> {
+    fetch_chunks(req).map(|buffer| {
+        buffer
+            .strip_suffix("\n")
+            .map(|s| s.to_owned())
+            .unwrap_or(buffer)
+    })
+}
+
 pub fn fetch_as_signal(req: http::Request) -> leptos::ReadSignal<Option<String>> {
     leptos::create_signal_from_stream(Box::pin(fetch_as_stream(req)))
 }
 
+/// Fetches a log as NDJSON records. Records split across chunks are put
+/// back together.
+pub fn fetch_records_as_signal(
+    req: http::Request,
+) -> leptos::ReadSignal<Option<Vec<responses::LogRecord>>> {
+    let s = stream! {
+        let mut buffer = String::new();
+        for await chunk in fetch_chunks(req) {
+            buffer.push_str(&chunk);
+            let mut records = Vec::new();
+            while let Some(i) = buffer.find('\n') {
+                let line: String = buffer.drain(..=i).collect();
+                match serde_json::from_str(&line) {
+                    Ok(record) => records.push(record),
+                    Err(e) => log!(format!("failed to parse log record: {:?}", e)),
+                }
+            }
+            yield records;
+        }
+    };
+    leptos::create_signal_from_stream(Box::pin(s))
+}
+
 pub fn events_stream() -> impl Stream<Item = Event> + Unpin + 'static {
     let req = http::RequestBuilder::new("/api/events").method(http::Method::GET);
     let req = req.build().unwrap();
@@ -2,9 +2,10 @@ use typhon_core::error;
 use typhon_core::handle_request;
 use typhon_core::User;
 use typhon_core::EVENT_LOGGER;
+use typhon_types::data::Role;
 use typhon_types::handles;
 use typhon_types::requests::*;
-use typhon_types::responses::{Response, ResponseError};
+use typhon_types::responses::{LogRecord, Response, ResponseError};
 
 use actix_files::NamedFile;
 use actix_session::Session;
@@ -51,11 +52,18 @@ impl Responder for ResponseWrapper {
             JobsetInfo(payload) => web::Json(payload).respond_to(req),
             JobsetEvaluate(payload) => web::Json(payload).respond_to(req),
             EvaluationInfo(payload) => web::Json(payload).respond_to(req),
+            EvaluationDiff(payload) => web::Json(payload).respond_to(req),
             JobInfo(payload) => web::Json(payload).respond_to(req),
+            BuildGraph(payload) => web::Json(payload).respond_to(req),
             BuildInfo(payload) => web::Json(payload).respond_to(req),
             ActionInfo(payload) => web::Json(payload).respond_to(req),
             RunInfo(payload) => web::Json(payload).respond_to(req),
             User(payload) => web::Json(payload).respond_to(req),
+            Token(payload) => web::Json(payload).respond_to(req),
+            Tokens(payload) => web::Json(payload).respond_to(req),
+            Subscriptions(payload) => web::Json(payload).respond_to(req),
+            Deliveries(payload) => web::Json(payload).respond_to(req),
+            AgentPoll(payload) => web::Json(payload).respond_to(req),
         }
     }
 }
@@ -67,17 +75,28 @@ impl FromRequest for UserWrapper {
     type Future = Pin<Box<dyn Future<Output = Result<UserWrapper, actix_web::Error>>>>;
 
     fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
-        let maybe_user = req
+        let token = req
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let password = req
             .headers()
             .get("password")
-            .map(|value| value.as_bytes())
-            .as_ref()
-            .map(|password| User::from_password(password));
+            .map(|value| value.as_bytes().to_vec());
         let session = Session::from_request(req, pl);
         Box::pin(async move {
-            match maybe_user {
-                Some(user) => Ok(UserWrapper(user)),
-                None => {
+            // authentication queries the database or hashes the password, so
+            // it runs on the blocking thread pool
+            match (token, password) {
+                (Some(token), _) => Ok(UserWrapper(
+                    web::block(move || User::from_token(&token)).await?,
+                )),
+                (None, Some(password)) => Ok(UserWrapper(
+                    web::block(move || User::from_password(&password)).await?,
+                )),
+                (None, None) => {
                     let user = session
                         .await?
                         .get::<User>("user")?
@@ -121,11 +140,11 @@ r!(
         Request::CreateProject { name, decl }
     };
 
-    //project_delete(path: web::Path<String>) =>
-    //    Request::Project(
-    //        handles::project(path.into_inner()),
-    //        Project::Delete,
-    //    );
+    project_delete(path: web::Path<String>) =>
+        Request::Project(
+            handles::project(path.into_inner()),
+            Project::Delete,
+        );
 
     project_info(path: web::Path<String>) =>
         Request::Project(
@@ -145,6 +164,18 @@ r!(
             Project::SetDecl(body.into_inner()),
         );
 
+    project_set_build_limits(path: web::Path<String>, body: web::Json<BuildLimitsDecl>) =>
+        Request::Project(
+            handles::project(path.into_inner()),
+            Project::SetBuildLimits(body.into_inner()),
+        );
+
+    project_set_notifications(path: web::Path<String>, body: web::Json<NotificationsDecl>) =>
+        Request::Project(
+            handles::project(path.into_inner()),
+            Project::SetNotifications(body.into_inner()),
+        );
+
     project_update_jobsets(path: web::Path<String>) =>
         Request::Project(
             handles::project(path.into_inner()),
@@ -175,17 +206,25 @@ r!(
             Evaluation::Info,
         );
 
+    evaluation_diff(path: web::Path<(Uuid,Uuid)>) => {
+        let (evaluation, base) = path.into_inner();
+        Request::Evaluation(
+            handles::evaluation(evaluation),
+            Evaluation::Diff(handles::evaluation(base)),
+        )
+    };
+
     job_info(path: web::Path<(Uuid,String,String)>) =>
         Request::Job(
             handles::job(path.into_inner()),
             Job::Info,
         );
 
-    //run_cancel(path: web::Path<(Uuid,String,String,u32)>) =>
-    //    Request::Run(
-    //        handles::run(path.into_inner()),
-    //        Run::Cancel,
-    //    );
+    run_cancel(path: web::Path<(Uuid,String,String,u32)>) =>
+        Request::Run(
+            handles::run(path.into_inner()),
+            Run::Cancel,
+        );
 
     run_info(path: web::Path<(Uuid,String,String,u32)>) =>
         Request::Run(
@@ -205,6 +244,12 @@ r!(
             Build::Info,
         );
 
+    build_graph(path: web::Path<Uuid>) =>
+        Request::Build(
+            handles::build(path.into_inner()),
+            Build::Graph,
+        );
+
     action_info(path: web::Path<Uuid>) =>
         Request::Action(
             handles::action(path.into_inner()),
@@ -212,12 +257,63 @@ r!(
         );
 
     login(body: web::Json<String>) =>
-        Request::Login { password: body.into_inner() };
+        Request::Login { username: None, password: body.into_inner() };
+
+    user_login(path: web::Path<String>, body: web::Json<String>) =>
+        Request::Login { username: Some(path.into_inner()), password: body.into_inner() };
+
+    user_create(path: web::Path<String>, body: web::Json<UserDecl>) => {
+        let name = path.into_inner();
+        let decl = body.into_inner();
+        Request::CreateUser { name, decl }
+    };
+
+    user_delete(path: web::Path<String>) =>
+        Request::DeleteUser { name: path.into_inner() };
+
+    tokens() => Request::Tokens;
+
+    token_create(path: web::Path<String>, body: web::Json<TokenDecl>) => {
+        let name = path.into_inner();
+        let decl = body.into_inner();
+        Request::CreateToken { name, decl }
+    };
+
+    token_revoke(path: web::Path<String>) =>
+        Request::RevokeToken { name: path.into_inner() };
+
+    subscriptions() => Request::Subscriptions { project: None };
+
+    project_subscriptions(path: web::Path<String>) =>
+        Request::Subscriptions { project: Some(handles::project(path.into_inner())) };
+
+    subscription_create(path: web::Path<String>, body: web::Json<SubscriptionDecl>) => {
+        let name = path.into_inner();
+        let decl = body.into_inner();
+        Request::CreateSubscription { name, decl }
+    };
+
+    subscription_delete(path: web::Path<String>) =>
+        Request::DeleteSubscription { name: path.into_inner() };
+
+    subscription_deliveries(path: web::Path<String>) =>
+        Request::Deliveries { name: path.into_inner() };
+
+    project_set_role(path: web::Path<(String, String)>, body: web::Json<Option<Role>>) => {
+        let (project, user) = path.into_inner();
+        Request::Project(
+            handles::project(project),
+            Project::SetRole { user, role: body.into_inner() },
+        )
+    };
 );
 
+/// Serves a file from an output of a job, selected with the `output` query
+/// parameter (the main output by default)
 async fn dist(
     user: UserWrapper,
     path: web::Path<(Uuid, String, String, String)>,
+    query: web::Query<HashMap<String, String>>,
 ) -> Result<impl Responder, ResponseErrorWrapper> {
     let (evaluation, system, job, path) = path.into_inner();
     let handle = handles::job((evaluation, system, job));
@@ -230,7 +326,13 @@ async fn dist(
         _ => Err(ResponseErrorWrapper(ResponseError::InternalError)),
     }?;
     if info.dist {
-        Ok(NamedFile::open_async(format!("{}/{}", info.out, path)).await)
+        let out = match query.get("output") {
+            Some(output) => info.outputs.get(output).ok_or_else(|| {
+                ResponseErrorWrapper(ResponseError::BadRequest(format!("no output {}", output)))
+            })?,
+            None => &info.out,
+        };
+        Ok(NamedFile::open_async(format!("{}/{}", out, path)).await)
     } else {
         Err(ResponseErrorWrapper(ResponseError::BadRequest(
             "typhonDist is not set".into(),
@@ -238,36 +340,65 @@ async fn dist(
     }
 }
 
-fn streaming_response(
-    stream: impl futures_core::stream::Stream<Item = String> + 'static,
+/// Streams a log as plain text, or as NDJSON records with `format=ndjson`
+fn log_response(
+    stream: impl futures_core::stream::Stream<Item = LogRecord> + 'static,
+    ndjson: bool,
 ) -> HttpResponse {
     use futures::stream::StreamExt;
-    let stream = stream.map(|x: String| {
-        Ok::<_, actix_web::Error>(actix_web::web::Bytes::from(format!("{}\n", x)))
+    let stream = stream.map(move |record: LogRecord| {
+        let line = if ndjson {
+            serde_json::to_string(&record).unwrap()
+        } else {
+            record.text
+        };
+        Ok::<_, actix_web::Error>(actix_web::web::Bytes::from(format!("{}\n", line)))
     });
-    HttpResponse::Ok().streaming(stream)
+    let content_type = if ndjson {
+        "application/x-ndjson"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .streaming(stream)
 }
 
 mod log_routes {
     type Response = Result<Option<HttpResponse>, ResponseErrorWrapper>;
+    type Query = web::Query<HashMap<String, String>>;
     use super::*;
     use handles::Log;
 
-    async fn serve(log: Log) -> Response {
+    async fn serve(log: Log, query: Query) -> Response {
+        let ndjson = match query.get("format").map(String::as_str) {
+            None | Some("plain") => false,
+            Some("ndjson") => true,
+            Some(format) => {
+                return Err(ResponseErrorWrapper(ResponseError::BadRequest(format!(
+                    "unknown log format {}",
+                    format
+                ))))
+            }
+        };
         let maybe_stream = web::block(move || typhon_core::log(log)).await??;
-        Ok(maybe_stream.map(streaming_response))
+        Ok(maybe_stream.map(|stream| log_response(stream, ndjson)))
     }
-    pub async fn evaluation(path: web::Path<Uuid>) -> Response {
-        serve(Log::Evaluation(handles::evaluation(path.into_inner()))).await
+    pub async fn evaluation(path: web::Path<Uuid>, query: Query) -> Response {
+        serve(
+            Log::Evaluation(handles::evaluation(path.into_inner())),
+            query,
+        )
+        .await
     }
-    pub async fn build(path: web::Path<Uuid>) -> Response {
-        serve(Log::Build(handles::build(path.into_inner()))).await
+    pub async fn build(path: web::Path<Uuid>, query: Query) -> Response {
+        serve(Log::Build(handles::build(path.into_inner())), query).await
     }
-    pub async fn action(path: web::Path<Uuid>) -> Response {
-        serve(Log::Action(handles::action(path.into_inner()))).await
+    pub async fn action(path: web::Path<Uuid>, query: Query) -> Response {
+        serve(Log::Action(handles::action(path.into_inner())), query).await
     }
-    pub async fn generic(path: web::Json<Log>) -> Response {
-        serve(path.into_inner()).await
+    pub async fn generic(path: web::Json<Log>, query: Query) -> Response {
+        serve(path.into_inner(), query).await
     }
 }
 
@@ -337,16 +468,27 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(
                 web::scope("/builds/{build}")
                     .route("", web::get().to(build_info))
+                    .route("/graph", web::get().to(build_graph))
                     .route("/log", web::get().to(log_routes::build)),
             )
             .service(
                 web::scope("/projects/{project}")
                     .route("", web::get().to(project_info))
                     .route("/create", web::post().to(create_project))
-                    //.route("/delete", web::post().to(project_delete))
+                    .route("/delete", web::post().to(project_delete))
                     .route("/refresh", web::post().to(project_refresh))
                     .route("/update_jobsets", web::post().to(project_update_jobsets))
                     .route("/set_decl", web::post().to(project_set_decl))
+                    .route(
+                        "/set_build_limits",
+                        web::post().to(project_set_build_limits),
+                    )
+                    .route(
+                        "/set_notifications",
+                        web::post().to(project_set_notifications),
+                    )
+                    .route("/roles/{user}", web::post().to(project_set_role))
+                    .route("/subscriptions", web::get().to(project_subscriptions))
                     .route("/webhook", web::post().to(webhook))
                     .service(
                         web::scope("/jobsets/{jobset}")
@@ -358,6 +500,7 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                 web::scope("/evaluations/{evaluation}")
                     .route("", web::get().to(evaluation_info))
                     .route("/cancel", web::post().to(evaluation_cancel))
+                    .route("/diff/{base}", web::get().to(evaluation_diff))
                     .route("/log", web::get().to(log_routes::evaluation))
                     .service(
                         web::scope("/jobs/{system}/{job}")
@@ -366,7 +509,7 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                             .route("/dist/{path:.*}", web::get().to(dist))
                             .service(
                                 web::scope("/runs/{run}")
-                                    //.route("/cancel", web::post().to(run_cancel))
+                                    .route("/cancel", web::post().to(run_cancel))
                                     .route("", web::get().to(run_info)),
                             ),
                     ),
@@ -377,6 +520,25 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                     .route("/log", web::get().to(log_routes::action)),
             )
             .route("/login", web::post().to(login))
+            .service(
+                web::scope("/users/{user}")
+                    .route("/create", web::post().to(user_create))
+                    .route("/delete", web::post().to(user_delete))
+                    .route("/login", web::post().to(user_login)),
+            )
+            .route("/tokens", web::get().to(tokens))
+            .service(
+                web::scope("/tokens/{token}")
+                    .route("/create", web::post().to(token_create))
+                    .route("/revoke", web::post().to(token_revoke)),
+            )
+            .route("/subscriptions", web::get().to(subscriptions))
+            .service(
+                web::scope("/subscriptions/{subscription}")
+                    .route("/create", web::post().to(subscription_create))
+                    .route("/delete", web::post().to(subscription_delete))
+                    .route("/deliveries", web::get().to(subscription_deliveries)),
+            )
             .route(
                 "{anything:.*}",
                 web::route()